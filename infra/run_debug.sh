@@ -1,4 +1,4 @@
 clear
 echo
 cargo build
-sudo ./target/debug/infra
+sudo ./target/debug/infra --config cluster.toml --inventory inventory.toml
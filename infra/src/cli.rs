@@ -0,0 +1,174 @@
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use std::{net::SocketAddr, path::PathBuf};
+
+#[derive(Debug, Parser)]
+#[command(name = "infra", about = "8inary node installer.")]
+pub struct Cli {
+	/// Cluster spec, defaults to /etc/8inary/cluster.toml when present.
+	#[arg(long, global = true, value_name = "PATH")]
+	pub config: Option<PathBuf>,
+	/// Machine inventory, defaults to /etc/8inary/inventory.toml when present.
+	#[arg(long, global = true, value_name = "PATH")]
+	pub inventory: Option<PathBuf>,
+	/// Print the run report as JSON on stdout instead of the human summary.
+	#[arg(long, global = true)]
+	pub json: bool,
+	/// Replay canned command results from a TOML script instead of running anything.
+	#[arg(long, global = true, value_name = "PATH", hide = true)]
+	pub script: Option<PathBuf>,
+	#[command(subcommand)]
+	pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+	/// Check every selected step and set the ones that drifted.
+	Apply(StepArgs),
+	/// Report what apply would change, without changing anything.
+	Plan(StepArgs),
+	/// Exit non-zero if any selected step has drifted.
+	Check(StepArgs),
+	/// Tear down the selected steps in reverse order.
+	Reset {
+		/// Confirm the destructive reset.
+		#[arg(long)]
+		yes: bool,
+		#[command(flatten)]
+		steps: StepArgs,
+	},
+	/// List setup steps in execution order.
+	ListSteps,
+	/// Show this machine's identity and the state of every step.
+	Status,
+	/// Check steps on an interval, log drift and optionally remediate it.
+	Watch(WatchArgs),
+	/// Take a verified etcd snapshot and rotate old ones, the backup timer runs this.
+	Backup {
+		/// Snapshot directory, defaults to etcd_backup.dir.
+		#[arg(long, value_name = "PATH")]
+		dir: Option<PathBuf>,
+		/// Snapshots kept, defaults to etcd_backup.retention.
+		#[arg(long, value_name = "COUNT")]
+		retention: Option<usize>,
+	},
+	/// Rebuild the control plane root from an etcd snapshot, keeping the cluster CA.
+	Restore {
+		/// Snapshot taken by `infra backup`.
+		#[arg(long, value_name = "FILE")]
+		snapshot: PathBuf,
+		/// Cluster CA to keep, e.g. /etc/kubernetes/pki copied from a surviving control plane.
+		#[arg(long, value_name = "DIR", default_value = "/etc/kubernetes/pki")]
+		pki: PathBuf,
+		/// Only restore the snapshot into this etcd data dir, leaving the node alone.
+		#[arg(long, value_name = "DIR")]
+		data_dir: Option<PathBuf>,
+		/// Confirm resetting this node.
+		#[arg(long)]
+		yes: bool,
+	},
+	/// Roll every inventory machine to the configured Kubernetes version, one at a time.
+	Upgrade {
+		/// SSH private key used for every machine.
+		#[arg(long, value_name = "PATH")]
+		identity: Option<PathBuf>,
+		/// Run one node-local phase, the rolling upgrade calls this over SSH.
+		#[arg(long, value_enum, hide = true)]
+		phase: Option<UpgradePhase>,
+	},
+	/// Manage single inventory machines in the cluster.
+	Node {
+		#[command(subcommand)]
+		command: NodeCommand,
+	},
+	/// Drive every inventory machine over SSH from this workstation.
+	Fleet {
+		#[command(subcommand)]
+		command: FleetCommand,
+	},
+}
+
+impl Command {
+	pub fn name(&self) -> &'static str {
+		match self {
+			Command::Apply(_) => "apply",
+			Command::Plan(_) => "plan",
+			Command::Check(_) => "check",
+			Command::Reset { .. } => "reset",
+			Command::ListSteps => "list-steps",
+			Command::Status => "status",
+			Command::Watch(_) => "watch",
+			Command::Backup { .. } => "backup",
+			Command::Restore { .. } => "restore",
+			Command::Upgrade { .. } => "upgrade",
+			Command::Node { .. } => "node",
+			Command::Fleet { .. } => "fleet",
+		}
+	}
+}
+
+#[derive(Debug, Subcommand)]
+pub enum FleetCommand {
+	/// Apply the root first, then join control planes one at a time and workers in parallel.
+	Apply {
+		/// Workers applied at once.
+		#[arg(long, default_value_t = 4)]
+		parallel: usize,
+		/// SSH private key used for every machine.
+		#[arg(long, value_name = "PATH")]
+		identity: Option<PathBuf>,
+	},
+}
+
+#[derive(Debug, Subcommand)]
+pub enum NodeCommand {
+	/// Drain a machine, remove its etcd member and kube-vip, reset it and drop it from the inventory.
+	Remove {
+		/// The machine's /etc/machine-id as listed in the inventory.
+		machine_id: String,
+		/// SSH private key used for every machine.
+		#[arg(long, value_name = "PATH")]
+		identity: Option<PathBuf>,
+	},
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum UpgradePhase {
+	/// Upgrade kubeadm and the control plane or node configuration.
+	Kubeadm,
+	/// Upgrade kubelet and kubectl and restart the kubelet.
+	Kubelet,
+}
+
+#[derive(Debug, Args)]
+pub struct WatchArgs {
+	/// Seconds between passes.
+	#[arg(long, default_value_t = 300, value_name = "SECS")]
+	pub interval: u64,
+	/// Apply these steps again when they drift, ControlPlane and Worker are refused.
+	#[arg(long, value_delimiter = ',', value_name = "STEP")]
+	pub remediate: Vec<String>,
+	/// Run a single pass and exit, as the systemd timer does.
+	#[arg(long)]
+	pub once: bool,
+	/// Serve Prometheus metrics on this address, e.g. 0.0.0.0:9469.
+	#[arg(long, value_name = "ADDR", conflicts_with = "once")]
+	pub metrics: Option<SocketAddr>,
+	/// Write and enable a systemd unit running this watch.
+	#[arg(long)]
+	pub install_unit: bool,
+	#[command(flatten)]
+	pub steps: StepArgs,
+}
+
+#[derive(Debug, Default, Args)]
+pub struct StepArgs {
+	/// Only run these steps.
+	#[arg(long, value_delimiter = ',', value_name = "STEP")]
+	pub only: Vec<String>,
+	/// Skip these steps.
+	#[arg(long, value_delimiter = ',', value_name = "STEP")]
+	pub skip: Vec<String>,
+	/// Start from this step, skipping the ones before it.
+	#[arg(long, value_name = "STEP")]
+	pub from: Option<String>,
+}
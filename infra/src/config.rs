@@ -0,0 +1,378 @@
+use crate::error::InstallError;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{fs, io, net::IpAddr, path::Path, sync::OnceLock};
+use tracing::info;
+
+pub const DEFAULT_PATH: &str = "/etc/8inary/cluster.toml";
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+	pub kubernetes: Kubernetes,
+	pub kube_vip: KubeVip,
+	pub cilium: Cilium,
+	pub istio: Istio,
+	pub firewall: Firewall,
+	pub identity_database: IdentityDatabase,
+	pub etcd_backup: EtcdBackup,
+	pub certificates: Certificates,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Kubernetes {
+	pub version: String,
+	pub pod_cidr: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KubeVip {
+	pub address: String,
+	pub port: u16,
+	pub interface: String,
+	pub image: String,
+	pub version: String,
+	pub image_hash: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Cilium {
+	pub version: String,
+	pub cli_version: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Istio {
+	pub version: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Firewall {
+	pub source_cidr: String,
+	pub backend: FirewallBackend,
+}
+
+// Auto picks ufw when it is active, nftables otherwise.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FirewallBackend {
+	Auto,
+	Nftables,
+	Ufw,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct IdentityDatabase {
+	pub operator_version: String,
+	pub pd_replicas: u32,
+	pub tikv_replicas: u32,
+	pub tidb_replicas: u32,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EtcdBackup {
+	pub etcd_version: String,
+	pub dir: String,
+	pub retention: usize,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Certificates {
+	pub renew_before_days: u32,
+}
+
+impl Default for Kubernetes {
+	fn default() -> Self {
+		Kubernetes {
+			version: "v1.34.2".to_owned(),
+			pod_cidr: "10.0.0.0/16".to_owned(),
+		}
+	}
+}
+
+impl Default for KubeVip {
+	fn default() -> Self {
+		KubeVip {
+			address: "192.168.0.2".to_owned(),
+			port: 6443,
+			interface: "wlo1".to_owned(),
+			image: "ghcr.io/kube-vip/kube-vip".to_owned(),
+			version: "v1.0.2".to_owned(),
+			image_hash: "f86c774c4c0dcab81e56e3bdb42a5a6105c324767cfbc3a44df044f8a2666f8e"
+				.to_owned(),
+		}
+	}
+}
+
+impl Default for Cilium {
+	fn default() -> Self {
+		Cilium {
+			version: "v1.18.4".to_owned(),
+			cli_version: "v0.18.9".to_owned(),
+		}
+	}
+}
+
+impl Default for Istio {
+	fn default() -> Self {
+		Istio {
+			version: "1.28.0".to_owned(),
+		}
+	}
+}
+
+impl Default for Firewall {
+	fn default() -> Self {
+		Firewall {
+			source_cidr: "192.168.0.0/16".to_owned(),
+			backend: FirewallBackend::Auto,
+		}
+	}
+}
+
+impl Default for IdentityDatabase {
+	fn default() -> Self {
+		IdentityDatabase {
+			operator_version: "v1.6.3".to_owned(),
+			pd_replicas: 5,
+			tikv_replicas: 5,
+			tidb_replicas: 5,
+		}
+	}
+}
+
+impl Default for EtcdBackup {
+	fn default() -> Self {
+		EtcdBackup {
+			etcd_version: "v3.6.4".to_owned(),
+			dir: "/var/backups/etcd".to_owned(),
+			retention: 7,
+		}
+	}
+}
+
+impl Default for Certificates {
+	fn default() -> Self {
+		Certificates {
+			renew_before_days: 30,
+		}
+	}
+}
+
+impl Kubernetes {
+	pub fn package_version(&self) -> &str {
+		self.version.strip_prefix('v').unwrap_or(&self.version)
+	}
+
+	pub fn minor(&self) -> &str {
+		match self.version.rmatch_indices('.').next() {
+			Some((idx, _)) => &self.version[..idx],
+			None => &self.version,
+		}
+	}
+}
+
+impl KubeVip {
+	pub fn endpoint(&self) -> String {
+		format!("{}:{}", self.address, self.port)
+	}
+}
+
+impl Config {
+	pub fn load(path: &Path) -> Result<Config, InstallError> {
+		let config_txt = fs::read_to_string(path)?;
+		let config: Config = toml::from_str(&config_txt)
+			.map_err(|err| InstallError::Config(format!("{}: {}", path.display(), err)))?;
+		config
+			.validate()
+			.map_err(|err| InstallError::Config(format!("{}: {}", path.display(), err)))?;
+		Ok(config)
+	}
+
+	// Fingerprint of the effective spec, defaults included, recorded in the state ledger.
+	pub fn hash(&self) -> String {
+		let spec = toml::to_string(self).expect("Fatal config serialization.");
+		format!("{:x}", Sha256::digest(spec))
+	}
+
+	fn validate(&self) -> Result<(), String> {
+		check_version("kubernetes.version", &self.kubernetes.version)?;
+		check_cidr("kubernetes.pod_cidr", &self.kubernetes.pod_cidr)?;
+		self.kube_vip.address.parse::<IpAddr>().map_err(|_| {
+			format!(
+				"kube_vip.address '{}' is not an IP address",
+				self.kube_vip.address
+			)
+		})?;
+		if self.kube_vip.port == 0 {
+			return Err("kube_vip.port must not be 0".to_owned());
+		}
+		if self.kube_vip.interface.is_empty() {
+			return Err("kube_vip.interface must not be empty".to_owned());
+		}
+		check_version("kube_vip.version", &self.kube_vip.version)?;
+		if self.kube_vip.image_hash.len() != 64
+			|| !self
+				.kube_vip
+				.image_hash
+				.chars()
+				.all(|ch| ch.is_ascii_hexdigit())
+		{
+			return Err("kube_vip.image_hash must be a sha256 hex digest".to_owned());
+		}
+		check_version("cilium.version", &self.cilium.version)?;
+		check_version("cilium.cli_version", &self.cilium.cli_version)?;
+		check_version("istio.version", &self.istio.version)?;
+		check_cidr("firewall.source_cidr", &self.firewall.source_cidr)?;
+		let database = &self.identity_database;
+		check_version(
+			"identity_database.operator_version",
+			&database.operator_version,
+		)?;
+		if database.pd_replicas.is_multiple_of(2) {
+			return Err(format!(
+				"identity_database.pd_replicas must be odd for raft quorum, got {}",
+				database.pd_replicas
+			));
+		}
+		if database.tikv_replicas == 0 || database.tidb_replicas == 0 {
+			return Err("identity_database replicas must be at least 1".to_owned());
+		}
+		let backup = &self.etcd_backup;
+		check_version("etcd_backup.etcd_version", &backup.etcd_version)?;
+		if !Path::new(&backup.dir).is_absolute() {
+			return Err(format!(
+				"etcd_backup.dir '{}' is not an absolute path",
+				backup.dir
+			));
+		}
+		if backup.retention == 0 {
+			return Err("etcd_backup.retention must keep at least 1 snapshot".to_owned());
+		}
+		// kubeadm issues certificates for a year, a longer window would renew on every run.
+		if !(1..365).contains(&self.certificates.renew_before_days) {
+			return Err(format!(
+				"certificates.renew_before_days must be between 1 and 364, got {}",
+				self.certificates.renew_before_days
+			));
+		}
+		Ok(())
+	}
+}
+
+fn check_version(field: &str, version: &str) -> Result<(), String> {
+	let numbers = version
+		.strip_prefix('v')
+		.unwrap_or(version)
+		.split('.')
+		.collect::<Vec<_>>();
+	if numbers.len() == 3 && numbers.iter().all(|num| num.parse::<u32>().is_ok()) {
+		Ok(())
+	} else {
+		Err(format!("{field} '{version}' is not an X.Y.Z version"))
+	}
+}
+
+fn check_cidr(field: &str, cidr: &str) -> Result<(), String> {
+	let is_valid = cidr.split_once('/').is_some_and(|(addr, prefix)| {
+		match (addr.parse::<IpAddr>(), prefix.parse::<u8>()) {
+			(Ok(IpAddr::V4(_)), Ok(prefix)) => prefix <= 32,
+			(Ok(IpAddr::V6(_)), Ok(prefix)) => prefix <= 128,
+			_ => false,
+		}
+	});
+	if is_valid {
+		Ok(())
+	} else {
+		Err(format!("{field} '{cidr}' is not a valid CIDR"))
+	}
+}
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+pub fn init(path: Option<&Path>) -> Result<(), InstallError> {
+	let config = match path {
+		Some(path) => Config::load(path)?,
+		None => match Config::load(Path::new(DEFAULT_PATH)) {
+			Err(InstallError::Io(err)) if err.kind() == io::ErrorKind::NotFound => {
+				info!("No cluster config at {DEFAULT_PATH}, using built-in defaults.");
+				Config::default()
+			}
+			result => result?,
+		},
+	};
+	CONFIG.set(config).expect("Fatal config initialization.");
+	Ok(())
+}
+
+#[cfg(test)]
+pub fn set(config: Config) {
+	CONFIG.set(config).expect("Fatal config initialization.");
+}
+
+pub fn get() -> &'static Config {
+	CONFIG.get().expect("Fatal failure to get config.")
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn defaults_and_example_are_valid() {
+		Config::default().validate().unwrap();
+		Config::load(&Path::new(env!("CARGO_MANIFEST_DIR")).join("cluster.toml")).unwrap();
+	}
+
+	#[test]
+	fn bad_cidrs_are_refused() {
+		for cidr in [
+			"10.0.0.0",
+			"10.0.0.0/33",
+			"fd00::/129",
+			"10.0.0/16",
+			"cluster/16",
+			"10.0.0.0/-1",
+			"",
+		] {
+			let mut config = Config::default();
+			config.kubernetes.pod_cidr = cidr.to_owned();
+			assert_eq!(
+				config.validate(),
+				Err(format!("kubernetes.pod_cidr '{cidr}' is not a valid CIDR"))
+			);
+		}
+		for cidr in ["0.0.0.0/0", "10.1.2.3/32", "fd00:10::/64", "::/0"] {
+			let mut config = Config::default();
+			config.firewall.source_cidr = cidr.to_owned();
+			assert_eq!(config.validate(), Ok(()), "{cidr}");
+		}
+	}
+
+	#[test]
+	fn renew_before_days_bounds() {
+		for days in [1, 30, 364] {
+			let mut config = Config::default();
+			config.certificates.renew_before_days = days;
+			assert_eq!(config.validate(), Ok(()), "{days}");
+		}
+		for days in [0, 365, 1000] {
+			let mut config = Config::default();
+			config.certificates.renew_before_days = days;
+			assert_eq!(
+				config.validate(),
+				Err(format!(
+					"certificates.renew_before_days must be between 1 and 364, got {days}"
+				))
+			);
+		}
+	}
+}
@@ -10,8 +10,21 @@ pub struct Context {
 
 static CONTEXT: OnceLock<Context> = OnceLock::new();
 
+// Under sudo the invoking user owns the kubeconfig, systemd units run as root without one.
 pub fn init() {
-	let user = env::var("SUDO_USER").expect("Fatal failure to resolve sudo user.");
+	let user = match env::var("SUDO_USER") {
+		Ok(user) => user,
+		Err(_) => str::from_utf8(
+			&Command::new("id")
+				.arg("-un")
+				.output()
+				.expect("Fatal failure to resolve the current user.")
+				.stdout,
+		)
+		.expect("Fatal failure in user name non-utf8 encoding.")
+		.trim()
+		.to_owned(),
+	};
 	let home = str::from_utf8(
 		&Command::new("bash")
 			.arg("-c")
@@ -37,12 +50,15 @@ pub fn init() {
 		.expect("Fatal failure to resolve machine-id.")
 		.trim()
 		.to_owned();
-	let context = Context {
+	set(Context {
 		home,
 		hostname,
 		machine_id,
 		user,
-	};
+	});
+}
+
+pub fn set(context: Context) {
 	CONTEXT.set(context).expect("Fatal context initialization.");
 }
 
@@ -1,4 +1,4 @@
-use std::{io, process::ExitStatus, str::Utf8Error, string::FromUtf8Error};
+use std::{io, process::ExitStatus, str::Utf8Error, string::FromUtf8Error, time::Duration};
 
 #[derive(Debug, thiserror::Error)]
 pub enum InstallError {
@@ -19,12 +19,42 @@ pub enum InstallError {
 		stderr: Option<String>,
 	},
 
+	#[error("Command timed out after {}s: {cmd}", timeout.as_secs())]
+	CommandTimeout { cmd: String, timeout: Duration },
+
+	#[error("Not ready: {0}.")]
+	NotReady(String),
+
+	#[error("Gave up on {what} after {attempts} attempts in {}s: {last}", elapsed.as_secs())]
+	RetryExhausted {
+		what: String,
+		attempts: u32,
+		elapsed: Duration,
+		#[source]
+		last: Box<InstallError>,
+	},
+
 	#[error("Step '{step}' failed after attempt to set it.")]
 	StepFailed { step: &'static str },
 
 	#[error("Kubernetes error: {0}")]
 	Kube(String),
 
+	#[error("Kubernetes API error: {0}")]
+	KubeApi(#[from] kube::Error),
+
+	#[error("Kubeconfig error: {0}")]
+	Kubeconfig(#[from] kube::config::KubeconfigError),
+
+	#[error("Refusing to break etcd quorum: {0}.")]
+	Quorum(String),
+
+	#[error("Unsupported version skew: {0}.")]
+	VersionSkew(String),
+
+	#[error("Package error: {0}.")]
+	Package(String),
+
 	#[error("Helm error: {0}")]
 	Helm(String),
 
@@ -0,0 +1,523 @@
+use crate::config;
+use crate::error::InstallError;
+use crate::setup::{self, Cmd, Machine, MachineRole, Retry};
+use std::{
+	env,
+	ffi::OsStr,
+	path::{Path, PathBuf},
+	thread,
+	time::{Duration, Instant},
+};
+use tracing::{error, info, warn};
+
+const REMOTE_DIR: &str = "/tmp/8inary";
+// An unreachable or silently dropped machine fails within a minute instead of hanging the fleet.
+const SSH_OPTIONS: &[&str] = &[
+	"-o",
+	"BatchMode=yes",
+	"-o",
+	"LogLevel=ERROR",
+	"-o",
+	"ConnectTimeout=10",
+	"-o",
+	"ServerAliveInterval=15",
+	"-o",
+	"ServerAliveCountMax=4",
+];
+const REMOTE_TIMEOUT: Duration = Duration::from_secs(120);
+const UPLOAD_TIMEOUT: Duration = Duration::from_secs(600);
+const APPLY_TIMEOUT: Duration = Duration::from_secs(3600);
+const CURL_TIMEOUT: Duration = Duration::from_secs(10);
+const API_LIVE: Retry =
+	Retry::within(Duration::from_secs(300)).backoff(Duration::from_secs(5), Duration::from_secs(5));
+
+#[derive(Debug)]
+pub struct FleetOptions {
+	pub parallel: usize,
+	pub identity: Option<PathBuf>,
+	pub config: Option<PathBuf>,
+	pub inventory: Option<PathBuf>,
+}
+
+#[derive(Debug)]
+enum Outcome {
+	Applied,
+	Failed(String),
+	Skipped,
+}
+
+#[derive(Debug)]
+struct HostResult {
+	host: String,
+	role: MachineRole,
+	outcome: Outcome,
+	elapsed: Duration,
+}
+
+struct Uploads {
+	binary: PathBuf,
+	config: Option<PathBuf>,
+	inventory: PathBuf,
+}
+
+impl Uploads {
+	fn paths(&self) -> impl Iterator<Item = &PathBuf> {
+		[&self.binary]
+			.into_iter()
+			.chain(self.config.iter())
+			.chain([&self.inventory])
+	}
+}
+
+struct Host<'a> {
+	machine: &'a Machine,
+	address: String,
+}
+
+impl Host<'_> {
+	fn target(&self) -> String {
+		format!("{}@{}", self.machine.ssh_user, self.address)
+	}
+}
+
+fn hosts(command: &str) -> Result<Vec<Host<'static>>, InstallError> {
+	let inventory = setup::inventory();
+	if inventory.machines.is_empty() {
+		return Err(InstallError::Config(format!(
+			"{command} needs an inventory with machines"
+		)));
+	}
+	let mut hosts = Vec::with_capacity(inventory.machines.len());
+	for machine in &inventory.machines {
+		let address = machine
+			.ip
+			.map(|ip| ip.to_string())
+			.or_else(|| machine.hostname.clone())
+			.ok_or_else(|| {
+				InstallError::Config(format!(
+					"machine '{}' has neither ip nor hostname in the inventory",
+					machine.id
+				))
+			})?;
+		hosts.push(Host { machine, address });
+	}
+	Ok(hosts)
+}
+
+pub fn apply(options: &FleetOptions) -> Result<bool, InstallError> {
+	let hosts = hosts("fleet apply")?;
+	let uploads = uploads(options)?;
+	let (roots, others): (Vec<_>, Vec<_>) = hosts
+		.iter()
+		.partition(|host| host.machine.role == MachineRole::ControlPlaneRoot);
+	let (control_planes, workers): (Vec<_>, Vec<_>) = others
+		.into_iter()
+		.partition(|host| host.machine.role == MachineRole::ControlPlane);
+	let mut results = Vec::with_capacity(hosts.len());
+	let root_results = roots
+		.iter()
+		.map(|host| apply_host(host, &uploads, options))
+		.collect::<Vec<_>>();
+	let root_ok = root_results
+		.iter()
+		.all(|result| matches!(result.outcome, Outcome::Applied));
+	results.extend(root_results);
+	let api_ok = root_ok && wait_for_api().inspect_err(|err| error!("{}", err)).is_ok();
+	if api_ok {
+		// Stacked etcd members are added one at a time, only workers fan out.
+		for host in &control_planes {
+			results.push(apply_host(host, &uploads, options));
+		}
+		for batch in workers.chunks(options.parallel.max(1)) {
+			let batch_results = thread::scope(|scope| {
+				let handles = batch
+					.iter()
+					.map(|host| scope.spawn(|| apply_host(host, &uploads, options)))
+					.collect::<Vec<_>>();
+				handles
+					.into_iter()
+					.zip(batch)
+					.map(|(handle, host)| {
+						handle.join().unwrap_or_else(|_| HostResult {
+							host: host.address.clone(),
+							role: host.machine.role,
+							outcome: Outcome::Failed("fleet worker panicked".to_owned()),
+							elapsed: Duration::ZERO,
+						})
+					})
+					.collect::<Vec<_>>()
+			});
+			results.extend(batch_results);
+		}
+	} else {
+		for host in control_planes.iter().chain(workers.iter()) {
+			results.push(HostResult {
+				host: host.address.clone(),
+				role: host.machine.role,
+				outcome: Outcome::Skipped,
+				elapsed: Duration::ZERO,
+			});
+		}
+	}
+	print_summary(&results);
+	Ok(results
+		.iter()
+		.all(|result| matches!(result.outcome, Outcome::Applied)))
+}
+
+// Strictly one machine at a time, each one drained, upgraded and healthy before the next.
+pub fn upgrade(options: &FleetOptions) -> Result<bool, InstallError> {
+	let mut hosts = hosts("upgrade")?;
+	hosts.sort_by_key(|host| match host.machine.role {
+		MachineRole::ControlPlaneRoot => 0,
+		MachineRole::ControlPlane => 1,
+		MachineRole::Worker => 2,
+	});
+	let root = hosts
+		.first()
+		.filter(|host| host.machine.role == MachineRole::ControlPlaneRoot)
+		.ok_or_else(|| {
+			InstallError::Config("upgrade needs a control plane root in the inventory".to_owned())
+		})?;
+	let admin_kubeconfig = remote_output(
+		root,
+		options,
+		&["sudo", "-n", "cat", setup::upgrade::ADMIN_KUBECONFIG],
+	)?;
+	setup::upgrade::preflight(admin_kubeconfig)?;
+	let uploads = uploads(options)?;
+	let mut results = Vec::with_capacity(hosts.len());
+	for host in &hosts {
+		let failed = results
+			.iter()
+			.any(|result: &HostResult| !matches!(result.outcome, Outcome::Applied));
+		if failed {
+			results.push(HostResult {
+				host: host.address.clone(),
+				role: host.machine.role,
+				outcome: Outcome::Skipped,
+				elapsed: Duration::ZERO,
+			});
+			continue;
+		}
+		let started = Instant::now();
+		info!("Upgrading {} ({:?}).", host.address, host.machine.role);
+		let outcome = match upgrade_host(host, &uploads, options) {
+			Ok(()) => Outcome::Applied,
+			Err(err) => {
+				error!("Upgrade failed on {}: {}", host.address, err);
+				Outcome::Failed(err.to_string())
+			}
+		};
+		results.push(HostResult {
+			host: host.address.clone(),
+			role: host.machine.role,
+			outcome,
+			elapsed: started.elapsed(),
+		});
+	}
+	print_summary(&results);
+	Ok(results
+		.iter()
+		.all(|result| matches!(result.outcome, Outcome::Applied)))
+}
+
+// A failure after the cordon puts the node back into service, the upgrade still stops there.
+fn upgrade_host(
+	host: &Host,
+	uploads: &Uploads,
+	options: &FleetOptions,
+) -> Result<(), InstallError> {
+	upload(host, uploads, options)?;
+	let node = node_name(host, options)?;
+	run_remote(
+		host,
+		uploads,
+		options,
+		&["upgrade", "--phase", "kubeadm"],
+		setup::upgrade::PHASE_TIMEOUT,
+	)?;
+	let upgraded = setup::upgrade::drain(&node).and_then(|()| {
+		run_remote(
+			host,
+			uploads,
+			options,
+			&["upgrade", "--phase", "kubelet"],
+			setup::upgrade::PHASE_TIMEOUT,
+		)
+	});
+	if let Err(err) = upgraded {
+		warn!("Upgrade of {} failed, restoring the node.", node);
+		if let Err(restore_err) = setup::upgrade::restore(&node) {
+			error!("Restoring {} failed: {}", node, restore_err);
+		}
+		return Err(err);
+	}
+	setup::upgrade::restore(&node)
+}
+
+// Cluster changes go through the root, the target is only touched for its hostname, kube-vip and reset.
+pub fn remove_node(options: &FleetOptions, machine_id: &str) -> Result<(), InstallError> {
+	let hosts = hosts("node remove")?;
+	let root = hosts
+		.iter()
+		.find(|host| host.machine.role == MachineRole::ControlPlaneRoot)
+		.ok_or_else(|| InstallError::Config("inventory has no ControlPlaneRoot".to_owned()))?;
+	let target = hosts
+		.iter()
+		.find(|host| host.machine.id == machine_id)
+		.ok_or_else(|| {
+			InstallError::Config(format!("machine-id '{machine_id}' is not in the inventory"))
+		})?;
+	if target.machine.role == MachineRole::ControlPlaneRoot {
+		return Err(InstallError::Config(format!(
+			"machine '{machine_id}' is the ControlPlaneRoot, promote another control plane first"
+		)));
+	}
+	setup::decommission::connect(remote_output(
+		root,
+		options,
+		&["sudo", "-n", "cat", setup::upgrade::ADMIN_KUBECONFIG],
+	)?);
+	let node = node_name(target, options)?;
+	let member = if target.machine.role.runs_etcd() {
+		let root_node = node_name(root, options)?;
+		let members = remote_output(
+			root,
+			options,
+			&setup::decommission::etcdctl(&root_node, &["member", "list", "--write-out", "json"]),
+		)?;
+		// endpoint health exits non-zero when any member is down, its report is still complete.
+		let health = ssh(root, options)
+			.args(setup::decommission::etcdctl(
+				&root_node,
+				&["endpoint", "health", "--cluster", "--write-out", "json"],
+			))
+			.probe()?;
+		setup::decommission::etcd_member(&members, &health.stdout, &node)?
+			.map(|member| (root_node, member))
+	} else {
+		None
+	};
+	info!("Removing {} ({}) from the cluster.", machine_id, node);
+	setup::upgrade::drain(&node)?;
+	if target.machine.role.runs_etcd() {
+		info!("Removing kube-vip from {}.", node);
+		remote_output(
+			target,
+			options,
+			&[
+				"sudo",
+				"-n",
+				"rm",
+				"-f",
+				setup::decommission::KUBE_VIP_MANIFEST,
+			],
+		)?;
+		setup::decommission::wait_api()?;
+	}
+	if let Some((root_node, member)) = member {
+		info!("Removing etcd member {} of {}.", member, node);
+		remote_output(
+			root,
+			options,
+			&setup::decommission::etcdctl(&root_node, &["member", "remove", &member]),
+		)?;
+	}
+	setup::decommission::delete_node(&node)?;
+	let step = match target.machine.role {
+		MachineRole::Worker => "Worker",
+		MachineRole::ControlPlane | MachineRole::ControlPlaneRoot => "ControlPlane",
+	};
+	let uploads = uploads(options)?;
+	upload(target, &uploads, options)?;
+	run_remote(
+		target,
+		&uploads,
+		options,
+		&["reset", "--yes", "--only", step],
+		APPLY_TIMEOUT,
+	)?;
+	setup::decommission::remove_from_inventory(&uploads.inventory, machine_id)?;
+	info!("Machine {} has been removed from the cluster.", machine_id);
+	Ok(())
+}
+
+// kubeadm registers nodes under their lowercased hostname.
+fn node_name(host: &Host, options: &FleetOptions) -> Result<String, InstallError> {
+	Ok(remote_output(host, options, &["hostname"])?
+		.trim()
+		.to_lowercase())
+}
+
+fn uploads(options: &FleetOptions) -> Result<Uploads, InstallError> {
+	let config = match &options.config {
+		Some(path) => Some(path.clone()),
+		None => Some(PathBuf::from(config::DEFAULT_PATH)).filter(|path| path.exists()),
+	};
+	let inventory = options
+		.inventory
+		.clone()
+		.unwrap_or_else(|| PathBuf::from(setup::INVENTORY_PATH));
+	Ok(Uploads {
+		binary: env::current_exe()?,
+		config,
+		inventory,
+	})
+}
+
+fn remote_path(local: &Path) -> String {
+	format!(
+		"{}/{}",
+		REMOTE_DIR,
+		local.file_name().unwrap_or_default().to_string_lossy()
+	)
+}
+
+// Bounded by REMOTE_TIMEOUT unless the caller sets a longer one.
+fn ssh(host: &Host, options: &FleetOptions) -> Cmd {
+	let mut cmd = Cmd::new("ssh").args(SSH_OPTIONS);
+	if let Some(identity) = &options.identity {
+		cmd = cmd.arg("-i").arg(identity);
+	}
+	cmd.arg(host.target()).timeout(REMOTE_TIMEOUT)
+}
+
+fn apply_host(host: &Host, uploads: &Uploads, options: &FleetOptions) -> HostResult {
+	let started = Instant::now();
+	info!("Fleet apply on {} ({:?}).", host.address, host.machine.role);
+	let outcome = match run_host(host, uploads, options) {
+		Ok(()) => Outcome::Applied,
+		Err(err) => {
+			error!("Fleet apply failed on {}: {}", host.address, err);
+			Outcome::Failed(err.to_string())
+		}
+	};
+	HostResult {
+		host: host.address.clone(),
+		role: host.machine.role,
+		outcome,
+		elapsed: started.elapsed(),
+	}
+}
+
+fn run_host(host: &Host, uploads: &Uploads, options: &FleetOptions) -> Result<(), InstallError> {
+	upload(host, uploads, options)?;
+	run_remote(host, uploads, options, &["apply"], APPLY_TIMEOUT)
+}
+
+fn upload(host: &Host, uploads: &Uploads, options: &FleetOptions) -> Result<(), InstallError> {
+	ssh(host, options)
+		.args(["mkdir", "-p", REMOTE_DIR])
+		.echo(&host.address)
+		.run()?;
+	let mut scp = Cmd::new("scp").arg("-q").args(SSH_OPTIONS);
+	if let Some(identity) = &options.identity {
+		scp = scp.arg("-i").arg(identity);
+	}
+	scp.args(uploads.paths())
+		.arg(format!("{}:{}/", host.target(), REMOTE_DIR))
+		.timeout(UPLOAD_TIMEOUT)
+		.echo(&host.address)
+		.run()
+}
+
+fn run_remote(
+	host: &Host,
+	uploads: &Uploads,
+	options: &FleetOptions,
+	command: &[&str],
+	timeout: Duration,
+) -> Result<(), InstallError> {
+	let mut remote_args = vec![
+		"sudo".to_owned(),
+		"-n".to_owned(),
+		remote_path(&uploads.binary),
+	];
+	if let Some(config) = &uploads.config {
+		remote_args.push("--config".to_owned());
+		remote_args.push(remote_path(config));
+	}
+	remote_args.push("--inventory".to_owned());
+	remote_args.push(remote_path(&uploads.inventory));
+	remote_args.extend(command.iter().map(|arg| (*arg).to_owned()));
+	ssh(host, options)
+		.args(remote_args)
+		.timeout(timeout)
+		.echo(&host.address)
+		.run()
+}
+
+fn remote_output(
+	host: &Host,
+	options: &FleetOptions,
+	args: &[impl AsRef<OsStr>],
+) -> Result<String, InstallError> {
+	Ok(ssh(host, options).args(args).output()?.stdout)
+}
+
+fn wait_for_api() -> Result<(), InstallError> {
+	let endpoint = config::get().kube_vip.endpoint();
+	let url = format!("https://{endpoint}/livez");
+	info!("Waiting for the API server at {}.", endpoint);
+	API_LIVE.until(&format!("the API server at {endpoint}"), || {
+		Ok(Cmd::new("curl")
+			.args(["-ksf", "--max-time", "5", "-o", "/dev/null", &url])
+			.timeout(CURL_TIMEOUT)
+			.probe()
+			.is_ok_and(|output| output.success()))
+	})?;
+	info!("API server at {} is live.", endpoint);
+	Ok(())
+}
+
+fn print_summary(results: &[HostResult]) {
+	println!("Fleet summary:");
+	for result in results {
+		let outcome = match &result.outcome {
+			Outcome::Applied => "ok".to_owned(),
+			Outcome::Failed(err) => format!("failed: {err}"),
+			Outcome::Skipped => "skipped".to_owned(),
+		};
+		println!(
+			"  {:<24} {:<16} {:>6}s  {}",
+			result.host,
+			format!("{:?}", result.role),
+			result.elapsed.as_secs(),
+			outcome
+		);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn ssh_bounds_connect_and_dead_connections() {
+		let machine: Machine = toml::from_str(
+			r#"
+				id = "0123456789abcdef0123456789abcdef"
+				environment = "Dev"
+				role = "Worker"
+				ip = "10.0.0.7"
+			"#,
+		)
+		.unwrap();
+		let host = Host {
+			machine: &machine,
+			address: "10.0.0.7".to_owned(),
+		};
+		let options = FleetOptions {
+			parallel: 1,
+			identity: Some(PathBuf::from("/keys/fleet")),
+			config: None,
+			inventory: None,
+		};
+		assert_eq!(
+			ssh(&host, &options).arg("hostname").display(),
+			"ssh -o BatchMode=yes -o LogLevel=ERROR -o ConnectTimeout=10 -o ServerAliveInterval=15 \
+			 -o ServerAliveCountMax=4 -i /keys/fleet mgmt@10.0.0.7 hostname"
+		);
+	}
+}
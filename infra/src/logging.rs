@@ -1,4 +1,4 @@
-use std::panic;
+use std::{io, panic};
 use tracing_journald::layer as journald_layer;
 use tracing_panic::panic_hook;
 use tracing_subscriber::{fmt, layer::SubscriberExt, registry::Registry, EnvFilter};
@@ -21,6 +21,7 @@ pub fn init() {
 				.with_thread_ids(true)
 				.with_thread_names(true)
 				.with_timer(fmt::time::SystemTime)
+				.with_writer(io::stderr)
 				.compact(),
 		)
 		.with(
@@ -1,17 +1,380 @@
+mod cli;
+mod config;
 mod context;
 mod error;
+mod fleet;
 mod logging;
+mod metrics;
 mod setup;
+mod watch;
 
-use tracing::{error, info};
+use clap::{Parser, error::ErrorKind};
+use cli::{Cli, Command, FleetCommand, NodeCommand, StepArgs, UpgradePhase, WatchArgs};
+use error::InstallError;
+use setup::{Report, StepFilter};
+use std::{
+	path::{Path, PathBuf},
+	process::exit,
+	time::Duration,
+};
+use tracing::{error, info, warn};
+
+const EXIT_STEP_FAILED: i32 = 1;
+const EXIT_DRIFT: i32 = 2;
+const EXIT_USAGE: i32 = 64;
+const EXIT_CONFIG: i32 = 78;
+const STATUS_HISTORY: usize = 20;
 
 fn main() {
-	context::init();
+	let mut cli = match Cli::try_parse() {
+		Ok(cli) => cli,
+		Err(err) => {
+			let _ = err.print();
+			match err.kind() {
+				ErrorKind::DisplayHelp | ErrorKind::DisplayVersion => exit(0),
+				_ => exit(EXIT_USAGE),
+			}
+		}
+	};
+	let command = cli
+		.command
+		.take()
+		.unwrap_or(Command::Apply(StepArgs::default()));
+	if let Command::ListSteps = command {
+		match setup::step_names() {
+			Ok(steps) => {
+				for (name, requires) in steps {
+					if requires.is_empty() {
+						println!("{name}");
+					} else {
+						println!("{name} (after {})", requires.join(", "));
+					}
+				}
+			}
+			Err(err) => {
+				eprintln!("error: {err}");
+				exit(EXIT_USAGE);
+			}
+		}
+		return;
+	}
+	let filter = match &command {
+		Command::Apply(args)
+		| Command::Plan(args)
+		| Command::Check(args)
+		| Command::Reset { steps: args, .. }
+		| Command::Watch(WatchArgs { steps: args, .. }) => StepFilter {
+			only: args.only.clone(),
+			skip: args.skip.clone(),
+			from: args.from.clone(),
+		},
+		_ => StepFilter::default(),
+	};
+	if let Err(err) = setup::validate(&filter) {
+		eprintln!("error: {err}");
+		exit(EXIT_USAGE);
+	}
+	if let Command::Watch(args) = &command {
+		let remediate = StepFilter {
+			only: args.remediate.clone(),
+			..StepFilter::default()
+		};
+		if let Err(err) =
+			setup::validate(&remediate).and_then(|()| watch::validate_remediate(&args.remediate))
+		{
+			eprintln!("error: {err}");
+			exit(EXIT_USAGE);
+		}
+		if args.interval == 0 {
+			eprintln!("error: --interval must be at least 1 second.");
+			exit(EXIT_USAGE);
+		}
+	}
+	if let Command::Reset { yes: false, .. } = command {
+		eprintln!("error: reset tears down this node's setup, pass --yes to confirm.");
+		exit(EXIT_USAGE);
+	}
+	if let Command::Restore {
+		yes: false,
+		data_dir: None,
+		..
+	} = command
+	{
+		eprintln!(
+			"error: restore resets this node and rebuilds the cluster, pass --yes to confirm."
+		);
+		exit(EXIT_USAGE);
+	}
 	logging::init();
-	info!("Infrastructure setup started.");
-	if let Err(err) = setup::setup() {
-		error!("Installer failed: {}", err);
-		std::process::exit(1);
+	if let Err(err) = config::init(cli.config.as_deref()) {
+		error!("Cluster config failed: {}", err);
+		exit(EXIT_CONFIG);
+	}
+	let etcd_members = match command {
+		Command::Node {
+			command: NodeCommand::Remove { .. },
+		} => setup::EtcdMembers::Any,
+		_ => setup::EtcdMembers::Odd,
 	};
-	info!("Infrastructure setup finished successfully.");
+	if let Err(err) = setup::init_inventory(cli.inventory.as_deref(), etcd_members) {
+		error!("Inventory failed: {}", err);
+		exit(EXIT_CONFIG);
+	}
+	if let Command::Fleet {
+		command: FleetCommand::Apply { parallel, identity },
+	} = command
+	{
+		let options = fleet::FleetOptions {
+			parallel,
+			identity,
+			config: cli.config,
+			inventory: cli.inventory,
+		};
+		match fleet::apply(&options) {
+			Ok(true) => exit(0),
+			Ok(false) => exit(EXIT_STEP_FAILED),
+			Err(err) => {
+				error!("Fleet apply failed: {}", err);
+				exit(EXIT_CONFIG);
+			}
+		}
+	}
+	if let Command::Node {
+		command: NodeCommand::Remove {
+			machine_id,
+			identity,
+		},
+	} = command
+	{
+		let options = fleet::FleetOptions {
+			parallel: 1,
+			identity,
+			config: cli.config,
+			inventory: cli.inventory,
+		};
+		match fleet::remove_node(&options, &machine_id) {
+			Ok(()) => exit(0),
+			Err(err) => {
+				error!("Node remove failed: {}", err);
+				exit(match err {
+					InstallError::Config(_) => EXIT_CONFIG,
+					_ => EXIT_STEP_FAILED,
+				});
+			}
+		}
+	}
+	if let Command::Upgrade {
+		identity,
+		phase: None,
+	} = command
+	{
+		let options = fleet::FleetOptions {
+			parallel: 1,
+			identity,
+			config: cli.config,
+			inventory: cli.inventory,
+		};
+		match fleet::upgrade(&options) {
+			Ok(true) => exit(0),
+			Ok(false) => exit(EXIT_STEP_FAILED),
+			Err(err) => {
+				error!("Upgrade failed: {}", err);
+				exit(EXIT_CONFIG);
+			}
+		}
+	}
+	if let Some(script) = &cli.script
+		&& let Err(err) = setup::init_script(script)
+	{
+		error!("Command script failed: {}", err);
+		exit(EXIT_CONFIG);
+	}
+	let ledger_path = setup::scripted_commands()
+		.is_none()
+		.then(|| Path::new(setup::LEDGER_PATH));
+	if let Err(err) = setup::init_ledger(ledger_path) {
+		error!("State ledger failed: {}", err);
+		exit(EXIT_CONFIG);
+	}
+	context::init();
+	if let Command::Watch(args) = command {
+		let options = watch::WatchOptions {
+			interval: Duration::from_secs(args.interval),
+			remediate: args.remediate,
+			once: args.once,
+			metrics: args.metrics,
+			filter,
+			config: cli.config,
+			inventory: cli.inventory,
+		};
+		if args.install_unit {
+			match watch::install_units(&options) {
+				Ok(()) => exit(0),
+				Err(err) => {
+					error!("Watch unit install failed: {}", err);
+					exit(EXIT_STEP_FAILED);
+				}
+			}
+		}
+		exit(match watch::run(&options) {
+			watch::Pass::Clean => 0,
+			watch::Pass::Drift => EXIT_DRIFT,
+			watch::Pass::Failed => EXIT_STEP_FAILED,
+		});
+	}
+	let mut report = Report::new(command.name());
+	let code = run(command, &filter, &mut report, cli.json);
+	report.finish(code);
+	if let Some(commands) = setup::scripted_commands() {
+		// Replayed runs are not real, keep them out of the run history.
+		if !cli.json {
+			println!("Recorded commands:");
+			for command in commands {
+				println!("  {command}");
+			}
+		}
+	} else if let Err(err) = report.write(Path::new(setup::RUNS_DIR)) {
+		warn!("Run report not written: {}", err);
+	}
+	if cli.json {
+		match report.to_json() {
+			Ok(json) => println!("{json}"),
+			Err(err) => error!("Run report failed: {}", err),
+		}
+	}
+	exit(code);
+}
+
+fn run(command: Command, filter: &StepFilter, report: &mut Report, json: bool) -> i32 {
+	match command {
+		Command::Apply(_) => {
+			info!("Infrastructure setup started.");
+			if let Err(err) = setup::setup(filter, report) {
+				error!("Installer failed: {}", err);
+				return EXIT_STEP_FAILED;
+			};
+			info!("Infrastructure setup finished successfully.");
+			0
+		}
+		Command::Plan(_) | Command::Check(_) | Command::Status => {
+			if let Command::Status = command
+				&& !json
+			{
+				let context = context::get();
+				println!("Host:    {}", context.hostname);
+				match setup::machine() {
+					Ok(machine) => {
+						println!("Machine: {}", machine.id);
+						println!("Env:     {:?}", machine.environment);
+						println!("Role:    {:?}", machine.role);
+						if let Some(ip) = machine.ip {
+							println!("IP:      {ip}");
+						}
+						if let Some(zone) = &machine.zone {
+							println!("Zone:    {zone}");
+						}
+						for (key, value) in &machine.labels {
+							println!("Label:   {key}={value}");
+						}
+						for disk in &machine.disks {
+							println!("Disk:    {disk}");
+						}
+					}
+					Err(err) => println!("Machine: {} ({err})", context.machine_id),
+				}
+				let history = setup::history();
+				if !history.is_empty() {
+					println!("History:");
+				}
+				for event in history
+					.iter()
+					.skip(history.len().saturating_sub(STATUS_HISTORY))
+				{
+					let versions = event
+						.versions
+						.iter()
+						.map(|(component, version)| format!(" {component}={version}"))
+						.collect::<String>();
+					println!(
+						"  {}  {:<8} {:<16} config {}{}",
+						event.at.strftime("%Y-%m-%d %H:%M:%S"),
+						format!("{:?}", event.action),
+						event.step,
+						&event.config_hash[..12.min(event.config_hash.len())],
+						versions
+					);
+				}
+			}
+			let plan = match setup::plan(filter, report) {
+				Ok(plan) => plan,
+				Err(err) => {
+					error!("Plan failed: {}", err);
+					return EXIT_STEP_FAILED;
+				}
+			};
+			match command {
+				_ if json => {}
+				Command::Check(_) => plan.print_drift(),
+				_ => plan.print(),
+			}
+			if plan.has_errors() {
+				EXIT_STEP_FAILED
+			} else if plan.has_drift() {
+				EXIT_DRIFT
+			} else {
+				0
+			}
+		}
+		Command::Reset { .. } => {
+			info!("Node reset started.");
+			if let Err(err) = setup::reset(filter, report) {
+				error!("Reset failed: {}", err);
+				return EXIT_STEP_FAILED;
+			}
+			info!("Node reset finished.");
+			0
+		}
+		Command::Backup { dir, retention } => {
+			let backup = &config::get().etcd_backup;
+			let dir = dir.unwrap_or_else(|| PathBuf::from(&backup.dir));
+			let retention = retention.unwrap_or(backup.retention).max(1);
+			if let Err(err) = setup::backup(&dir, retention, report) {
+				error!("Backup failed: {}", err);
+				return EXIT_STEP_FAILED;
+			}
+			0
+		}
+		Command::Restore {
+			snapshot,
+			pki,
+			data_dir,
+			..
+		} => {
+			info!("Restore started.");
+			if let Err(err) = setup::restore(&snapshot, &pki, data_dir.as_deref(), report) {
+				error!("Restore failed: {}", err);
+				return EXIT_STEP_FAILED;
+			}
+			info!("Restore finished.");
+			0
+		}
+		Command::Upgrade {
+			phase: Some(phase), ..
+		} => {
+			let result = match phase {
+				UpgradePhase::Kubeadm => setup::upgrade::kubeadm(report),
+				UpgradePhase::Kubelet => setup::upgrade::kubelet(report),
+			};
+			if let Err(err) = result {
+				error!("Upgrade phase failed: {}", err);
+				return EXIT_STEP_FAILED;
+			}
+			0
+		}
+		Command::ListSteps
+		| Command::Node { .. }
+		| Command::Fleet { .. }
+		| Command::Watch(_)
+		| Command::Upgrade { phase: None, .. } => 0,
+	}
 }
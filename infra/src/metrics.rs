@@ -0,0 +1,300 @@
+use crate::error::InstallError;
+use crate::setup::{CheckReport, Report};
+use std::{
+	collections::BTreeMap,
+	fmt::Write as _,
+	io::{self, BufRead, BufReader, Write},
+	net::{SocketAddr, TcpListener, TcpStream},
+	sync::{Mutex, MutexGuard, PoisonError},
+	thread,
+	time::Duration,
+};
+use tracing::{info, warn};
+
+#[derive(Debug, Default)]
+struct StepMetrics {
+	satisfied: bool,
+	duration_ms: u64,
+	failures: u64,
+	remediations: u64,
+}
+
+#[derive(Debug)]
+struct Metrics {
+	steps: BTreeMap<&'static str, StepMetrics>,
+	runs: u64,
+	last_run: i64,
+	last_duration_ms: u64,
+}
+
+static METRICS: Mutex<Metrics> = Mutex::new(Metrics {
+	steps: BTreeMap::new(),
+	runs: 0,
+	last_run: 0,
+	last_duration_ms: 0,
+});
+
+fn lock() -> MutexGuard<'static, Metrics> {
+	METRICS.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+// A watch pass reports the check of every step, then the set of remediated ones.
+pub fn observe(report: &Report) {
+	let mut metrics = lock();
+	let mut durations = BTreeMap::new();
+	for step_report in &report.steps {
+		let step = metrics.steps.entry(step_report.step).or_default();
+		let last_check = step_report.recheck.as_ref().or(step_report.check.as_ref());
+		if let Some(check) = last_check {
+			step.satisfied = matches!(check, CheckReport::Satisfied);
+		}
+		if step_report.set_ran {
+			step.remediations += 1;
+		}
+		if step_report.error.is_some() {
+			step.satisfied = false;
+			step.failures += 1;
+		}
+		*durations.entry(step_report.step).or_default() += step_report.duration_ms;
+	}
+	for (step, duration_ms) in durations {
+		metrics.steps.entry(step).or_default().duration_ms = duration_ms;
+	}
+	metrics.runs += 1;
+	metrics.last_run = report.started_at.as_second();
+	metrics.last_duration_ms = report.duration_ms;
+}
+
+fn family(out: &mut String, name: &str, kind: &str, help: &str) {
+	let _ = writeln!(out, "# HELP {name} {help}");
+	let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+// Label values escape backslash, double quote and newline in the text exposition format.
+fn label(value: &str) -> String {
+	value
+		.replace('\\', "\\\\")
+		.replace('"', "\\\"")
+		.replace('\n', "\\n")
+}
+
+fn seconds(millis: u64) -> f64 {
+	Duration::from_millis(millis).as_secs_f64()
+}
+
+pub fn render() -> String {
+	let metrics = lock();
+	let mut out = String::new();
+	family(
+		&mut out,
+		"eightinary_step_satisfied",
+		"gauge",
+		"Whether the step was satisfied on the last watch pass.",
+	);
+	for (step, step_metrics) in &metrics.steps {
+		let _ = writeln!(
+			out,
+			"eightinary_step_satisfied{{step=\"{}\"}} {}",
+			label(step),
+			u8::from(step_metrics.satisfied)
+		);
+	}
+	family(
+		&mut out,
+		"eightinary_step_duration_seconds",
+		"gauge",
+		"Time spent on the step during the last watch pass.",
+	);
+	for (step, step_metrics) in &metrics.steps {
+		let _ = writeln!(
+			out,
+			"eightinary_step_duration_seconds{{step=\"{}\"}} {}",
+			label(step),
+			seconds(step_metrics.duration_ms)
+		);
+	}
+	family(
+		&mut out,
+		"eightinary_step_failures_total",
+		"counter",
+		"Failed checks and remediations of the step.",
+	);
+	for (step, step_metrics) in &metrics.steps {
+		let _ = writeln!(
+			out,
+			"eightinary_step_failures_total{{step=\"{}\"}} {}",
+			label(step),
+			step_metrics.failures
+		);
+	}
+	family(
+		&mut out,
+		"eightinary_step_remediations_total",
+		"counter",
+		"Times the step was set again after drifting.",
+	);
+	for (step, step_metrics) in &metrics.steps {
+		let _ = writeln!(
+			out,
+			"eightinary_step_remediations_total{{step=\"{}\"}} {}",
+			label(step),
+			step_metrics.remediations
+		);
+	}
+	family(
+		&mut out,
+		"eightinary_watch_runs_total",
+		"counter",
+		"Watch passes since the daemon started.",
+	);
+	let _ = writeln!(out, "eightinary_watch_runs_total {}", metrics.runs);
+	family(
+		&mut out,
+		"eightinary_watch_last_run_timestamp_seconds",
+		"gauge",
+		"Unix time the last watch pass started.",
+	);
+	let _ = writeln!(
+		out,
+		"eightinary_watch_last_run_timestamp_seconds {}",
+		metrics.last_run
+	);
+	family(
+		&mut out,
+		"eightinary_watch_last_run_duration_seconds",
+		"gauge",
+		"Duration of the last watch pass.",
+	);
+	let _ = writeln!(
+		out,
+		"eightinary_watch_last_run_duration_seconds {}",
+		seconds(metrics.last_duration_ms)
+	);
+	out
+}
+
+pub fn serve(addr: SocketAddr) -> Result<(), InstallError> {
+	let listener = TcpListener::bind(addr)?;
+	info!(
+		"Serving metrics on http://{}/metrics.",
+		listener.local_addr()?
+	);
+	thread::Builder::new()
+		.name("metrics".to_owned())
+		.spawn(move || {
+			for stream in listener.incoming() {
+				if let Err(err) = stream.and_then(respond) {
+					warn!("Metrics request failed: {}", err);
+				}
+			}
+		})?;
+	Ok(())
+}
+
+fn respond(mut stream: TcpStream) -> io::Result<()> {
+	stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+	let mut reader = BufReader::new(&stream);
+	let mut request_line = String::new();
+	reader.read_line(&mut request_line)?;
+	// Headers are read and ignored, answering before the request ends resets the connection.
+	loop {
+		let mut header = String::new();
+		if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+			break;
+		}
+	}
+	let mut parts = request_line.split_whitespace();
+	let (status, body) = match (parts.next(), parts.next()) {
+		(Some("GET"), Some("/metrics")) => ("200 OK", render()),
+		(Some("GET"), _) => ("404 Not Found", "Not found.\n".to_owned()),
+		_ => (
+			"405 Method Not Allowed",
+			"Only GET is supported.\n".to_owned(),
+		),
+	};
+	write!(
+		stream,
+		"HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+		body.len()
+	)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::setup::StepReport;
+	use crate::setup::testing;
+
+	fn step(
+		name: &'static str,
+		check: CheckReport,
+		recheck: Option<CheckReport>,
+		duration_ms: u64,
+	) -> StepReport {
+		StepReport {
+			check: Some(check),
+			set_ran: recheck.is_some(),
+			recheck,
+			duration_ms,
+			..StepReport::new(name)
+		}
+	}
+
+	fn drift() -> CheckReport {
+		CheckReport::Drift {
+			reason: "changed".to_owned(),
+		}
+	}
+
+	#[test]
+	fn render_reports_the_observed_pass() {
+		testing::init();
+		let mut report = Report::new("watch");
+		report.steps = vec![
+			step("Sysctl", CheckReport::Satisfied, None, 1500),
+			step("Firewall", drift(), None, 100),
+			step("Kubes", drift(), None, 20),
+			// The remediation of Firewall follows the checks of the pass.
+			step("Firewall", drift(), Some(CheckReport::Satisfied), 400),
+			StepReport::failed("Odd\"Step\\\n", &InstallError::NotReady("x".to_owned())),
+		];
+		report.duration_ms = 2250;
+		observe(&report);
+		let rendered = render();
+		for line in [
+			"# HELP eightinary_step_satisfied Whether the step was satisfied on the last watch pass.",
+			"# TYPE eightinary_step_satisfied gauge",
+			"# TYPE eightinary_step_failures_total counter",
+			"eightinary_step_satisfied{step=\"Sysctl\"} 1",
+			"eightinary_step_satisfied{step=\"Firewall\"} 1",
+			"eightinary_step_satisfied{step=\"Kubes\"} 0",
+			"eightinary_step_satisfied{step=\"Odd\\\"Step\\\\\\n\"} 0",
+			"eightinary_step_duration_seconds{step=\"Sysctl\"} 1.5",
+			"eightinary_step_duration_seconds{step=\"Firewall\"} 0.5",
+			"eightinary_step_failures_total{step=\"Odd\\\"Step\\\\\\n\"} 1",
+			"eightinary_step_failures_total{step=\"Kubes\"} 0",
+			"eightinary_step_remediations_total{step=\"Firewall\"} 1",
+			"eightinary_step_remediations_total{step=\"Sysctl\"} 0",
+			"eightinary_watch_runs_total 1",
+			"eightinary_watch_last_run_duration_seconds 2.25",
+		] {
+			assert!(
+				rendered.lines().any(|rendered| rendered == line),
+				"{line} missing from\n{rendered}"
+			);
+		}
+		// Every sample line belongs to a family announced before it.
+		let mut families = Vec::new();
+		for line in rendered.lines() {
+			match line.strip_prefix("# TYPE ") {
+				Some(family) => families.push(family.split(' ').next().unwrap()),
+				None if !line.starts_with('#') => {
+					let name = line.split(['{', ' ']).next().unwrap();
+					assert_eq!(families.last(), Some(&name));
+				}
+				None => {}
+			}
+		}
+		assert_eq!(families.len(), 7);
+	}
+}
@@ -0,0 +1,212 @@
+use crate::config;
+use crate::error::InstallError;
+use crate::setup::steps::ControlPlane;
+use crate::setup::utils::{inventory::Inventory, kctl};
+use k8s_openapi::api::core::v1::Node;
+use serde::Deserialize;
+use std::path::Path;
+use tracing::info;
+
+pub const KUBE_VIP_MANIFEST: &str = ControlPlane::KUBE_VIP_MANIFEST;
+
+#[derive(Debug, Deserialize)]
+struct MemberList {
+	members: Vec<Member>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Member {
+	#[serde(rename = "ID")]
+	id: u64,
+	name: String,
+	#[serde(rename = "clientURLs", default)]
+	client_urls: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct EndpointHealth {
+	endpoint: String,
+	health: bool,
+}
+
+// Runs on the workstation, the root's admin kubeconfig gives access to the cluster.
+pub fn connect(admin_kubeconfig: String) {
+	kctl::use_admin_kubeconfig(admin_kubeconfig);
+}
+
+// etcdctl ships in the root's etcd static pod, with the kubeadm PKI mounted.
+pub fn etcdctl(root_node: &str, args: &[&str]) -> Vec<String> {
+	[
+		"sudo",
+		"-n",
+		"kubectl",
+		"--kubeconfig",
+		kctl::KUBECONFIG,
+		"--namespace",
+		"kube-system",
+		"exec",
+		&format!("etcd-{root_node}"),
+		"--",
+		"etcdctl",
+		"--endpoints",
+		"https://127.0.0.1:2379",
+		"--cacert",
+		"/etc/kubernetes/pki/etcd/ca.crt",
+		"--cert",
+		"/etc/kubernetes/pki/etcd/server.crt",
+		"--key",
+		"/etc/kubernetes/pki/etcd/server.key",
+	]
+	.into_iter()
+	.chain(args.iter().copied())
+	.map(str::to_owned)
+	.collect()
+}
+
+// The member id of the node, in the hex etcdctl expects, or None when it has no member.
+// Fails when the members left behind could not form a quorum.
+pub fn etcd_member(
+	members_json: &str,
+	health_json: &str,
+	node: &str,
+) -> Result<Option<String>, InstallError> {
+	let members: MemberList = serde_json::from_str(members_json)
+		.map_err(|err| InstallError::Kube(format!("Unreadable etcd member list: {err}")))?;
+	let health: Vec<EndpointHealth> = serde_json::from_str(health_json)
+		.map_err(|err| InstallError::Kube(format!("Unreadable etcd endpoint health: {err}")))?;
+	let Some(target) = members.members.iter().find(|member| member.name == node) else {
+		info!("Node {} has no etcd member.", node);
+		return Ok(None);
+	};
+	let remaining = members.members.len() - 1;
+	let healthy = members
+		.members
+		.iter()
+		.filter(|member| member.id != target.id)
+		.filter(|member| {
+			member.client_urls.iter().any(|url| {
+				health
+					.iter()
+					.any(|endpoint| endpoint.health && endpoint.endpoint == *url)
+			})
+		})
+		.count();
+	let quorum = remaining / 2 + 1;
+	if remaining == 0 || healthy < quorum {
+		return Err(InstallError::Quorum(format!(
+			"removing {node} leaves {healthy} healthy of {remaining} etcd members, {quorum} are needed"
+		)));
+	}
+	info!(
+		"Removing {} leaves {} healthy of {} etcd members.",
+		node, healthy, remaining
+	);
+	Ok(Some(format!("{:x}", target.id)))
+}
+
+pub fn delete_node(node: &str) -> Result<(), InstallError> {
+	info!("Deleting node {}.", node);
+	kctl::delete(&kctl::cluster::<Node>()?, node)
+}
+
+// Once the node's kube-vip is gone another control plane must hold the address.
+pub fn wait_api() -> Result<(), InstallError> {
+	kctl::wait_apiserver_healthy(
+		&config::get().kube_vip.endpoint(),
+		ControlPlane::API_HEALTHY,
+	)
+}
+
+pub fn remove_from_inventory(path: &Path, machine_id: &str) -> Result<(), InstallError> {
+	Inventory::remove_machine(path, machine_id)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// `etcdctl member list --write-out json` for members cp-1 to cp-<count>.
+	fn members(count: u64) -> String {
+		let members = (1..=count)
+			.map(|n| {
+				format!(
+					r#"{{"ID":{},"name":"cp-{n}","peerURLs":["https://10.0.0.{n}:2380"],"clientURLs":["https://10.0.0.{n}:2379"]}}"#,
+					0x8e9e05c52164694d_u64 + n
+				)
+			})
+			.collect::<Vec<_>>()
+			.join(",");
+		format!(
+			r#"{{"header":{{"cluster_id":14841639068965178418,"member_id":10276657743932975437,"raft_term":2}},"members":[{members}]}}"#
+		)
+	}
+
+	// `etcdctl endpoint health --cluster --write-out json`, one entry per member.
+	fn health(healthy: &[bool]) -> String {
+		let endpoints = healthy
+			.iter()
+			.zip(1..)
+			.map(|(health, n)| {
+				format!(
+					r#"{{"endpoint":"https://10.0.0.{n}:2379","health":{health},"took":"1.2ms"}}"#
+				)
+			})
+			.collect::<Vec<_>>()
+			.join(",");
+		format!("[{endpoints}]")
+	}
+
+	fn remove(count: u64, healthy: &[bool], node: &str) -> Result<Option<String>, InstallError> {
+		etcd_member(&members(count), &health(healthy), node)
+	}
+
+	#[test]
+	fn three_to_two_needs_both_others_healthy() {
+		assert_eq!(
+			remove(3, &[true, true, true], "cp-3").unwrap(),
+			Some("8e9e05c521646950".to_owned())
+		);
+		// The member being removed may already be down.
+		assert!(remove(3, &[true, true, false], "cp-3").is_ok());
+		let Err(InstallError::Quorum(message)) = remove(3, &[true, false, true], "cp-3") else {
+			panic!("removal below quorum was allowed");
+		};
+		assert_eq!(
+			message,
+			"removing cp-3 leaves 1 healthy of 2 etcd members, 2 are needed"
+		);
+	}
+
+	#[test]
+	fn two_to_one_needs_the_other_healthy() {
+		assert_eq!(
+			remove(2, &[true, false], "cp-2").unwrap(),
+			Some("8e9e05c52164694f".to_owned())
+		);
+		assert!(matches!(
+			remove(2, &[false, true], "cp-2"),
+			Err(InstallError::Quorum(_))
+		));
+	}
+
+	#[test]
+	fn last_member_is_never_removed() {
+		assert!(matches!(
+			remove(1, &[true], "cp-1"),
+			Err(InstallError::Quorum(_))
+		));
+	}
+
+	#[test]
+	fn node_without_member_is_none() {
+		assert_eq!(remove(3, &[true, true, true], "worker-1").unwrap(), None);
+	}
+
+	#[test]
+	fn unreadable_output_is_an_error() {
+		assert!(matches!(
+			etcd_member("Error: context deadline exceeded", &health(&[true]), "cp-1"),
+			Err(InstallError::Kube(_))
+		));
+	}
+}
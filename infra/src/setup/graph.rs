@@ -0,0 +1,154 @@
+use crate::error::InstallError;
+use crate::setup::SetupStep;
+
+pub fn order(
+	steps: &[&'static dyn SetupStep],
+) -> Result<Vec<&'static dyn SetupStep>, InstallError> {
+	for step in steps {
+		for prerequisite in step.requires() {
+			if !steps.iter().any(|other| other.name() == *prerequisite) {
+				return Err(InstallError::Config(format!(
+					"step '{}' requires unknown step '{}'",
+					step.name(),
+					prerequisite
+				)));
+			}
+		}
+	}
+	let mut ordered: Vec<&'static dyn SetupStep> = Vec::with_capacity(steps.len());
+	let mut pending = steps.to_vec();
+	while !pending.is_empty() {
+		let (ready, waiting): (Vec<&'static dyn SetupStep>, Vec<_>) =
+			pending.into_iter().partition(|step| {
+				step.requires()
+					.iter()
+					.all(|prerequisite| ordered.iter().any(|done| done.name() == *prerequisite))
+			});
+		if ready.is_empty() {
+			let names = waiting
+				.iter()
+				.map(|step| step.name())
+				.collect::<Vec<_>>()
+				.join(", ");
+			return Err(InstallError::Config(format!(
+				"dependency cycle between steps: {names}"
+			)));
+		}
+		ordered.extend(ready);
+		pending = waiting;
+	}
+	Ok(ordered)
+}
+
+pub fn is_ready(step: &dyn SetupStep, pending: &[&'static dyn SetupStep]) -> bool {
+	!step
+		.requires()
+		.iter()
+		.any(|prerequisite| pending.iter().any(|other| other.name() == *prerequisite))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::setup::{Check, SETUP_STEPS};
+
+	struct Node {
+		name: &'static str,
+		requires: &'static [&'static str],
+	}
+
+	impl SetupStep for Node {
+		fn name(&self) -> &'static str {
+			self.name
+		}
+
+		fn requires(&self) -> &'static [&'static str] {
+			self.requires
+		}
+
+		fn check(&self) -> Result<Check, InstallError> {
+			Ok(Check::Satisfied)
+		}
+
+		fn set(&self) -> Result<(), InstallError> {
+			Ok(())
+		}
+	}
+
+	static A: Node = Node {
+		name: "A",
+		requires: &[],
+	};
+	static B: Node = Node {
+		name: "B",
+		requires: &["A"],
+	};
+	static C: Node = Node {
+		name: "C",
+		requires: &[],
+	};
+	static D: Node = Node {
+		name: "D",
+		requires: &["B", "C"],
+	};
+
+	fn names(steps: &[&'static dyn SetupStep]) -> Vec<&'static str> {
+		steps.iter().map(|step| step.name()).collect()
+	}
+
+	#[test]
+	fn prerequisites_come_first_in_declared_order() {
+		let ordered = order(&[&D, &B, &C, &A]).unwrap();
+		assert_eq!(names(&ordered), ["C", "A", "B", "D"]);
+		// Ties keep the declared order, the same input always gives the same order.
+		assert_eq!(
+			names(&order(&[&A, &C, &B, &D]).unwrap()),
+			["A", "C", "B", "D"]
+		);
+		assert_eq!(names(&order(&[&D, &B, &C, &A]).unwrap()), names(&ordered));
+	}
+
+	#[test]
+	fn unknown_prerequisite_is_refused() {
+		let Err(InstallError::Config(message)) = order(&[&B, &C]) else {
+			panic!("an unknown prerequisite was accepted");
+		};
+		assert_eq!(message, "step 'B' requires unknown step 'A'");
+	}
+
+	#[test]
+	fn cycle_is_refused() {
+		static X: Node = Node {
+			name: "X",
+			requires: &["Y"],
+		};
+		static Y: Node = Node {
+			name: "Y",
+			requires: &["X"],
+		};
+		let Err(InstallError::Config(message)) = order(&[&A, &X, &Y, &C]) else {
+			panic!("a cycle was accepted");
+		};
+		assert_eq!(message, "dependency cycle between steps: X, Y");
+	}
+
+	#[test]
+	fn ready_once_no_prerequisite_is_pending() {
+		let pending: &[&'static dyn SetupStep] = &[&A, &B, &C, &D];
+		assert!(is_ready(&A, pending));
+		assert!(!is_ready(&B, pending));
+		assert!(!is_ready(&D, pending));
+		assert!(is_ready(&B, &[&B, &C, &D]));
+		assert!(!is_ready(&D, &[&C, &D]));
+		assert!(is_ready(&D, &[&D]));
+	}
+
+	#[test]
+	fn setup_steps_order() {
+		let ordered = names(&order(SETUP_STEPS).unwrap());
+		let position = |name| ordered.iter().position(|step| *step == name).unwrap();
+		assert!(position("KernelModules") < position("Sysctl"));
+		assert!(position("ControlPlane") < position("Certificates"));
+		assert_eq!(ordered.len(), SETUP_STEPS.len());
+	}
+}
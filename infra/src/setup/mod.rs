@@ -1,17 +1,51 @@
+pub mod decommission;
+mod graph;
+mod report;
 mod steps;
+#[cfg(test)]
+pub mod testing;
+pub mod upgrade;
 mod utils;
 
 use crate::error::InstallError;
+pub use crate::setup::report::{CheckReport, RUNS_DIR, Report, StepReport};
 use crate::setup::steps::{
-	Containerd, ControlPlane, DisableSwap, Firewall, Helm, IdentityDatabase, Istio, KernelModules,
-	Kubes, Sysctl,
+	Certificates, Containerd, ControlPlane, DisableSwap, EtcdBackup, Firewall, Helm,
+	IdentityDatabase, Istio, KernelModules, Kubes, Sysctl, Worker,
 };
-use tracing::info;
+use crate::setup::utils::ledger::{self, Action};
+use std::{collections::HashSet, path::Path, thread};
+use tracing::{error, info, warn};
 
-pub trait SetupStep {
+#[derive(Debug, Clone, PartialEq)]
+pub enum Check {
+	Satisfied,
+	Drift(String),
+}
+
+impl Check {
+	pub fn drift(reason: impl Into<String>) -> Check {
+		Check::Drift(reason.into())
+	}
+
+	pub fn is_satisfied(&self) -> bool {
+		matches!(self, Check::Satisfied)
+	}
+}
+
+pub trait SetupStep: Sync {
 	fn name(&self) -> &'static str;
-	fn check(&self) -> Result<bool, InstallError>;
+	fn requires(&self) -> &'static [&'static str] {
+		&[]
+	}
+	fn check(&self) -> Result<Check, InstallError>;
 	fn set(&self) -> Result<(), InstallError>;
+	fn unset(&self) -> Result<(), InstallError> {
+		Ok(())
+	}
+	fn versions(&self) -> Vec<(&'static str, String)> {
+		Vec::new()
+	}
 }
 
 const SETUP_STEPS: &[&dyn SetupStep] = &[
@@ -23,23 +57,491 @@ const SETUP_STEPS: &[&dyn SetupStep] = &[
 	&Helm,
 	&Firewall,
 	&ControlPlane,
+	&Worker,
+	&EtcdBackup,
+	&Certificates,
 	&Istio,
 	&IdentityDatabase,
 ];
 
-pub fn setup() -> Result<(), InstallError> {
-	for step in SETUP_STEPS {
-		let step_name = step.name();
-		info!("Checking step: {}.", step_name);
-		if !step.check()? {
-			info!("Applying step: {}.", step_name);
-			step.set()?;
-			if !step.check()? {
-				return Err(InstallError::StepFailed { step: step_name });
+#[derive(Debug, Default)]
+pub struct StepFilter {
+	pub only: Vec<String>,
+	pub skip: Vec<String>,
+	pub from: Option<String>,
+}
+
+impl StepFilter {
+	fn select(&self) -> Result<Vec<&'static dyn SetupStep>, InstallError> {
+		let names = self
+			.only
+			.iter()
+			.chain(self.skip.iter())
+			.chain(self.from.iter());
+		for name in names {
+			if !SETUP_STEPS.iter().any(|step| step.name() == name) {
+				return Err(InstallError::Config(format!("unknown step '{name}'")));
+			}
+		}
+		let ordered = graph::order(SETUP_STEPS)?;
+		let start = match &self.from {
+			Some(from) => ordered
+				.iter()
+				.position(|step| step.name() == from)
+				.unwrap_or_default(),
+			None => 0,
+		};
+		let selected = ordered[start..]
+			.iter()
+			.copied()
+			.filter(|step| self.only.is_empty() || self.only.iter().any(|name| name == step.name()))
+			.filter(|step| !self.skip.iter().any(|name| name == step.name()))
+			.collect();
+		Ok(selected)
+	}
+}
+
+#[derive(Debug)]
+pub struct PlanEntry {
+	pub step: &'static str,
+	pub outcome: Result<Check, InstallError>,
+}
+
+#[derive(Debug)]
+pub struct Plan {
+	pub entries: Vec<PlanEntry>,
+}
+
+impl Plan {
+	pub fn has_errors(&self) -> bool {
+		self.entries.iter().any(|entry| entry.outcome.is_err())
+	}
+
+	pub fn has_drift(&self) -> bool {
+		self.entries
+			.iter()
+			.any(|entry| matches!(entry.outcome, Ok(Check::Drift(_))))
+	}
+
+	pub fn print(&self) {
+		for entry in &self.entries {
+			match &entry.outcome {
+				Ok(Check::Satisfied) => println!("  ok      {}", entry.step),
+				Ok(Check::Drift(reason)) => println!("  change  {}: {}", entry.step, reason),
+				Err(err) => println!("  error   {}: {}", entry.step, err),
 			}
-		} else {
-			info!("Step already satisfied: {}.", step_name);
+		}
+		let changes = self
+			.entries
+			.iter()
+			.filter(|entry| !entry.outcome.as_ref().is_ok_and(Check::is_satisfied))
+			.count();
+		println!(
+			"Plan: {} of {} steps would change.",
+			changes,
+			self.entries.len()
+		);
+	}
+
+	pub fn print_drift(&self) {
+		for entry in &self.entries {
+			match &entry.outcome {
+				Ok(Check::Satisfied) => {}
+				Ok(Check::Drift(reason)) => println!("drift  {}: {}", entry.step, reason),
+				Err(err) => println!("error  {}: {}", entry.step, err),
+			}
+		}
+	}
+}
+
+pub fn step_names() -> Result<Vec<(&'static str, &'static [&'static str])>, InstallError> {
+	Ok(graph::order(SETUP_STEPS)?
+		.iter()
+		.map(|step| (step.name(), step.requires()))
+		.collect())
+}
+
+pub fn validate(filter: &StepFilter) -> Result<(), InstallError> {
+	filter.select().map(|_| ())
+}
+
+fn apply(step: &dyn SetupStep, report: &mut StepReport) -> Result<(), InstallError> {
+	let step_name = step.name();
+	info!("Checking step: {}.", step_name);
+	let check = step.check();
+	report.check = Some(CheckReport::from(&check));
+	if let Check::Drift(reason) = check? {
+		info!("Applying step: {}, {}", step_name, reason);
+		report.set_ran = true;
+		step.set()?;
+		ledger::record(step_name, Action::Applied, step.versions())?;
+		let recheck = step.check();
+		report.recheck = Some(CheckReport::from(&recheck));
+		if let Check::Drift(reason) = recheck? {
+			warn!("Step still drifted after set: {}, {}", step_name, reason);
+			return Err(InstallError::StepFailed { step: step_name });
+		}
+	} else {
+		info!("Step already satisfied: {}.", step_name);
+		if ledger::applied(step_name).is_none() {
+			ledger::record(step_name, Action::Adopted, step.versions())?;
 		}
 	}
 	Ok(())
 }
+
+pub fn setup(filter: &StepFilter, report: &mut Report) -> Result<(), InstallError> {
+	run_steps(filter.select()?, report)
+}
+
+// Steps whose prerequisites are done run concurrently, a failure skips everything that needs it.
+fn run_steps(
+	mut pending: Vec<&'static dyn SetupStep>,
+	report: &mut Report,
+) -> Result<(), InstallError> {
+	let mut failed = HashSet::new();
+	let mut first_err = None;
+	while !pending.is_empty() {
+		let (ready, waiting): (Vec<&'static dyn SetupStep>, Vec<_>) = pending
+			.iter()
+			.partition(|step| graph::is_ready(**step, &pending));
+		let (blocked, runnable): (Vec<&'static dyn SetupStep>, Vec<_>) =
+			ready.into_iter().partition(|step| {
+				step.requires()
+					.iter()
+					.any(|prerequisite| failed.contains(prerequisite))
+			});
+		for step in blocked {
+			warn!("Skipping step: {}, a prerequisite failed.", step.name());
+			failed.insert(step.name());
+			let mut step_report = StepReport::new(step.name());
+			step_report.error = Some("a prerequisite failed".to_owned());
+			report.steps.push(step_report);
+		}
+		let results = thread::scope(|scope| {
+			let handles = runnable
+				.iter()
+				.map(|step| {
+					thread::Builder::new()
+						.name(step.name().to_owned())
+						.spawn_scoped(scope, || {
+							StepReport::run(step.name(), |step_report| apply(*step, step_report))
+						})
+				})
+				.collect::<Vec<_>>();
+			runnable
+				.iter()
+				.zip(handles)
+				.map(|(step, handle)| {
+					let joined = handle.map_err(InstallError::from).and_then(|handle| {
+						handle.join().map_err(|_| {
+							InstallError::Other(format!("step '{}' panicked", step.name()).into())
+						})
+					});
+					match joined {
+						Ok((step_report, result)) => (step.name(), step_report, result),
+						Err(err) => (step.name(), StepReport::failed(step.name(), &err), Err(err)),
+					}
+				})
+				.collect::<Vec<_>>()
+		});
+		for (step_name, step_report, result) in results {
+			report.steps.push(step_report);
+			if let Err(err) = result {
+				error!("Step failed: {}, {}", step_name, err);
+				failed.insert(step_name);
+				first_err.get_or_insert(err);
+			}
+		}
+		pending = waiting;
+	}
+	first_err.map_or(Ok(()), Err)
+}
+
+pub fn plan(filter: &StepFilter, report: &mut Report) -> Result<Plan, InstallError> {
+	let entries = filter
+		.select()?
+		.into_iter()
+		.map(|step| {
+			info!("Checking step: {}.", step.name());
+			let (step_report, outcome) = StepReport::run(step.name(), |step_report| {
+				let outcome = step.check();
+				step_report.check = Some(CheckReport::from(&outcome));
+				outcome
+			});
+			report.steps.push(step_report);
+			PlanEntry {
+				step: step.name(),
+				outcome,
+			}
+		})
+		.collect();
+	Ok(Plan { entries })
+}
+
+pub fn reset(filter: &StepFilter, report: &mut Report) -> Result<(), InstallError> {
+	let mut first_err = None;
+	for step in filter.select()?.into_iter().rev() {
+		info!("Tearing down step: {}.", step.name());
+		let (step_report, result) = StepReport::run(step.name(), |step_report| {
+			step_report.unset_ran = true;
+			step.unset()?;
+			ledger::record(step.name(), Action::Reset, Vec::new())
+		});
+		report.steps.push(step_report);
+		if let Err(err) = result {
+			error!("Teardown failed: {}, {}", step.name(), err);
+			first_err.get_or_insert(err);
+		}
+	}
+	first_err.map_or(Ok(()), Err)
+}
+
+// One snapshot outside of any step, the backup timer runs this.
+pub fn backup(dir: &Path, retention: usize, report: &mut Report) -> Result<(), InstallError> {
+	let (step_report, result) = StepReport::run(EtcdBackup.name(), |_| {
+		steps::etcd_backup::snapshot(dir, retention).map(|_| ())
+	});
+	report.steps.push(step_report);
+	result
+}
+
+// Rebuilds the root from an etcd snapshot, with a data dir only the snapshot is restored there.
+pub fn restore(
+	snapshot: &Path,
+	pki: &Path,
+	data_dir: Option<&Path>,
+	report: &mut Report,
+) -> Result<(), InstallError> {
+	let (step_report, result) = StepReport::run("Restore", |_| match data_dir {
+		Some(data_dir) => {
+			steps::etcd_backup::ensure_tools()?;
+			steps::etcd_backup::restore_data(snapshot, data_dir)
+		}
+		None => steps::control_plane::restore_root(snapshot, pki),
+	});
+	report.steps.push(step_report);
+	result
+}
+
+pub use utils::cmd::Cmd;
+pub use utils::inventory::{
+	DEFAULT_PATH as INVENTORY_PATH, EtcdMembers, Inventory, Machine, MachineRole,
+};
+pub use utils::ledger::{DEFAULT_PATH as LEDGER_PATH, Event};
+pub use utils::retry::Retry;
+pub use utils::systemd::{BINARY_PATH, UNIT_DIR, quote as systemd_quote};
+
+pub fn inventory() -> &'static Inventory {
+	utils::inventory::get()
+}
+
+pub fn init_inventory(path: Option<&Path>, etcd_members: EtcdMembers) -> Result<(), InstallError> {
+	utils::inventory::init(path, etcd_members)
+}
+
+pub fn machine() -> Result<&'static Machine, InstallError> {
+	utils::inventory::this()
+}
+
+pub fn init_ledger(path: Option<&Path>) -> Result<(), InstallError> {
+	utils::ledger::init(path)
+}
+
+pub fn history() -> Vec<Event> {
+	utils::ledger::history()
+}
+
+pub fn init_script(path: &Path) -> Result<(), InstallError> {
+	utils::cmd::init_script(path)
+}
+
+pub fn scripted_commands() -> Option<Vec<String>> {
+	utils::cmd::scripted().map(|runner| runner.recorded())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::setup::testing;
+
+	// Probes `probe <name>`, satisfied when it prints "ok", and fixes with `fix <name>`.
+	struct Fake {
+		name: &'static str,
+		requires: &'static [&'static str],
+	}
+
+	impl SetupStep for Fake {
+		fn name(&self) -> &'static str {
+			self.name
+		}
+
+		fn requires(&self) -> &'static [&'static str] {
+			self.requires
+		}
+
+		fn check(&self) -> Result<Check, InstallError> {
+			let output = Cmd::new("probe").arg(self.name).output()?;
+			if output.stdout.trim() == "ok" {
+				Ok(Check::Satisfied)
+			} else {
+				Ok(Check::drift(output.stdout.trim()))
+			}
+		}
+
+		fn set(&self) -> Result<(), InstallError> {
+			Cmd::new("fix").arg(self.name).run()
+		}
+	}
+
+	fn report() -> Report {
+		testing::init();
+		Report::new("setup")
+	}
+
+	#[test]
+	fn drifted_step_is_set_and_rechecked() {
+		static STEP: Fake = Fake {
+			name: "Drifted",
+			requires: &[],
+		};
+		let mut report = report();
+		let runner = testing::script(
+			r#"
+				[[command]]
+				match = "probe Drifted"
+				stdout = "missing"
+				[[command]]
+				match = "fix Drifted"
+				[[command]]
+				match = "probe Drifted"
+				stdout = "ok"
+			"#,
+		);
+		run_steps(vec![&STEP], &mut report).unwrap();
+		assert_eq!(
+			runner.recorded(),
+			["probe Drifted", "fix Drifted", "probe Drifted"]
+		);
+		let step = &report.steps[0];
+		assert!(matches!(&step.check, Some(CheckReport::Drift { reason }) if reason == "missing"));
+		assert!(step.set_ran);
+		assert!(matches!(step.recheck, Some(CheckReport::Satisfied)));
+		assert_eq!(step.commands.len(), 3);
+		assert!(step.error.is_none());
+		assert!(ledger::applied("Drifted").is_some());
+	}
+
+	#[test]
+	fn satisfied_step_is_adopted_without_set() {
+		static STEP: Fake = Fake {
+			name: "Adopted",
+			requires: &[],
+		};
+		let mut report = report();
+		let runner = testing::script(
+			r#"
+				[[command]]
+				match = "probe Adopted"
+				stdout = "ok"
+			"#,
+		);
+		run_steps(vec![&STEP], &mut report).unwrap();
+		assert_eq!(runner.recorded(), ["probe Adopted"]);
+		assert!(!report.steps[0].set_ran);
+		assert!(report.steps[0].recheck.is_none());
+		assert!(
+			ledger::history()
+				.iter()
+				.any(|event| event.step == "Adopted" && event.action == Action::Adopted)
+		);
+	}
+
+	#[test]
+	fn step_still_drifted_after_set_fails() {
+		static STEP: Fake = Fake {
+			name: "Stuck",
+			requires: &[],
+		};
+		let mut report = report();
+		let _runner = testing::script(
+			r#"
+				[[command]]
+				match = "probe Stuck"
+				stdout = "missing"
+				repeat = true
+				[[command]]
+				match = "fix Stuck"
+			"#,
+		);
+		let err = run_steps(vec![&STEP], &mut report).unwrap_err();
+		assert!(matches!(err, InstallError::StepFailed { step: "Stuck" }));
+		assert!(matches!(
+			&report.steps[0].recheck,
+			Some(CheckReport::Drift { .. })
+		));
+		assert!(report.steps[0].error.is_some());
+	}
+
+	#[test]
+	fn failed_prerequisite_skips_dependents_only() {
+		static BROKEN: Fake = Fake {
+			name: "Broken",
+			requires: &[],
+		};
+		static DEPENDENT: Fake = Fake {
+			name: "Dependent",
+			requires: &["Broken"],
+		};
+		static TRANSITIVE: Fake = Fake {
+			name: "Transitive",
+			requires: &["Dependent"],
+		};
+		static INDEPENDENT: Fake = Fake {
+			name: "Independent",
+			requires: &[],
+		};
+		let mut report = report();
+		let runner = testing::script(
+			r#"
+				[[command]]
+				match = "probe Broken"
+				stdout = "missing"
+				[[command]]
+				match = "fix Broken"
+				status = 1
+				stderr = "no space left on device"
+				[[command]]
+				match = "probe Independent"
+				stdout = "ok"
+			"#,
+		);
+		let err = run_steps(
+			vec![&BROKEN, &INDEPENDENT, &DEPENDENT, &TRANSITIVE],
+			&mut report,
+		)
+		.unwrap_err();
+		assert!(matches!(err, InstallError::CommandFailed { .. }));
+		let recorded = runner.recorded();
+		assert!(recorded.contains(&"probe Independent".to_owned()));
+		assert!(!recorded.iter().any(|cmd| cmd.contains("Dependent")));
+		assert!(!recorded.iter().any(|cmd| cmd.contains("Transitive")));
+		let error = |name: &str| {
+			report
+				.steps
+				.iter()
+				.find(|step| step.step == name)
+				.unwrap()
+				.error
+				.clone()
+		};
+		assert!(error("Broken").is_some());
+		assert_eq!(error("Dependent").as_deref(), Some("a prerequisite failed"));
+		assert_eq!(
+			error("Transitive").as_deref(),
+			Some("a prerequisite failed")
+		);
+		assert_eq!(error("Independent"), None);
+		assert!(ledger::applied("Dependent").is_none());
+	}
+}
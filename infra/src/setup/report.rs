@@ -0,0 +1,144 @@
+use crate::context;
+use crate::error::InstallError;
+use crate::setup::Check;
+use crate::setup::utils::cmd::{self, CommandRecord};
+use jiff::Timestamp;
+use serde::Serialize;
+use std::{
+	fs::{self, OpenOptions},
+	io::Write,
+	path::{Path, PathBuf},
+	process,
+	time::{Duration, Instant},
+};
+use tracing::info;
+
+pub const RUNS_DIR: &str = "/var/lib/8inary/runs";
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum CheckReport {
+	Satisfied,
+	Drift { reason: String },
+	Error,
+}
+
+impl From<&Result<Check, InstallError>> for CheckReport {
+	fn from(outcome: &Result<Check, InstallError>) -> Self {
+		match outcome {
+			Ok(Check::Satisfied) => CheckReport::Satisfied,
+			Ok(Check::Drift(reason)) => CheckReport::Drift {
+				reason: reason.clone(),
+			},
+			Err(_) => CheckReport::Error,
+		}
+	}
+}
+
+#[derive(Debug, Serialize)]
+pub struct StepReport {
+	pub step: &'static str,
+	pub check: Option<CheckReport>,
+	pub set_ran: bool,
+	pub recheck: Option<CheckReport>,
+	#[serde(skip_serializing_if = "std::ops::Not::not")]
+	pub unset_ran: bool,
+	pub duration_ms: u64,
+	pub commands: Vec<CommandRecord>,
+	pub error: Option<String>,
+}
+
+impl StepReport {
+	pub fn new(step: &'static str) -> StepReport {
+		StepReport {
+			step,
+			check: None,
+			set_ran: false,
+			recheck: None,
+			unset_ran: false,
+			duration_ms: 0,
+			commands: Vec::new(),
+			error: None,
+		}
+	}
+
+	pub fn failed(step: &'static str, err: &InstallError) -> StepReport {
+		StepReport {
+			error: Some(err.to_string()),
+			..StepReport::new(step)
+		}
+	}
+
+	// Times `body` and collects the commands it ran on this thread.
+	pub fn run<T>(
+		step: &'static str,
+		body: impl FnOnce(&mut StepReport) -> Result<T, InstallError>,
+	) -> (StepReport, Result<T, InstallError>) {
+		let started = Instant::now();
+		let mut report = StepReport::new(step);
+		let (result, commands) = cmd::record(|| body(&mut report));
+		report.duration_ms = millis(started.elapsed());
+		report.commands = commands;
+		report.error = result.as_ref().err().map(ToString::to_string);
+		(report, result)
+	}
+}
+
+#[derive(Debug, Serialize)]
+pub struct Report {
+	pub command: &'static str,
+	pub host: String,
+	pub machine_id: String,
+	pub started_at: Timestamp,
+	pub duration_ms: u64,
+	pub exit_code: i32,
+	pub steps: Vec<StepReport>,
+	#[serde(skip)]
+	started: Instant,
+}
+
+impl Report {
+	pub fn new(command: &'static str) -> Report {
+		let context = context::get();
+		Report {
+			command,
+			host: context.hostname.clone(),
+			machine_id: context.machine_id.clone(),
+			started_at: Timestamp::now(),
+			duration_ms: 0,
+			exit_code: 0,
+			steps: Vec::new(),
+			started: Instant::now(),
+		}
+	}
+
+	pub fn finish(&mut self, exit_code: i32) {
+		self.exit_code = exit_code;
+		self.duration_ms = millis(self.started.elapsed());
+	}
+
+	pub fn to_json(&self) -> Result<String, InstallError> {
+		serde_json::to_string_pretty(self).map_err(|err| InstallError::Other(err.into()))
+	}
+
+	// Runs from the watch timer and a shell can start in the same instant, the pid and create_new keep them apart.
+	pub fn write(&self, dir: &Path) -> Result<PathBuf, InstallError> {
+		fs::create_dir_all(dir)?;
+		let path = dir.join(format!(
+			"{}-{}.json",
+			self.started_at.strftime("%Y%m%dT%H%M%S%.6fZ"),
+			process::id()
+		));
+		let mut file = OpenOptions::new()
+			.write(true)
+			.create_new(true)
+			.open(&path)?;
+		file.write_all((self.to_json()? + "\n").as_bytes())?;
+		info!("Run report written to {}.", path.display());
+		Ok(path)
+	}
+}
+
+fn millis(duration: Duration) -> u64 {
+	duration.as_millis().try_into().unwrap_or(u64::MAX)
+}
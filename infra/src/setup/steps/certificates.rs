@@ -0,0 +1,394 @@
+use crate::config;
+use crate::context;
+use crate::error::InstallError;
+use crate::setup::steps::control_plane::{self, ControlPlane};
+use crate::setup::utils::{
+	cmd::Cmd,
+	inventory::{self, MachineRole},
+	kctl,
+	retry::Retry,
+};
+use crate::setup::{Check, SetupStep};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use jiff::{SignedDuration, Timestamp};
+use kube::config::Kubeconfig;
+use std::{
+	fs,
+	path::{Path, PathBuf},
+	time::Duration,
+};
+use tracing::{info, warn};
+use x509_parser::pem::Pem;
+
+pub struct Certificates;
+
+struct Expiry {
+	source: String,
+	not_after: Timestamp,
+}
+
+impl Certificates {
+	pub const MANIFEST_DIR: &str = "/etc/kubernetes/manifests";
+	// The kubelet reads every file in MANIFEST_DIR, a parked manifest must live outside it.
+	pub const PARKED_DIR: &str = "/etc/kubernetes";
+	pub const CRI_ENDPOINT: &str = "unix:///run/containerd/containerd.sock";
+	// etcd first, the API server then starts against an etcd serving the renewed certificates.
+	pub const STATIC_PODS: &[&str] = &[
+		"etcd",
+		"kube-apiserver",
+		"kube-controller-manager",
+		"kube-scheduler",
+	];
+	// kubeadm renews these, the kubelet rotates its own client certificate.
+	pub const KUBECONFIGS: &[&str] = &[
+		"/etc/kubernetes/admin.conf",
+		"/etc/kubernetes/super-admin.conf",
+		"/etc/kubernetes/controller-manager.conf",
+		"/etc/kubernetes/scheduler.conf",
+		"/root/.kube/config",
+	];
+	// kubeadm certs renew leaves the CAs alone, they are only reported.
+	pub const CA_CERTS: &[&str] = &["ca.crt", "front-proxy-ca.crt", "etcd/ca.crt"];
+	pub const POD_RESTART: Retry = Retry::within(Duration::from_secs(180))
+		.backoff(Duration::from_secs(2), Duration::from_secs(10));
+
+	fn kubeconfigs() -> Vec<PathBuf> {
+		let mut paths = Certificates::KUBECONFIGS
+			.iter()
+			.map(PathBuf::from)
+			.collect::<Vec<_>>();
+		let user_config = Path::new(&context::get().home).join(".kube/config");
+		if !paths.contains(&user_config) {
+			paths.push(user_config);
+		}
+		paths
+	}
+}
+
+fn not_after(pem: &[u8], source: &str) -> Result<Timestamp, InstallError> {
+	let invalid =
+		|err: String| InstallError::Kube(format!("Unreadable certificate in {source}: {err}"));
+	let pem = Pem::iter_from_buffer(pem)
+		.next()
+		.ok_or_else(|| invalid("no PEM block".to_owned()))?
+		.map_err(|err| invalid(err.to_string()))?;
+	let cert = pem.parse_x509().map_err(|err| invalid(err.to_string()))?;
+	Timestamp::from_second(cert.validity().not_after.timestamp())
+		.map_err(|err| invalid(err.to_string()))
+}
+
+fn pki_certs(dir: &Path, expiries: &mut Vec<Expiry>) -> Result<(), InstallError> {
+	for entry in fs::read_dir(dir)? {
+		let path = entry?.path();
+		if path.is_dir() {
+			pki_certs(&path, expiries)?;
+		} else if path.extension().is_some_and(|ext| ext == "crt") {
+			let source = path.display().to_string();
+			let not_after = not_after(&fs::read(&path)?, &source)?;
+			expiries.push(Expiry { source, not_after });
+		}
+	}
+	Ok(())
+}
+
+// Client certificates are embedded in the kubeconfig or referenced by path.
+fn kubeconfig_certs(path: &Path, expiries: &mut Vec<Expiry>) -> Result<(), InstallError> {
+	let kubeconfig = Kubeconfig::read_from(path)?;
+	for named in &kubeconfig.auth_infos {
+		let Some(auth_info) = &named.auth_info else {
+			continue;
+		};
+		let source = format!("{} user {}", path.display(), named.name);
+		let pem = if let Some(data) = &auth_info.client_certificate_data {
+			STANDARD.decode(data.trim()).map_err(|err| {
+				InstallError::Kube(format!("Unreadable certificate in {source}: {err}"))
+			})?
+		} else if let Some(cert_path) = &auth_info.client_certificate {
+			fs::read(cert_path)?
+		} else {
+			continue;
+		};
+		let not_after = not_after(&pem, &source)?;
+		expiries.push(Expiry { source, not_after });
+	}
+	Ok(())
+}
+
+fn expiries(pki: &Path, kubeconfigs: &[PathBuf]) -> Result<Vec<Expiry>, InstallError> {
+	let mut expiries = Vec::new();
+	pki_certs(pki, &mut expiries)?;
+	for path in kubeconfigs {
+		if path.exists() {
+			kubeconfig_certs(path, &mut expiries)?;
+		}
+	}
+	expiries.sort_by_key(|expiry| expiry.not_after);
+	Ok(expiries)
+}
+
+fn is_ca(pki: &Path, expiry: &Expiry) -> bool {
+	Certificates::CA_CERTS
+		.iter()
+		.any(|name| expiry.source == pki.join(name).display().to_string())
+}
+
+// Until kubeadm init wrote the PKI and the admin kubeconfig there is nothing to renew.
+fn check_expiries(
+	pki: &Path,
+	admin_kubeconfig: &Path,
+	kubeconfigs: &[PathBuf],
+	now: Timestamp,
+	renew_before_days: u32,
+) -> Result<Check, InstallError> {
+	if !pki.is_dir() || !admin_kubeconfig.exists() {
+		return Ok(Check::drift("no cluster certificates yet"));
+	}
+	let renew_before = now + SignedDuration::from_hours(i64::from(renew_before_days) * 24);
+	let (cas, expiries): (Vec<_>, Vec<_>) = expiries(pki, kubeconfigs)?
+		.into_iter()
+		.partition(|expiry| is_ca(pki, expiry));
+	for ca in cas.iter().filter(|ca| ca.not_after < renew_before) {
+		warn!(
+			"CA {} expires {}, it needs a manual rotation.",
+			ca.source,
+			ca.not_after.strftime("%Y-%m-%d")
+		);
+	}
+	let expiring = expiries
+		.iter()
+		.filter(|expiry| expiry.not_after < renew_before)
+		.collect::<Vec<_>>();
+	match (expiring.first(), expiries.first()) {
+		(Some(earliest), _) => Ok(Check::drift(format!(
+			"{} certificates expire within {} days, {} first on {}.",
+			expiring.len(),
+			renew_before_days,
+			earliest.source,
+			earliest.not_after.strftime("%Y-%m-%d")
+		))),
+		(None, Some(earliest)) => {
+			info!(
+				"{} certificates are valid, {} expires first on {}.",
+				expiries.len(),
+				earliest.source,
+				earliest.not_after.strftime("%Y-%m-%d")
+			);
+			Ok(Check::Satisfied)
+		}
+		(None, None) => Ok(Check::drift(format!(
+			"No certificates in {}.",
+			pki.display()
+		))),
+	}
+}
+
+fn running_containers(name: &str) -> Result<Vec<String>, InstallError> {
+	let output = Cmd::new("crictl")
+		.args(["--runtime-endpoint", Certificates::CRI_ENDPOINT])
+		.args(["ps", "--state", "running", "--quiet", "--name"])
+		.arg(format!("^{name}$"))
+		.output()?;
+	Ok(output.stdout.lines().map(str::to_owned).collect())
+}
+
+// The kubelet stops a static pod whose manifest disappears and starts it again once it is back.
+fn restart_static_pod(name: &str) -> Result<(), InstallError> {
+	let manifest = Path::new(Certificates::MANIFEST_DIR).join(format!("{name}.yaml"));
+	if !manifest.exists() {
+		info!("No static pod manifest {}, skipping.", manifest.display());
+		return Ok(());
+	}
+	let parked = Path::new(Certificates::PARKED_DIR).join(format!("{name}.yaml.renewing"));
+	let before = running_containers(name)?;
+	info!("Restarting static pod {}.", name);
+	fs::rename(&manifest, &parked)?;
+	let stopped = Certificates::POD_RESTART.until(&format!("{name} to stop"), || {
+		Ok(running_containers(name)?.is_empty())
+	});
+	fs::rename(&parked, &manifest)?;
+	stopped?;
+	Certificates::POD_RESTART.until(&format!("{name} to start"), || {
+		Ok(running_containers(name)?
+			.iter()
+			.any(|id| !before.contains(id)))
+	})
+}
+
+impl SetupStep for Certificates {
+	fn name(&self) -> &'static str {
+		"Certificates"
+	}
+
+	fn requires(&self) -> &'static [&'static str] {
+		&["ControlPlane"]
+	}
+
+	fn check(&self) -> Result<Check, InstallError> {
+		if inventory::this()?.role == MachineRole::Worker {
+			info!("This machine is a worker, the kubelet rotates its certificates.");
+			return Ok(Check::Satisfied);
+		}
+		check_expiries(
+			Path::new(ControlPlane::PKI_DIR),
+			Path::new(kctl::KUBECONFIG),
+			&Certificates::kubeconfigs(),
+			Timestamp::now(),
+			config::get().certificates.renew_before_days,
+		)
+	}
+
+	fn set(&self) -> Result<(), InstallError> {
+		info!("Renewing the kubeadm certificates.");
+		Cmd::new("kubeadm")
+			.args(["certs", "renew", "all"])
+			.timeout(ControlPlane::KUBEADM_TIMEOUT)
+			.run()?;
+		for name in Certificates::STATIC_PODS {
+			restart_static_pod(name)?;
+		}
+		kctl::wait_apiserver_healthy(
+			&config::get().kube_vip.endpoint(),
+			ControlPlane::API_HEALTHY,
+		)?;
+		control_plane::copy_admin_kubeconfig()?;
+		info!("Certificates renewed.");
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use rcgen::{CertificateParams, KeyPair, date_time_ymd};
+	use std::{env, process};
+
+	const NOW: &str = "2026-01-01T12:00:00Z";
+	const LATER: (i32, u8, u8) = (2027, 1, 1);
+
+	// A kubeadm shaped PKI and admin kubeconfig under a scratch directory.
+	struct Cluster {
+		dir: PathBuf,
+	}
+
+	impl Cluster {
+		fn new(name: &str) -> Cluster {
+			let dir = env::temp_dir().join(format!("certificates-{}-{name}", process::id()));
+			let _ = fs::remove_dir_all(&dir);
+			let cluster = Cluster { dir };
+			for name in [
+				"ca.crt",
+				"apiserver.crt",
+				"front-proxy-ca.crt",
+				"etcd/ca.crt",
+				"etcd/server.crt",
+			] {
+				cluster.cert(name, LATER);
+			}
+			cluster.admin_kubeconfig(LATER);
+			cluster
+		}
+
+		fn pki(&self) -> PathBuf {
+			self.dir.join("pki")
+		}
+
+		fn admin(&self) -> PathBuf {
+			self.dir.join("admin.conf")
+		}
+
+		fn cert(&self, name: &str, (year, month, day): (i32, u8, u8)) {
+			let path = self.pki().join(name);
+			fs::create_dir_all(path.parent().unwrap()).unwrap();
+			fs::write(path, pem(year, month, day)).unwrap();
+		}
+
+		fn admin_kubeconfig(&self, (year, month, day): (i32, u8, u8)) {
+			fs::create_dir_all(&self.dir).unwrap();
+			let kubeconfig_txt = format!(
+				"apiVersion: v1\nkind: Config\nusers:\n- name: kubernetes-admin\n  user:\n    client-certificate-data: {}\n",
+				STANDARD.encode(pem(year, month, day))
+			);
+			fs::write(self.admin(), kubeconfig_txt).unwrap();
+		}
+
+		fn check(&self) -> Check {
+			check_expiries(
+				&self.pki(),
+				&self.admin(),
+				&[self.admin()],
+				NOW.parse().unwrap(),
+				30,
+			)
+			.unwrap()
+		}
+	}
+
+	impl Drop for Cluster {
+		fn drop(&mut self) {
+			let _ = fs::remove_dir_all(&self.dir);
+		}
+	}
+
+	fn pem(year: i32, month: u8, day: u8) -> String {
+		let mut params = CertificateParams::new(vec!["kubernetes".to_owned()]).unwrap();
+		params.not_before = date_time_ymd(2025, 1, 1);
+		params.not_after = date_time_ymd(year, month, day);
+		params
+			.self_signed(&KeyPair::generate().unwrap())
+			.unwrap()
+			.pem()
+	}
+
+	#[test]
+	fn missing_pki_or_kubeconfig_is_drift() {
+		let cluster = Cluster::new("missing");
+		fs::remove_file(cluster.admin()).unwrap();
+		assert_eq!(cluster.check(), Check::drift("no cluster certificates yet"));
+		fs::remove_dir_all(cluster.pki()).unwrap();
+		assert_eq!(cluster.check(), Check::drift("no cluster certificates yet"));
+	}
+
+	#[test]
+	fn certificates_outside_the_window_are_satisfied() {
+		let cluster = Cluster::new("valid");
+		// 30 days and 12 hours out.
+		cluster.cert("apiserver.crt", (2026, 2, 1));
+		assert_eq!(cluster.check(), Check::Satisfied);
+	}
+
+	#[test]
+	fn certificate_inside_the_window_drifts() {
+		let cluster = Cluster::new("expiring");
+		// 29 days and 12 hours out.
+		cluster.cert("apiserver.crt", (2026, 1, 31));
+		cluster.cert("etcd/server.crt", (2026, 1, 20));
+		assert_eq!(
+			cluster.check(),
+			Check::drift(format!(
+				"2 certificates expire within 30 days, {} first on 2026-01-20.",
+				cluster.pki().join("etcd/server.crt").display()
+			))
+		);
+	}
+
+	#[test]
+	fn expiring_cas_are_only_reported() {
+		let cluster = Cluster::new("cas");
+		cluster.cert("ca.crt", (2026, 1, 5));
+		cluster.cert("etcd/ca.crt", (2026, 1, 5));
+		assert_eq!(cluster.check(), Check::Satisfied);
+	}
+
+	#[test]
+	fn kubeconfig_client_certificate_drifts() {
+		let cluster = Cluster::new("kubeconfig");
+		cluster.admin_kubeconfig((2026, 1, 10));
+		assert_eq!(
+			cluster.check(),
+			Check::drift(format!(
+				"1 certificates expire within 30 days, {} user kubernetes-admin first on 2026-01-10.",
+				cluster.admin().display()
+			))
+		);
+	}
+}
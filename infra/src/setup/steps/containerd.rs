@@ -1,7 +1,7 @@
 use crate::error::InstallError;
-use crate::setup::utils::pkg;
-use crate::setup::SetupStep;
-use std::{fs, path::Path, process::Command};
+use crate::setup::utils::{cmd::Cmd, pkg};
+use crate::setup::{Check, SetupStep};
+use std::{fs, path::Path};
 use tracing::info;
 
 pub struct Containerd;
@@ -16,26 +16,27 @@ impl SetupStep for Containerd {
 		"Containerd"
 	}
 
-	fn check(&self) -> Result<bool, InstallError> {
+	fn requires(&self) -> &'static [&'static str] {
+		&["KernelModules"]
+	}
+
+	fn check(&self) -> Result<Check, InstallError> {
 		let is_installed = pkg::is_installed(Containerd::PACKAGE_NAME)?;
 		if !is_installed {
-			info!("Containerd is not installed.");
-			return Ok(false);
+			return Ok(Check::drift("Containerd is not installed."));
 		}
 		let is_configured = Path::new(Containerd::CONFIG_PATH).exists();
 		if !is_configured {
-			info!("Containerd is not configured.");
-			return Ok(false);
+			return Ok(Check::drift("Containerd is not configured."));
 		}
-		let is_active = Command::new("systemctl")
+		let is_active = Cmd::new("systemctl")
 			.args(["is-active", "--quiet", Containerd::PACKAGE_NAME])
-			.status()
-			.is_ok_and(|s| s.success());
+			.probe()?
+			.success();
 		if !is_active {
-			info!("Containerd is not active.");
-			Ok(false)
+			Ok(Check::drift("Containerd is not active."))
 		} else {
-			Ok(true)
+			Ok(Check::Satisfied)
 		}
 	}
 
@@ -45,33 +46,16 @@ impl SetupStep for Containerd {
 		let config_path = Path::new(Containerd::CONFIG_PATH);
 		if !config_path.exists() || fs::read(config_path)?.is_empty() {
 			info!("Generating default containerd config.");
-			let default_config = Command::new(Containerd::PACKAGE_NAME)
-				.arg("config")
-				.arg("default")
-				.output()
-				.map_err(|err| InstallError::CommandLaunch {
-					cmd: format!("{} config default", Containerd::PACKAGE_NAME),
-					source: err,
-				})?;
+			let default_config = Cmd::new(Containerd::PACKAGE_NAME)
+				.args(["config", "default"])
+				.output()?;
 			fs::write(config_path, default_config.stdout)?;
 		} else {
 			info!("Containerd config already exists, skipping generation.");
 		}
 		info!("Restarting containerd service.");
-		let status = Command::new("systemctl")
+		Cmd::new("systemctl")
 			.args(["restart", Containerd::PACKAGE_NAME])
-			.status()
-			.map_err(|source| InstallError::CommandLaunch {
-				cmd: format!("systemctl restart {}", Containerd::PACKAGE_NAME),
-				source,
-			})?;
-		if !status.success() {
-			return Err(InstallError::CommandFailed {
-				cmd: format!("systemctl restart {}", Containerd::PACKAGE_NAME),
-				status,
-				stderr: None,
-			});
-		}
-		Ok(())
+			.run()
 	}
 }
@@ -1,30 +1,37 @@
-use crate::context;
 use crate::error::InstallError;
-use crate::setup::utils::inventory;
-use crate::setup::SetupStep;
-use std::{
-	fs,
-	io::Write,
-	process::{Command, Stdio},
-	thread::sleep,
-	time::Duration,
-};
+use crate::setup::steps::etcd_backup;
+use crate::setup::utils::{cmd::Cmd, inventory, kctl, ledger, retry::Retry};
+use crate::setup::{Check, SetupStep};
+use crate::{config, context};
+use k8s_openapi::api::core::v1::Node;
+use kube::ResourceExt;
+use std::{fs, os::unix::fs::PermissionsExt, path::Path, time::Duration};
 use tracing::info;
 
 pub struct ControlPlane;
 
 impl ControlPlane {
-	pub const CILIUM_CLI_VERSION: &str = "v0.18.9";
-	pub const CILIUM_VERSION: &str = "v1.18.4";
-	pub const K8S_VERSION: &str = "v1.34.2";
-	pub const KUBE_VIP: &str = "192.168.0.2";
-	pub const KUBE_VIP_CONTAINER: &str = "ghcr.io/kube-vip/kube-vip";
-	pub const KUBE_VIP_CONTAINER_HASH: &str =
-		"f86c774c4c0dcab81e56e3bdb42a5a6105c324767cfbc3a44df044f8a2666f8e";
-	pub const KUBE_VIP_PORT: &str = "6443";
-	pub const KUBE_VIP_VERSION: &str = "v1.0.2";
-	pub const NETWORK_INTERFACE: &str = "wlo1";
-	pub const POD_CIDR: &str = "10.0.0.0/16";
+	pub const API_HEALTHY: Duration = Duration::from_secs(300);
+	pub const NODE_READY: Duration = Duration::from_secs(300);
+	pub const NODE_UPDATE: Retry = Retry::attempts(10)
+		.deadline(Duration::from_secs(120))
+		.backoff(Duration::from_secs(2), Duration::from_secs(15));
+	pub const JOIN_COMMAND: Retry = Retry::attempts(3).deadline(Duration::from_secs(180));
+	pub const KUBEADM_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+	pub const PKI_DIR: &str = "/etc/kubernetes/pki";
+	pub const ETCD_DATA_DIR: &str = "/var/lib/etcd";
+	pub const KUBE_VIP_MANIFEST: &str = "/etc/kubernetes/manifests/kube-vip.yaml";
+	// Everything kubeadm cannot regenerate without invalidating certificates and service account tokens.
+	pub const CA_FILES: &[&str] = &[
+		"ca.crt",
+		"ca.key",
+		"sa.key",
+		"sa.pub",
+		"front-proxy-ca.crt",
+		"front-proxy-ca.key",
+		"etcd/ca.crt",
+		"etcd/ca.key",
+	];
 }
 
 impl SetupStep for ControlPlane {
@@ -32,36 +39,51 @@ impl SetupStep for ControlPlane {
 		"ControlPlane"
 	}
 
-	fn check(&self) -> Result<bool, InstallError> {
-		match inventory::this().role {
+	fn requires(&self) -> &'static [&'static str] {
+		&[
+			"DisableSwap",
+			"KernelModules",
+			"Sysctl",
+			"Containerd",
+			"Kubes",
+			"Firewall",
+		]
+	}
+
+	fn check(&self) -> Result<Check, InstallError> {
+		match inventory::this()?.role {
 			inventory::MachineRole::Worker => {
 				info!("This machine is a worker, no control plane setup required.");
-				return Ok(true);
+				return Ok(Check::Satisfied);
 			}
 			inventory::MachineRole::ControlPlaneRoot | inventory::MachineRole::ControlPlane => {}
 		}
-		let is_setup = str::from_utf8(
-			&Command::new("kubectl")
-				.args(["--kubeconfig", "/etc/kubernetes/admin.conf"])
-				.args(["get", "node", &context::get().hostname, "--show-labels"])
-				.output()?
-				.stdout,
-		)?
-		.trim()
-		.contains("node-role.kubernetes.io/control-plane=");
+		if !Path::new(kctl::KUBECONFIG).exists() {
+			return Ok(Check::drift("Node has not joined a cluster."));
+		}
+		let node = kctl::get(&kctl::cluster::<Node>()?, &context::get().hostname)?;
+		let is_setup = node.is_some_and(|node| {
+			node.labels()
+				.contains_key("node-role.kubernetes.io/control-plane")
+		});
 		if is_setup {
 			info!("ControlPlane is already set up.");
-			Ok(true)
+			Ok(Check::Satisfied)
+		} else if let Some(record) = ledger::applied(self.name()) {
+			Ok(Check::drift(format!(
+				"Node was set up at {} but is no longer labeled as a control plane.",
+				record.applied_at
+			)))
 		} else {
-			info!("ControlPlane is not set up.");
-			Ok(false)
+			Ok(Check::drift("Node is not labeled as a control plane."))
 		}
 	}
 
 	fn set(&self) -> Result<(), InstallError> {
 		info!("ControlPlane setup started.");
-		info!("Machine Id: {}", inventory::this().id);
-		match inventory::this().role {
+		let machine = inventory::this()?;
+		info!("Machine Id: {}", machine.id);
+		match machine.role {
 			inventory::MachineRole::Worker => {
 				info!("This machine is a worker, skipping control plane setup.");
 			}
@@ -77,41 +99,93 @@ impl SetupStep for ControlPlane {
 		info!("Control plane setup finished.");
 		Ok(())
 	}
+
+	fn unset(&self) -> Result<(), InstallError> {
+		if inventory::this()?.role == inventory::MachineRole::Worker {
+			info!("This machine is a worker, the Worker step resets it.");
+			return Ok(());
+		}
+		reset_node()
+	}
+
+	fn versions(&self) -> Vec<(&'static str, String)> {
+		let config = config::get();
+		vec![
+			("kubernetes", config.kubernetes.version.clone()),
+			("kube-vip", config.kube_vip.version.clone()),
+			("cilium", config.cilium.version.clone()),
+			("cilium-cli", config.cilium.cli_version.clone()),
+		]
+	}
 }
 
 fn remove_noschedule_taint() -> Result<(), InstallError> {
 	info!("Removing NoSchedule taint for control plane worker mode.");
-	sleep(Duration::from_secs(4));
-	Command::new("kubectl")
-		.args(["--kubeconfig", "/etc/kubernetes/admin.conf"])
-		.args([
-			"taint",
-			"nodes",
+	ControlPlane::NODE_UPDATE.run("removing the NoSchedule taint", || {
+		kctl::untaint(
 			&context::get().hostname,
-			"node-role.kubernetes.io/control-plane:NoSchedule-",
-		])
-		.status()?;
+			"node-role.kubernetes.io/control-plane",
+			"NoSchedule",
+		)
+	})?;
 	info!("NoSchedule taint removed.");
 	Ok(())
 }
 
+pub fn reset_node() -> Result<(), InstallError> {
+	info!("Hard reset Kubernetes node.");
+	Cmd::bash(
+		r#"
+			set -euo pipefail
+			sudo systemctl stop kubelet || true
+			sudo kubeadm reset --force || true
+			sudo rm -rf /etc/kubernetes/
+			sudo rm -rf /var/lib/kubelet/
+			sudo rm -rf /var/lib/etcd/
+			sudo rm -rf /opt/cni/
+			sudo mkdir -p /etc/kubernetes/manifests/
+			sudo mkdir /var/lib/kubelet/
+			sudo mkdir /var/lib/etcd/
+			sudo mkdir /opt/cni/
+			sudo iptables -X || true
+			sudo systemctl restart containerd || true
+			sudo systemctl start kubelet || true
+		"#,
+	)
+	.run()?;
+	info!("Node has been hard reset.");
+	Ok(())
+}
+
 fn setup_control_plane_root() -> Result<(), InstallError> {
 	info!("Bootstrapping control plane root node.");
+	prepare_root()?;
+	reset_node()?;
+	write_kube_vip_manifest(Path::new(ControlPlane::KUBE_VIP_MANIFEST))?;
+	kubeadm_init(&[])?;
+	kctl::wait_apiserver_healthy(
+		&config::get().kube_vip.endpoint(),
+		ControlPlane::API_HEALTHY,
+	)?;
+	install_kubeconfig()?;
+	install_cilium()?;
+	kctl::wait_node_ready(&context::get().hostname, ControlPlane::NODE_READY)?;
+	Ok(())
+}
+
+fn prepare_root() -> Result<(), InstallError> {
+	let config = config::get();
+	let kube_vip = &config.kube_vip;
 	info!("Pulling kube-vip container.");
-	Command::new("ctr")
-		.arg("image")
-		.arg("pull")
+	Cmd::new("ctr")
+		.args(["image", "pull"])
 		.arg(format!(
 			"{}:{}@sha256:{}",
-			ControlPlane::KUBE_VIP_CONTAINER,
-			ControlPlane::KUBE_VIP_VERSION,
-			ControlPlane::KUBE_VIP_CONTAINER_HASH,
+			kube_vip.image, kube_vip.version, kube_vip.image_hash,
 		))
-		.status()?;
+		.run()?;
 	info!("Installing cilium cluster mesh.");
-	Command::new("sh")
-		.arg("-c")
-		.arg(format!(
+	Cmd::bash(format!(
 			r#"
 			set -euo pipefail
 			cd /tmp
@@ -123,121 +197,126 @@ fn setup_control_plane_root() -> Result<(), InstallError> {
 			sudo install -m 0755 cilium /usr/local/bin/cilium
 			rm -f cilium-linux-amd64.tar.gz*
 		"#
-		, ControlPlane::CILIUM_CLI_VERSION))
-		.status()?;
+		, config.cilium.cli_version))
+		.run()?;
 	info!("Cilium is installed.");
-	info!("Hard reset Kubernetes control plane root node.");
-	Command::new("sh")
-		.arg("-c")
-		.arg(
-			r#"
-			set -euo pipefail
-			sudo systemctl stop kubelet || true
-			sudo kubeadm reset --force || true
-			sudo rm -rf /etc/kubernetes/
-			sudo rm -rf /var/lib/kubelet/
-			sudo rm -rf /var/lib/etcd/
-			sudo rm -rf /opt/cni/
-			sudo mkdir -p /etc/kubernetes/manifests/
-			sudo mkdir /var/lib/kubelet/
-			sudo mkdir /var/lib/etcd/
-			sudo mkdir /opt/cni/
-			sudo iptables -X || true
-			sudo systemctl restart containerd || true
-			sudo systemctl start kubelet || true
-		"#,
-		)
-		.status()?;
-	info!("Node has been hard reset.");
+	Ok(())
+}
+
+fn write_kube_vip_manifest(path: &Path) -> Result<(), InstallError> {
+	let kube_vip = &config::get().kube_vip;
+	let interface = inventory::this()?
+		.interface
+		.as_deref()
+		.unwrap_or(&kube_vip.interface);
 	info!("Bootstrapping kube-vip config.");
-	let kube_vip_config_out = Command::new("ctr")
+	let kube_vip_config = Cmd::new("ctr")
 		.arg("run")
 		.arg("--rm")
 		.arg("--net-host")
 		.args([
 			"--mount",
-			"type=bind,src=/etc/kubernetes/manifests,dst=/etc/kubernetes/manifests,options=rbind:rw"
+			"type=bind,src=/etc/kubernetes/manifests,dst=/etc/kubernetes/manifests,options=rbind:rw",
 		])
-		.arg(format!(
-			"{}:{}",
-			ControlPlane::KUBE_VIP_CONTAINER,
-			ControlPlane::KUBE_VIP_VERSION,
-		))
+		.arg(format!("{}:{}", kube_vip.image, kube_vip.version))
 		.arg("kube-vip")
 		.arg("manifest")
 		.arg("pod")
-		.args(["--vip", ControlPlane::KUBE_VIP])
-		.args(["--interface", ControlPlane::NETWORK_INTERFACE])
+		.args(["--vip", &kube_vip.address])
+		.args(["--interface", interface])
 		.arg("--arp")
 		.arg("--controlplane")
 		.arg("--leaderElection")
-		.output()?;
-	let kube_vip_config = String::from_utf8(kube_vip_config_out.stdout)?;
-	let kube_vip_config_path = "/etc/kubernetes/manifests/kube-vip.yaml";
-	fs::write(kube_vip_config_path, kube_vip_config)?;
-	info!("Sleeping for kube-vip to bootstrap.");
-	sleep(Duration::from_secs(4));
+		.output()?
+		.stdout;
+	fs::write(path, kube_vip_config)?;
 	info!("Kube-vip config written.");
+	Ok(())
+}
+
+// Preflight errors listed in `ignore` are tolerated on top of the small machine ones.
+fn kubeadm_init(ignore: &[&str]) -> Result<(), InstallError> {
+	let config = config::get();
+	let kube_vip = &config.kube_vip;
+	let ignore = ["NumCPU", "Mem"]
+		.iter()
+		.chain(ignore)
+		.copied()
+		.collect::<Vec<_>>()
+		.join(",");
 	info!("Kubeadm init.");
-	Command::new("kubeadm")
+	Cmd::new("kubeadm")
 		.arg("init")
-		.args([
-			"--control-plane-endpoint",
-			&format!("{}:{}", ControlPlane::KUBE_VIP, ControlPlane::KUBE_VIP_PORT,),
-		])
+		.args(["--control-plane-endpoint", &kube_vip.endpoint()])
 		.arg("--upload-certs")
-		.args(["--pod-network-cidr", ControlPlane::POD_CIDR])
-		.args(["--apiserver-advertise-address", ControlPlane::KUBE_VIP])
+		.args(["--pod-network-cidr", &config.kubernetes.pod_cidr])
+		.args(["--apiserver-advertise-address", &kube_vip.address])
 		.args([
 			"--apiserver-cert-extra-sans",
-			&format!("{},127.0.0.1,localhost", ControlPlane::KUBE_VIP),
+			&format!("{},127.0.0.1,localhost", kube_vip.address),
 		])
-		.args(["--kubernetes-version", ControlPlane::K8S_VERSION])
+		.args(["--kubernetes-version", &config.kubernetes.version])
 		.arg("--feature-gates=UserNamespacesSupport=true")
-		.arg("--ignore-preflight-errors=NumCPU,Mem")
+		.arg(format!("--ignore-preflight-errors={ignore}"))
 		.arg("--skip-phases=addon/kube-proxy")
-		.status()?;
+		.timeout(ControlPlane::KUBEADM_TIMEOUT)
+		.run()?;
 	info!("Kubeadm initalized.");
-	sleep(Duration::from_secs(2));
+	Ok(())
+}
+
+fn install_kubeconfig() -> Result<(), InstallError> {
 	info!("Setting cluster trust using embedded CA data.");
-	Command::new("bash")
-		.arg("-c")
-		.arg(format!(
-			r#"
+	Cmd::bash(format!(
+		r#"
 			kubectl config set-cluster kubernetes \
 				--certificate-authority=<(sudo cat /etc/kubernetes/pki/ca.crt) \
 				--embed-certs=true \
-				--server=https://{}:{}
+				--server=https://{}
 		"#,
-			ControlPlane::KUBE_VIP,
-			ControlPlane::KUBE_VIP_PORT,
-		))
-		.status()?;
-	sleep(Duration::from_secs(2));
+		config::get().kube_vip.endpoint(),
+	))
+	.run()?;
+	copy_admin_kubeconfig()
+}
+
+// The admin kubeconfig embeds a client certificate, the copies are refreshed after renewal.
+pub fn copy_admin_kubeconfig() -> Result<(), InstallError> {
 	let home = &context::get().home;
 	let user = &context::get().user;
-	Command::new("sh")
-		.arg("-c")
-		.arg(format!(
-			r#"
+	Cmd::sh(format!(
+		r#"
 			mkdir -p {}/.kube
 			sudo cp -f /etc/kubernetes/admin.conf {}/.kube/config
 			sudo chown {}:{} {}/.kube/config
 		"#,
-			home, home, user, user, home
-		))
-		.status()?;
+		home, home, user, user, home
+	))
+	.run()?;
+	Cmd::sh(
+		r#"
+			sudo mkdir -p /root/.kube
+			sudo cp -f /etc/kubernetes/admin.conf /root/.kube/config
+			sudo chmod 600 /root/.kube/config
+		"#,
+	)
+	.run()?;
 	info!("Kubeconfig set for current user.");
-	sleep(Duration::from_secs(2));
+	Ok(())
+}
+
+fn install_cilium() -> Result<(), InstallError> {
+	let config = config::get();
+	let home = &context::get().home;
 	info!("Cilium installing.");
-	Command::new("cilium")
+	Cmd::new("cilium")
 		.env("KUBECONFIG", format!("{}/.kube/config", home))
 		.arg("install")
-		.args(["--version", ControlPlane::CILIUM_VERSION])
+		.args(["--version", &config.cilium.version])
 		.args(["--set", "kubeProxyReplacement=true"])
 		.args([
 			"--set",
-			&format!(r#"cluster-pool.cidr="{}""#, ControlPlane::POD_CIDR),
+			&format!(r#"cluster-pool.cidr="{}""#, config.kubernetes.pod_cidr),
 		])
 		.args(["--set", "hubble.enabled=true"])
 		.args(["--set", "hubble.relay.enabled=true"])
@@ -245,39 +324,146 @@ fn setup_control_plane_root() -> Result<(), InstallError> {
 		.args(["--set", "tls.ca.enabled=true"])
 		.args(["--set", "tls.ca.manage=true"])
 		.arg("--wait")
-		.status()?;
+		.run()?;
 	info!("Cilium installed.");
 	Ok(())
 }
 
-fn get_control_plane_join_command() -> Result<String, InstallError> {
+fn read_ca(pki: &Path) -> Result<Vec<(&'static str, Vec<u8>)>, InstallError> {
+	ControlPlane::CA_FILES
+		.iter()
+		.map(|name| {
+			let path = pki.join(name);
+			fs::read(&path).map(|bytes| (*name, bytes)).map_err(|err| {
+				InstallError::Config(format!(
+					"{} is needed to keep the cluster CA: {err}",
+					path.display()
+				))
+			})
+		})
+		.collect()
+}
+
+fn write_ca(ca: &[(&str, Vec<u8>)], pki: &Path) -> Result<(), InstallError> {
+	for (name, bytes) in ca {
+		let path = pki.join(name);
+		if let Some(dir) = path.parent() {
+			fs::create_dir_all(dir)?;
+		}
+		fs::write(&path, bytes)?;
+		if name.ends_with(".key") {
+			fs::set_permissions(&path, fs::Permissions::from_mode(0o600))?;
+		}
+	}
+	info!("Cluster CA restored into {}.", pki.display());
+	Ok(())
+}
+
+// Where a root rebuild writes, tests point these at a scratch directory.
+struct RootPaths<'a> {
+	pki: &'a Path,
+	etcd_data: &'a Path,
+	kube_vip_manifest: &'a Path,
+}
+
+// The destructive part of a restore: the CA is back before kubeadm init, which would otherwise mint a new one.
+fn rebuild_root(
+	snapshot: &Path,
+	ca: &[(&str, Vec<u8>)],
+	paths: &RootPaths,
+) -> Result<(), InstallError> {
+	prepare_root()?;
+	reset_node()?;
+	write_ca(ca, paths.pki)?;
+	etcd_backup::restore_data(snapshot, paths.etcd_data)?;
+	write_kube_vip_manifest(paths.kube_vip_manifest)?;
+	kubeadm_init(&["DirAvailable--var-lib-etcd"])
+}
+
+// Rebuilds the root when etcd lost quorum, the other control planes rejoin through their ControlPlane step.
+pub fn restore_root(snapshot: &Path, pki: &Path) -> Result<(), InstallError> {
+	let machine = inventory::this()?;
+	if machine.role != inventory::MachineRole::ControlPlaneRoot {
+		return Err(InstallError::Config(format!(
+			"machine '{}' is not the ControlPlaneRoot, promote it in the inventory first",
+			machine.id
+		)));
+	}
+	let ca = read_ca(pki)?;
+	etcd_backup::ensure_tools()?;
+	etcd_backup::verify(snapshot)?;
+	info!(
+		"Restoring the control plane root from {}.",
+		snapshot.display()
+	);
+	rebuild_root(
+		snapshot,
+		&ca,
+		&RootPaths {
+			pki: Path::new(ControlPlane::PKI_DIR),
+			etcd_data: Path::new(ControlPlane::ETCD_DATA_DIR),
+			kube_vip_manifest: Path::new(ControlPlane::KUBE_VIP_MANIFEST),
+		},
+	)?;
+	kctl::wait_apiserver_healthy(
+		&config::get().kube_vip.endpoint(),
+		ControlPlane::API_HEALTHY,
+	)?;
+	install_kubeconfig()?;
+	// Their etcd members are not in the restored cluster, without a Node their ControlPlane step drifts.
+	let hostname = &context::get().hostname;
+	let nodes = kctl::cluster::<Node>()?;
+	for node in kctl::list(&nodes, "node-role.kubernetes.io/control-plane")? {
+		if node.name_any() != *hostname {
+			info!("Deleting stale control plane node {}.", node.name_any());
+			kctl::delete(&nodes, &node.name_any())?;
+		}
+	}
+	kctl::wait_node_ready(hostname, ControlPlane::NODE_READY)?;
+	remove_noschedule_taint()?;
+	ledger::record(
+		ControlPlane.name(),
+		ledger::Action::Applied,
+		ControlPlane.versions(),
+	)?;
+	info!("Control plane root restored, the other control planes rejoin on their next apply.");
+	Ok(())
+}
+
+// Joining nodes reach the root over SSH through the kube-vip address, its admin credentials stay there.
+pub fn run_on_root(script: &str) -> Result<String, InstallError> {
 	let home = &context::get().home;
-	let mut child = Command::new("ssh")
+	let output = Cmd::new("ssh")
 		.args(["-o", "LogLevel=ERROR"])
 		.args(["-i", &format!("{}/.ssh/id_ed25519", home)])
-		.arg(format!("mgmt@{}", ControlPlane::KUBE_VIP))
-		.arg("bash")
-		.arg("-s")
-		.stdin(Stdio::piped())
-		.stdout(Stdio::piped())
-		.stderr(Stdio::piped())
-		.spawn()?;
-	if let Some(mut stdin) = child.stdin.take() {
-		stdin.write_all(
-			br#"
-				set -e
-				sudo -n bash -c '
-					export KUBECONFIG=/etc/kubernetes/admin.conf
-					K8S_CERT_KEY=$(kubeadm init phase upload-certs --upload-certs | tail -1 | tr -d "\n")
-					kubeadm token create --print-join-command --certificate-key $K8S_CERT_KEY
-				'
-			"#,
-		)?;
-	}
-	let output = child.wait_with_output()?;
-	let join_cmd = String::from_utf8(output.stdout)?.trim().to_owned();
+		.arg(format!(
+			"{}@{}",
+			inventory::root()?.ssh_user,
+			config::get().kube_vip.address
+		))
+		.args(["bash", "-s"])
+		.timeout(Duration::from_secs(60))
+		.stdin(script)
+		.output()?;
+	Ok(output.stdout)
+}
+
+fn get_control_plane_join_command() -> Result<String, InstallError> {
+	let output = run_on_root(
+		r#"
+			set -e
+			sudo -n bash -c '
+				export KUBECONFIG=/etc/kubernetes/admin.conf
+				K8S_CERT_KEY=$(kubeadm init phase upload-certs --upload-certs | tail -1 | tr -d "\n")
+				kubeadm token create --print-join-command --certificate-key $K8S_CERT_KEY
+			'
+		"#,
+	)?;
+	let join_cmd = output.trim().to_owned();
 	if join_cmd.is_empty() || !join_cmd.contains("--control-plane") {
-		panic!("Received empty or invalid join command: {join_cmd:?}");
+		return Err(InstallError::Kube(format!(
+			"Received empty or invalid join command: {join_cmd:?}"
+		)));
 	}
 	info!("Successfully obtained fresh control-plane join command.");
 	Ok(join_cmd + " --v=5")
@@ -286,10 +472,8 @@ fn get_control_plane_join_command() -> Result<String, InstallError> {
 fn setup_control_plane() -> Result<(), InstallError> {
 	info!("Joining additional control plane node.");
 	info!("Hard reset Kubernetes control plane node.");
-	Command::new("sh")
-		.arg("-c")
-		.arg(
-			r#"
+	Cmd::bash(
+		r#"
 			set -euo pipefail
 			sudo systemctl stop kubelet || true
 			sudo kubeadm reset --force || true
@@ -302,37 +486,158 @@ fn setup_control_plane() -> Result<(), InstallError> {
 			sudo systemctl restart containerd || true
 			sudo systemctl start kubelet || true
 		"#,
-		)
-		.status()?;
+	)
+	.run()?;
 	info!("Node has been hard reset.");
-	let join_command = get_control_plane_join_command()?;
+	let join_command = ControlPlane::JOIN_COMMAND
+		.run("fetching the join command", get_control_plane_join_command)?;
 	info!("Executing join command:\n{join_command}\n");
-	Command::new("bash").arg("-c").arg(join_command).status()?;
+	Cmd::bash(join_command)
+		.timeout(ControlPlane::KUBEADM_TIMEOUT)
+		.run()?;
 	info!("This node has joined the control plane.");
-	sleep(Duration::from_secs(2));
-	let home = &context::get().home;
-	let user = &context::get().user;
-	Command::new("sh")
-		.arg("-c")
-		.arg(format!(
-			r#"
-			mkdir -p {}/.kube
-			sudo cp -f /etc/kubernetes/admin.conf {}/.kube/config
-			sudo chown {}:{} {}/.kube/config
-		"#,
-			home, home, user, user, home
-		))
-		.status()?;
-	Command::new("sh")
-		.arg("-c")
-		.arg(
-			r#"
-			sudo mkdir -p /root/.kube
-			sudo cp -f /etc/kubernetes/admin.conf /root/.kube/config
-			sudo chmod 600 /root/.kube/config
-		"#,
-		)
-		.status()?;
-	info!("Kubeconfig set for current user.");
+	kctl::wait_apiserver_healthy(
+		&config::get().kube_vip.endpoint(),
+		ControlPlane::API_HEALTHY,
+	)?;
+	kctl::wait_node_ready(&context::get().hostname, ControlPlane::NODE_READY)?;
+	copy_admin_kubeconfig()?;
 	Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::setup::testing;
+	use crate::setup::utils::cmd::{Output, Runner, ScriptedRunner};
+	use std::{
+		env,
+		path::PathBuf,
+		process,
+		sync::{Mutex, PoisonError},
+	};
+
+	// Replays the script and plays the part of the filesystem the commands would touch.
+	struct Rebuild {
+		scripted: ScriptedRunner,
+		pki: PathBuf,
+		// Which CA files were in place when kubeadm init ran.
+		ca_at_init: Mutex<Option<Vec<bool>>>,
+	}
+
+	impl Runner for Rebuild {
+		fn execute(&self, cmd: &Cmd) -> Result<Output, InstallError> {
+			let argv = cmd.argv();
+			if argv[..2] == ["kubeadm", "init"] {
+				let present = ControlPlane::CA_FILES
+					.iter()
+					.map(|name| self.pki.join(name).exists())
+					.collect();
+				*self
+					.ca_at_init
+					.lock()
+					.unwrap_or_else(PoisonError::into_inner) = Some(present);
+			}
+			let output = self.scripted.execute(cmd)?;
+			if argv[1..3] == ["snapshot", "restore"] {
+				let data_dir = argv[argv.iter().position(|arg| *arg == "--data-dir").unwrap() + 1];
+				let db = Path::new(data_dir).join("member/snap/db");
+				fs::create_dir_all(db.parent().unwrap()).unwrap();
+				fs::write(db, "").unwrap();
+			}
+			Ok(output)
+		}
+	}
+
+	const SCRIPT: &str = r#"
+		[[command]]
+		match = "ctr image pull"
+		[[command]]
+		match = "bash -c"
+		[[command]]
+		match = "bash -c"
+		[[command]]
+		match = "/usr/local/bin/etcdutl snapshot status"
+		stdout = '{"hash":3925164733,"revision":48121,"totalKey":1211,"totalSize":5230592}'
+		[[command]]
+		match = "/usr/local/bin/etcdutl snapshot restore"
+		[[command]]
+		match = "ctr run"
+		stdout = "apiVersion: v1\nkind: Pod\n"
+		[[command]]
+		match = "kubeadm init"
+	"#;
+
+	#[test]
+	fn rebuild_restores_the_ca_before_kubeadm_init() {
+		testing::init();
+		let dir = env::temp_dir().join(format!("rebuild-root-{}", process::id()));
+		let _ = fs::remove_dir_all(&dir);
+		let backup = dir.join("backup");
+		for name in ControlPlane::CA_FILES {
+			let path = backup.join(name);
+			fs::create_dir_all(path.parent().unwrap()).unwrap();
+			fs::write(path, *name).unwrap();
+		}
+		let runner: &'static Rebuild = Box::leak(Box::new(Rebuild {
+			scripted: ScriptedRunner::parse(SCRIPT).unwrap(),
+			pki: dir.join("pki"),
+			ca_at_init: Mutex::new(None),
+		}));
+		let installed = testing::install(runner);
+		let paths = RootPaths {
+			pki: &dir.join("pki"),
+			etcd_data: &dir.join("etcd"),
+			kube_vip_manifest: &dir.join("kube-vip.yaml"),
+		};
+		let snapshot = dir.join("etcd-cp-1-20260101T000000Z.db");
+		rebuild_root(&snapshot, &read_ca(&backup).unwrap(), &paths).unwrap();
+		drop(installed);
+
+		let recorded = runner.scripted.recorded();
+		let commands = recorded
+			.iter()
+			.map(|cmd| cmd.split_whitespace().take(3).collect::<Vec<_>>().join(" "))
+			.collect::<Vec<_>>();
+		assert_eq!(
+			commands,
+			[
+				"ctr image pull",
+				"bash -c set",
+				"bash -c set",
+				"/usr/local/bin/etcdutl snapshot status",
+				"/usr/local/bin/etcdutl snapshot restore",
+				"ctr run --rm",
+				"kubeadm init --control-plane-endpoint",
+			]
+		);
+		assert!(recorded[1].contains("cilium-cli"));
+		assert!(recorded[2].contains("kubeadm reset --force"));
+		assert!(recorded[4].contains(&format!(
+			"{} --data-dir {} --name cp-1",
+			snapshot.display(),
+			dir.join("etcd").display()
+		)));
+		assert!(recorded[6].ends_with(
+			"--ignore-preflight-errors=NumCPU,Mem,DirAvailable--var-lib-etcd --skip-phases=addon/kube-proxy"
+		));
+		assert_eq!(
+			*runner.ca_at_init.lock().unwrap(),
+			Some(vec![true; ControlPlane::CA_FILES.len()])
+		);
+		let key_mode = fs::metadata(dir.join("pki/etcd/ca.key"))
+			.unwrap()
+			.permissions()
+			.mode();
+		assert_eq!(key_mode & 0o777, 0o600);
+		assert_eq!(
+			fs::read_to_string(dir.join("pki/sa.pub")).unwrap(),
+			"sa.pub"
+		);
+		assert_eq!(
+			fs::read_to_string(dir.join("kube-vip.yaml")).unwrap(),
+			"apiVersion: v1\nkind: Pod\n"
+		);
+		fs::remove_dir_all(&dir).unwrap();
+	}
+}
@@ -1,6 +1,7 @@
 use crate::error::InstallError;
-use crate::setup::SetupStep;
-use std::{fs, process::Command};
+use crate::setup::utils::cmd::Cmd;
+use crate::setup::{Check, SetupStep};
+use std::fs;
 use tracing::info;
 
 pub struct DisableSwap;
@@ -10,15 +11,13 @@ impl SetupStep for DisableSwap {
 		"DisableSwap"
 	}
 
-	fn check(&self) -> Result<bool, InstallError> {
+	fn check(&self) -> Result<Check, InstallError> {
 		let is_swap_on = fs::read_to_string("/proc/swaps")?.lines().count() > 1;
 		if is_swap_on {
-			info!("Swap is enabled.");
-			return Ok(false);
+			return Ok(Check::drift("Swap is enabled."));
 		}
 		let Ok(config_txt) = fs::read_to_string("/etc/fstab") else {
-			info!("fstab is missing or unreadable.");
-			return Ok(false);
+			return Ok(Check::drift("fstab is missing or unreadable."));
 		};
 		let is_configured = config_txt
 			.lines()
@@ -28,34 +27,13 @@ impl SetupStep for DisableSwap {
 				fields.len() >= 3 && fields[2] == "swap"
 			});
 		if is_configured {
-			info!("Swap is enabled in fstab.");
-			return Ok(false);
+			return Ok(Check::drift("Swap is enabled in fstab."));
 		}
-		Ok(true)
+		Ok(Check::Satisfied)
 	}
 
 	fn set(&self) -> Result<(), InstallError> {
-		let output = Command::new("swapoff")
-			.arg("-a")
-			.output()
-			.map_err(|source| InstallError::CommandLaunch {
-				cmd: "swapoff -a".to_owned(),
-				source,
-			})?;
-		let status = output.status;
-		if !status.success() {
-			let stderr = if output.stderr.is_empty() {
-				None
-			} else {
-				Some(String::from_utf8_lossy(&output.stderr).trim().to_owned())
-			};
-
-			return Err(InstallError::CommandFailed {
-				cmd: "swapoff -a".to_owned(),
-				status,
-				stderr,
-			});
-		}
+		Cmd::new("swapoff").arg("-a").run()?;
 		let config_path = "/etc/fstab";
 		let original = fs::read_to_string(config_path)?;
 		let cleaned = original
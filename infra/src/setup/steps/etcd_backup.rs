@@ -0,0 +1,494 @@
+use crate::config;
+use crate::context;
+use crate::error::InstallError;
+use crate::setup::utils::systemd::{BINARY_PATH, UNIT_DIR};
+use crate::setup::utils::{cmd::Cmd, inventory};
+use crate::setup::{Check, SetupStep};
+use jiff::Timestamp;
+use serde::Deserialize;
+use std::{
+	env, fs,
+	os::unix::fs::PermissionsExt,
+	path::{Path, PathBuf},
+	time::{Duration, SystemTime},
+};
+use tracing::info;
+
+pub struct EtcdBackup;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SnapshotStatus {
+	pub hash: u32,
+	pub revision: i64,
+	pub total_key: i64,
+}
+
+impl EtcdBackup {
+	pub const RELEASE_URL: &str = "https://github.com/etcd-io/etcd/releases/download/{VERSION}";
+	pub const ETCDCTL_PATH: &str = "/usr/local/bin/etcdctl";
+	pub const ETCDUTL_PATH: &str = "/usr/local/bin/etcdutl";
+	pub const ENDPOINT: &str = "https://127.0.0.1:2379";
+	pub const PKI_DIR: &str = "/etc/kubernetes/pki/etcd";
+	pub const SERVICE_NAME: &str = "8inary-etcd-backup.service";
+	pub const TIMER_NAME: &str = "8inary-etcd-backup.timer";
+	pub const SNAPSHOT_TIMEOUT: Duration = Duration::from_secs(300);
+	// The timer runs daily, a missed day is tolerated before the step drifts.
+	pub const MAX_AGE: Duration = Duration::from_secs(2 * 24 * 60 * 60);
+
+	fn service_unit() -> String {
+		let backup = &config::get().etcd_backup;
+		format!(
+			r#"[Unit]
+Description=8inary etcd snapshot
+Wants=network-online.target
+After=network-online.target
+
+[Service]
+Type=oneshot
+ExecStart={} backup --dir {} --retention {}
+"#,
+			BINARY_PATH, backup.dir, backup.retention
+		)
+	}
+
+	fn timer_unit() -> String {
+		format!(
+			r#"[Unit]
+Description=Take an 8inary etcd snapshot daily
+
+[Timer]
+OnCalendar=daily
+RandomizedDelaySec=1h
+Persistent=true
+Unit={}
+
+[Install]
+WantedBy=timers.target
+"#,
+			EtcdBackup::SERVICE_NAME
+		)
+	}
+
+	fn units() -> [(PathBuf, String); 2] {
+		let unit_dir = Path::new(UNIT_DIR);
+		[
+			(
+				unit_dir.join(EtcdBackup::SERVICE_NAME),
+				EtcdBackup::service_unit(),
+			),
+			(
+				unit_dir.join(EtcdBackup::TIMER_NAME),
+				EtcdBackup::timer_unit(),
+			),
+		]
+	}
+
+	pub fn install_tools() -> Result<(), InstallError> {
+		let version = &config::get().etcd_backup.etcd_version;
+		info!("Installing etcdctl and etcdutl {}.", version);
+		Cmd::bash(format!(
+			r#"
+				set -euo pipefail
+				cd /tmp
+				BASE="{}"
+				curl -fsSL --location "$BASE/etcd-{version}-linux-amd64.tar.gz" -o etcd-{version}-linux-amd64.tar.gz
+				curl -fsSL --location "$BASE/SHA256SUMS" -o etcd-{version}-SHA256SUMS
+				grep " etcd-{version}-linux-amd64.tar.gz$" etcd-{version}-SHA256SUMS | sha256sum --check
+				tar xzf etcd-{version}-linux-amd64.tar.gz --strip-components=1 etcd-{version}-linux-amd64/etcdctl etcd-{version}-linux-amd64/etcdutl
+				sudo install -m 0755 etcdctl {}
+				sudo install -m 0755 etcdutl {}
+				rm -f etcd-{version}-linux-amd64.tar.gz etcd-{version}-SHA256SUMS etcdctl etcdutl
+			"#,
+			EtcdBackup::RELEASE_URL.replace("{VERSION}", version),
+			EtcdBackup::ETCDCTL_PATH,
+			EtcdBackup::ETCDUTL_PATH,
+		))
+		.run()
+	}
+
+	pub fn tools_version() -> Result<Option<String>, InstallError> {
+		if !Path::new(EtcdBackup::ETCDUTL_PATH).exists() {
+			return Ok(None);
+		}
+		let output = Cmd::new(EtcdBackup::ETCDUTL_PATH).arg("version").probe()?;
+		Ok(output
+			.stdout
+			.lines()
+			.find_map(|line| line.strip_prefix("etcdutl version:"))
+			.map(|version| format!("v{}", version.trim())))
+	}
+}
+
+// Snapshots sort by name, the UTC timestamp in it orders them by age.
+fn snapshots(dir: &Path) -> Result<Vec<PathBuf>, InstallError> {
+	if !dir.exists() {
+		return Ok(Vec::new());
+	}
+	let mut snapshots = fs::read_dir(dir)?
+		.map(|entry| entry.map(|entry| entry.path()))
+		.collect::<Result<Vec<_>, _>>()?;
+	snapshots.retain(|path| {
+		path.file_name()
+			.and_then(|name| name.to_str())
+			.is_some_and(|name| name.starts_with("etcd-") && name.ends_with(".db"))
+	});
+	snapshots.sort();
+	Ok(snapshots)
+}
+
+pub fn verify(path: &Path) -> Result<SnapshotStatus, InstallError> {
+	let output = Cmd::new(EtcdBackup::ETCDUTL_PATH)
+		.args(["snapshot", "status"])
+		.arg(path)
+		.args(["--write-out", "json"])
+		.output()?;
+	let status: SnapshotStatus = serde_json::from_str(output.stdout.trim()).map_err(|err| {
+		InstallError::Kube(format!("Unreadable status of {}: {err}", path.display()))
+	})?;
+	if status.total_key <= 0 {
+		return Err(InstallError::Kube(format!(
+			"Snapshot {} holds no keys.",
+			path.display()
+		)));
+	}
+	Ok(status)
+}
+
+// Written under a temporary name and only renamed once verified, rotation never counts a bad snapshot.
+pub fn snapshot(dir: &Path, retention: usize) -> Result<PathBuf, InstallError> {
+	fs::create_dir_all(dir)?;
+	fs::set_permissions(dir, fs::Permissions::from_mode(0o700))?;
+	let name = format!(
+		"etcd-{}-{}.db",
+		context::get().hostname,
+		Timestamp::now().strftime("%Y%m%dT%H%M%S%.6fZ")
+	);
+	let path = dir.join(&name);
+	let tmp_path = dir.join(format!("{name}.tmp"));
+	info!("Taking etcd snapshot {}.", path.display());
+	let pki = Path::new(EtcdBackup::PKI_DIR);
+	Cmd::new(EtcdBackup::ETCDCTL_PATH)
+		.args(["--endpoints", EtcdBackup::ENDPOINT])
+		.arg("--cacert")
+		.arg(pki.join("ca.crt"))
+		.arg("--cert")
+		.arg(pki.join("healthcheck-client.crt"))
+		.arg("--key")
+		.arg(pki.join("healthcheck-client.key"))
+		.args(["snapshot", "save"])
+		.arg(&tmp_path)
+		.timeout(EtcdBackup::SNAPSHOT_TIMEOUT)
+		.run()?;
+	let status = match verify(&tmp_path) {
+		Ok(status) => status,
+		Err(err) => {
+			let _ = fs::remove_file(&tmp_path);
+			return Err(err);
+		}
+	};
+	// The timer and a manual backup can race, a snapshot is never replaced.
+	if path.exists() {
+		let _ = fs::remove_file(&tmp_path);
+		return Err(InstallError::Kube(format!(
+			"Snapshot {} already exists.",
+			path.display()
+		)));
+	}
+	fs::rename(&tmp_path, &path)?;
+	info!(
+		"Snapshot verified, revision {}, {} keys, hash {:08x}.",
+		status.revision, status.total_key, status.hash
+	);
+	let snapshots = snapshots(dir)?;
+	let excess = snapshots.len().saturating_sub(retention);
+	for old in &snapshots[..excess] {
+		info!("Removing snapshot {} past retention.", old.display());
+		fs::remove_file(old)?;
+	}
+	Ok(path)
+}
+
+pub fn ensure_tools() -> Result<(), InstallError> {
+	let version = &config::get().etcd_backup.etcd_version;
+	if EtcdBackup::tools_version()?.as_ref() != Some(version) {
+		EtcdBackup::install_tools()?;
+	}
+	Ok(())
+}
+
+// The restored member takes the name and peer URL kubeadm init gives the root, etcd then starts on the restored data.
+pub fn restore_data(snapshot: &Path, data_dir: &Path) -> Result<(), InstallError> {
+	let status = verify(snapshot)?;
+	if data_dir.exists() {
+		if fs::read_dir(data_dir)?.next().is_none() {
+			fs::remove_dir(data_dir)?;
+		} else {
+			let mut aside = data_dir.as_os_str().to_owned();
+			aside.push(format!(
+				".before-restore-{}",
+				Timestamp::now().strftime("%Y%m%dT%H%M%S%.6fZ")
+			));
+			info!(
+				"Moving {} aside to {}.",
+				data_dir.display(),
+				aside.to_string_lossy()
+			);
+			fs::rename(data_dir, &aside)?;
+		}
+	}
+	let name = &context::get().hostname;
+	let peer_url = format!("https://{}:2380", config::get().kube_vip.address);
+	info!(
+		"Restoring snapshot {} at revision {} into {}.",
+		snapshot.display(),
+		status.revision,
+		data_dir.display()
+	);
+	// Bumping the revision past anything clients watched makes their caches relist.
+	Cmd::new(EtcdBackup::ETCDUTL_PATH)
+		.args(["snapshot", "restore"])
+		.arg(snapshot)
+		.arg("--data-dir")
+		.arg(data_dir)
+		.args(["--name", name])
+		.args(["--initial-cluster", &format!("{name}={peer_url}")])
+		.args(["--initial-advertise-peer-urls", &peer_url])
+		.args(["--bump-revision", "1000000000", "--mark-compacted"])
+		.timeout(EtcdBackup::SNAPSHOT_TIMEOUT)
+		.run()?;
+	let db = data_dir.join("member/snap/db");
+	if !db.exists() {
+		return Err(InstallError::Kube(format!(
+			"Restore left no database at {}.",
+			db.display()
+		)));
+	}
+	fs::set_permissions(data_dir, fs::Permissions::from_mode(0o700))?;
+	info!("etcd data restored into {}.", data_dir.display());
+	Ok(())
+}
+
+impl SetupStep for EtcdBackup {
+	fn name(&self) -> &'static str {
+		"EtcdBackup"
+	}
+
+	fn requires(&self) -> &'static [&'static str] {
+		&["ControlPlane"]
+	}
+
+	fn check(&self) -> Result<Check, InstallError> {
+		if !inventory::this()?.role.runs_etcd() {
+			info!("This machine runs no etcd member, nothing to back up.");
+			return Ok(Check::Satisfied);
+		}
+		let version = &config::get().etcd_backup.etcd_version;
+		match EtcdBackup::tools_version()? {
+			Some(installed) if installed == *version => {}
+			installed => {
+				return Ok(Check::drift(format!(
+					"etcdutl {} is installed, {} is configured.",
+					installed.as_deref().unwrap_or("nothing"),
+					version
+				)));
+			}
+		}
+		for (path, unit) in EtcdBackup::units() {
+			if fs::read_to_string(&path).ok().as_deref() != Some(unit.as_str()) {
+				return Ok(Check::drift(format!(
+					"{} is not up to date.",
+					path.display()
+				)));
+			}
+		}
+		let is_enabled = Cmd::new("systemctl")
+			.args(["is-enabled", "--quiet", EtcdBackup::TIMER_NAME])
+			.probe()?
+			.success();
+		if !is_enabled {
+			return Ok(Check::drift(format!(
+				"{} is not enabled.",
+				EtcdBackup::TIMER_NAME
+			)));
+		}
+		let dir = &config::get().etcd_backup.dir;
+		let Some(latest) = snapshots(Path::new(dir))?.pop() else {
+			return Ok(Check::drift(format!("No etcd snapshot in {dir}.")));
+		};
+		let age = SystemTime::now()
+			.duration_since(fs::metadata(&latest)?.modified()?)
+			.unwrap_or_default();
+		if age > EtcdBackup::MAX_AGE {
+			return Ok(Check::drift(format!(
+				"Latest etcd snapshot {} is {}h old.",
+				latest.display(),
+				age.as_secs() / 3600
+			)));
+		}
+		info!("etcd is backed up, latest snapshot {}.", latest.display());
+		Ok(Check::Satisfied)
+	}
+
+	fn set(&self) -> Result<(), InstallError> {
+		EtcdBackup::install_tools()?;
+		info!(
+			"Installing {} as {}.",
+			env::current_exe()?.display(),
+			BINARY_PATH
+		);
+		Cmd::new("install")
+			.args(["-m", "0755"])
+			.arg(env::current_exe()?)
+			.arg(BINARY_PATH)
+			.run()?;
+		for (path, unit) in EtcdBackup::units() {
+			fs::write(path, unit)?;
+		}
+		Cmd::new("systemctl").arg("daemon-reload").run()?;
+		Cmd::new("systemctl")
+			.args(["enable", "--now", EtcdBackup::TIMER_NAME])
+			.run()?;
+		info!("etcd backup timer {} is enabled.", EtcdBackup::TIMER_NAME);
+		let backup = &config::get().etcd_backup;
+		snapshot(Path::new(&backup.dir), backup.retention)?;
+		Ok(())
+	}
+
+	fn unset(&self) -> Result<(), InstallError> {
+		if !inventory::this()?.role.runs_etcd() {
+			return Ok(());
+		}
+		Cmd::new("systemctl")
+			.args(["disable", "--now", EtcdBackup::TIMER_NAME])
+			.probe()?;
+		for path in EtcdBackup::units()
+			.into_iter()
+			.map(|(path, _)| path)
+			.chain([
+				PathBuf::from(EtcdBackup::ETCDCTL_PATH),
+				PathBuf::from(EtcdBackup::ETCDUTL_PATH),
+			]) {
+			if path.exists() {
+				fs::remove_file(path)?;
+			}
+		}
+		Cmd::new("systemctl").arg("daemon-reload").run()?;
+		info!("Snapshots in {} are kept.", config::get().etcd_backup.dir);
+		Ok(())
+	}
+
+	fn versions(&self) -> Vec<(&'static str, String)> {
+		vec![("etcd", config::get().etcd_backup.etcd_version.clone())]
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::setup::testing;
+	use crate::setup::utils::cmd::{Output, Runner, ScriptedRunner};
+	use std::process;
+
+	// Replays the script and writes the files etcdctl and etcdutl would leave behind.
+	struct Etcd {
+		scripted: ScriptedRunner,
+	}
+
+	impl Runner for Etcd {
+		fn execute(&self, cmd: &Cmd) -> Result<Output, InstallError> {
+			let argv = cmd.argv();
+			let output = self.scripted.execute(cmd)?;
+			if let [.., "snapshot", "save", path] = argv[..] {
+				fs::write(path, "snapshot").unwrap();
+			}
+			if argv[1..3] == ["snapshot", "restore"] {
+				let data_dir = argv[argv.iter().position(|arg| *arg == "--data-dir").unwrap() + 1];
+				let db = Path::new(data_dir).join("member/snap/db");
+				fs::create_dir_all(db.parent().unwrap()).unwrap();
+				fs::write(db, "").unwrap();
+			}
+			Ok(output)
+		}
+	}
+
+	const STATUS: &str = r#"
+		[[command]]
+		match = "/usr/local/bin/etcdutl snapshot status"
+		stdout = '{"hash":3925164733,"revision":48121,"totalKey":1211,"totalSize":5230592}'
+	"#;
+
+	fn scratch(name: &str) -> PathBuf {
+		let dir = env::temp_dir().join(format!("etcd-backup-{name}-{}", process::id()));
+		let _ = fs::remove_dir_all(&dir);
+		fs::create_dir_all(&dir).unwrap();
+		dir
+	}
+
+	#[test]
+	fn snapshot_rotates_the_oldest_past_retention() {
+		testing::init();
+		let dir = scratch("rotate");
+		for name in [
+			"etcd-cp-1-20250103T000000.000000Z.db",
+			"etcd-cp-1-20250101T000000.000000Z.db",
+			"etcd-cp-1-20250102T000000.000000Z.db",
+			"etcd-cp-1-20250104T000000.000000Z.db.tmp",
+			"notes.txt",
+		] {
+			fs::write(dir.join(name), "").unwrap();
+		}
+		let runner: &'static Etcd = Box::leak(Box::new(Etcd {
+			scripted: ScriptedRunner::parse(&format!(
+				"[[command]]\nmatch = \"/usr/local/bin/etcdctl\"\n{STATUS}"
+			))
+			.unwrap(),
+		}));
+		let installed = testing::install(runner);
+		let path = snapshot(&dir, 2).unwrap();
+		drop(installed);
+
+		assert_eq!(
+			snapshots(&dir).unwrap(),
+			[dir.join("etcd-cp-1-20250103T000000.000000Z.db"), path]
+		);
+		assert!(
+			dir.join("etcd-cp-1-20250104T000000.000000Z.db.tmp")
+				.exists()
+		);
+		assert!(dir.join("notes.txt").exists());
+		fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn restore_moves_existing_data_aside() {
+		testing::init();
+		let dir = scratch("restore");
+		let data_dir = dir.join("etcd");
+		fs::create_dir_all(data_dir.join("member")).unwrap();
+		fs::write(data_dir.join("member/old"), "old").unwrap();
+		let runner: &'static Etcd = Box::leak(Box::new(Etcd {
+			scripted: ScriptedRunner::parse(&format!(
+				"{STATUS}\n[[command]]\nmatch = \"/usr/local/bin/etcdutl snapshot restore\""
+			))
+			.unwrap(),
+		}));
+		let installed = testing::install(runner);
+		restore_data(&dir.join("etcd-cp-1-20250101T000000.000000Z.db"), &data_dir).unwrap();
+		drop(installed);
+
+		assert!(data_dir.join("member/snap/db").exists());
+		assert!(!data_dir.join("member/old").exists());
+		let aside = fs::read_dir(&dir)
+			.unwrap()
+			.map(|entry| entry.unwrap().file_name().into_string().unwrap())
+			.filter(|name| name.starts_with("etcd.before-restore-"))
+			.collect::<Vec<_>>();
+		assert_eq!(aside.len(), 1);
+		assert_eq!(
+			fs::read_to_string(dir.join(&aside[0]).join("member/old")).unwrap(),
+			"old"
+		);
+		fs::remove_dir_all(&dir).unwrap();
+	}
+}
@@ -0,0 +1,291 @@
+use crate::config::{self, FirewallBackend};
+use crate::error::InstallError;
+use crate::setup::utils::{
+	cmd::Cmd,
+	inventory::{self, MachineRole},
+	pkg,
+};
+use crate::setup::{Check, SetupStep};
+use std::fmt;
+use tracing::info;
+
+pub mod nftables;
+pub mod ufw;
+
+pub use nftables::Nftables;
+pub use ufw::Ufw;
+
+#[derive(Debug, Clone)]
+pub struct Firewall;
+
+// One allow rule, as configured here or as parsed back from the backend.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FirewallRule<'a> {
+	from: &'a str,
+	port: Option<&'a str>,
+	protocol: Option<&'a str>,
+	comment: &'a str,
+}
+
+// Live rules are matched to configured ones by comment and protocol, the nodeport range has one of each.
+#[derive(Debug, Default)]
+pub struct RuleDiff<'a> {
+	pub missing: Vec<FirewallRule<'a>>,
+	pub extra: Vec<FirewallRule<'a>>,
+	pub changed: Vec<(FirewallRule<'a>, FirewallRule<'a>)>,
+}
+
+impl FirewallRule<'static> {
+	const fn allow(port: &'static str, protocol: &'static str, comment: &'static str) -> Self {
+		FirewallRule {
+			from: "any",
+			port: Some(port),
+			protocol: Some(protocol),
+			comment,
+		}
+	}
+}
+
+impl FirewallRule<'_> {
+	fn is_same_rule(&self, other: &FirewallRule<'_>) -> bool {
+		self.comment == other.comment && self.protocol == other.protocol
+	}
+}
+
+impl fmt::Display for FirewallRule<'_> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(
+			f,
+			"'{}' {}/{} from {}",
+			self.comment,
+			self.port.unwrap_or("any"),
+			self.protocol.unwrap_or("any"),
+			self.from
+		)
+	}
+}
+
+impl<'a> RuleDiff<'a> {
+	// Exact matches are taken first, so a duplicate of a configured rule reads as extra.
+	pub fn new(wanted: &[FirewallRule<'a>], live: &[FirewallRule<'a>]) -> RuleDiff<'a> {
+		let mut diff = RuleDiff::default();
+		let mut unmatched = live.to_vec();
+		let mut unmatched_wanted = Vec::new();
+		for rule in wanted {
+			match unmatched.iter().position(|live| live == rule) {
+				Some(idx) => {
+					unmatched.remove(idx);
+				}
+				None => unmatched_wanted.push(*rule),
+			}
+		}
+		for rule in unmatched_wanted {
+			match unmatched.iter().position(|live| live.is_same_rule(&rule)) {
+				Some(idx) => diff.changed.push((unmatched.remove(idx), rule)),
+				None => diff.missing.push(rule),
+			}
+		}
+		diff.extra = unmatched;
+		diff
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.missing.is_empty() && self.extra.is_empty() && self.changed.is_empty()
+	}
+}
+
+impl fmt::Display for RuleDiff<'_> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let mut parts = Vec::new();
+		for rule in &self.missing {
+			parts.push(format!("missing {rule}"));
+		}
+		for rule in &self.extra {
+			parts.push(format!("extra {rule}"));
+		}
+		for (live, wanted) in &self.changed {
+			parts.push(format!("{live} should be {wanted}"));
+		}
+		write!(f, "{}", parts.join(", "))
+	}
+}
+
+impl Firewall {
+	pub const CONTROL_PLANE_RULES: &[FirewallRule<'static>] = &[
+		FirewallRule::allow("2379", "tcp", "etcd client"),
+		FirewallRule::allow("2380", "tcp", "etcd peer"),
+		FirewallRule::allow("4240", "tcp", "cilium health"),
+		FirewallRule::allow("6443", "tcp", "kube-apiserver"),
+		FirewallRule::allow("8472", "udp", "cilium vxlan"),
+		FirewallRule::allow("10250", "tcp", "kubelet"),
+		FirewallRule::allow("10257", "tcp", "controller-manager"),
+		FirewallRule::allow("10259", "tcp", "scheduler"),
+	];
+	pub const WORKER_RULES: &[FirewallRule<'static>] = &[
+		FirewallRule::allow("4240", "tcp", "cilium health"),
+		FirewallRule::allow("8472", "udp", "cilium vxlan"),
+		FirewallRule::allow("10250", "tcp", "kubelet"),
+		FirewallRule::allow("30000:32767", "tcp", "nodeport"),
+		FirewallRule::allow("30000:32767", "udp", "nodeport"),
+	];
+
+	// The configured rules, allowed from the cluster's source CIDR.
+	fn rules() -> Result<Vec<FirewallRule<'static>>, InstallError> {
+		let rules = match inventory::this()?.role {
+			MachineRole::ControlPlaneRoot | MachineRole::ControlPlane => {
+				Firewall::CONTROL_PLANE_RULES
+			}
+			MachineRole::Worker => Firewall::WORKER_RULES,
+		};
+		let from = &config::get().firewall.source_cidr;
+		Ok(rules
+			.iter()
+			.map(|rule| FirewallRule { from, ..*rule })
+			.collect())
+	}
+}
+
+// Both backends manage only the rules they created, other firewall configuration on the host is left alone.
+pub trait Backend: Sync {
+	fn name(&self) -> &'static str;
+	fn check(&self, rules: &[FirewallRule<'_>]) -> Result<Check, InstallError>;
+	fn set(&self, rules: &[FirewallRule<'_>]) -> Result<(), InstallError>;
+	fn unset(&self, rules: &[FirewallRule<'_>]) -> Result<(), InstallError>;
+}
+
+// An active ufw drops whatever it does not allow, rules in a table of our own could not open those ports.
+fn ufw_is_active() -> Result<bool, InstallError> {
+	if !pkg::is_installed(Ufw::PACKAGE_NAME)? {
+		return Ok(false);
+	}
+	let output = Cmd::new("ufw").arg("status").probe()?;
+	Ok(output.success() && output.stdout.lines().any(|line| line == "Status: active"))
+}
+
+pub fn backend() -> Result<&'static dyn Backend, InstallError> {
+	let backend: &'static dyn Backend = match config::get().firewall.backend {
+		FirewallBackend::Nftables => &Nftables,
+		FirewallBackend::Ufw => &Ufw,
+		FirewallBackend::Auto if ufw_is_active()? => &Ufw,
+		FirewallBackend::Auto => &Nftables,
+	};
+	info!("Using the {} firewall backend.", backend.name());
+	Ok(backend)
+}
+
+impl SetupStep for Firewall {
+	fn name(&self) -> &'static str {
+		"Firewall"
+	}
+
+	fn check(&self) -> Result<Check, InstallError> {
+		backend()?.check(&Firewall::rules()?)
+	}
+
+	fn set(&self) -> Result<(), InstallError> {
+		backend()?.set(&Firewall::rules()?)
+	}
+
+	fn unset(&self) -> Result<(), InstallError> {
+		backend()?.unset(&Firewall::rules()?)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const SOURCE: &str = "192.168.0.0/16";
+
+	fn wanted() -> Vec<FirewallRule<'static>> {
+		Firewall::WORKER_RULES
+			.iter()
+			.map(|rule| FirewallRule {
+				from: SOURCE,
+				..*rule
+			})
+			.collect()
+	}
+
+	#[test]
+	fn matching_rules_are_empty() {
+		let mut live = wanted();
+		live.reverse();
+		assert!(RuleDiff::new(&wanted(), &live).is_empty());
+	}
+
+	#[test]
+	fn duplicate_live_rule_is_extra() {
+		let mut live = wanted();
+		live.push(live[2]);
+		let diff = RuleDiff::new(&wanted(), &live);
+		assert!(diff.missing.is_empty());
+		assert!(diff.changed.is_empty());
+		assert_eq!(diff.extra, [live[2]]);
+		assert_eq!(
+			diff.to_string(),
+			"extra 'kubelet' 10250/tcp from 192.168.0.0/16"
+		);
+	}
+
+	#[test]
+	fn nodeport_protocols_are_separate_rules() {
+		let live = wanted()
+			.into_iter()
+			.filter(|rule| !(rule.comment == "nodeport" && rule.protocol == Some("udp")))
+			.collect::<Vec<_>>();
+		let diff = RuleDiff::new(&wanted(), &live);
+		assert!(diff.extra.is_empty());
+		assert!(diff.changed.is_empty());
+		assert_eq!(
+			diff.missing,
+			[FirewallRule {
+				from: SOURCE,
+				..FirewallRule::allow("30000:32767", "udp", "nodeport")
+			}]
+		);
+	}
+
+	#[test]
+	fn changed_source_pairs_live_with_wanted() {
+		let live = wanted()
+			.into_iter()
+			.map(|rule| match rule.comment {
+				"kubelet" => FirewallRule {
+					from: "10.10.0.0/16",
+					..rule
+				},
+				_ => rule,
+			})
+			.collect::<Vec<_>>();
+		let diff = RuleDiff::new(&wanted(), &live);
+		assert!(diff.missing.is_empty());
+		assert!(diff.extra.is_empty());
+		assert_eq!(diff.changed.len(), 1);
+		let (live, wanted) = diff.changed[0];
+		assert_eq!((live.from, wanted.from), ("10.10.0.0/16", SOURCE));
+		assert_eq!(
+			diff.to_string(),
+			"'kubelet' 10250/tcp from 10.10.0.0/16 should be 'kubelet' 10250/tcp from 192.168.0.0/16"
+		);
+	}
+
+	#[test]
+	fn changed_port_on_one_nodeport_protocol() {
+		let live = wanted()
+			.into_iter()
+			.map(|rule| match (rule.comment, rule.protocol) {
+				("nodeport", Some("tcp")) => FirewallRule {
+					port: Some("30000:31000"),
+					..rule
+				},
+				_ => rule,
+			})
+			.collect::<Vec<_>>();
+		let diff = RuleDiff::new(&wanted(), &live);
+		assert!(diff.missing.is_empty() && diff.extra.is_empty());
+		assert_eq!(diff.changed.len(), 1);
+		assert_eq!(diff.changed[0].1.protocol, Some("tcp"));
+		assert_eq!(diff.changed[0].0.port, Some("30000:31000"));
+	}
+}
@@ -0,0 +1,450 @@
+use super::{Backend, FirewallRule};
+use crate::config;
+use crate::error::InstallError;
+use crate::setup::Check;
+use crate::setup::utils::{cmd::Cmd, pkg, systemd::UNIT_DIR};
+use serde_json::Value;
+use std::{fs, net::IpAddr, path::Path};
+use tracing::info;
+
+pub struct Nftables;
+
+impl Nftables {
+	pub const PACKAGE_NAME: &str = "nftables";
+	pub const TABLE: &str = "eightinary";
+	pub const RULESET_PATH: &str = "/etc/8inary/firewall.nft";
+	pub const SERVICE_NAME: &str = "8inary-firewall.service";
+
+	// The rules as `nft list` prints them, which is also how the live table is compared.
+	// Only the managed ports are closed to other sources, everything else keeps the host's policy.
+	fn rule_lines(rules: &[FirewallRule<'_>]) -> Result<Vec<String>, InstallError> {
+		let pod_network = saddr(&config::get().kubernetes.pod_cidr)?;
+		let mut lines = vec![r#"iifname "lo" accept"#.to_owned()];
+		for rule in rules {
+			let mut parts = Vec::new();
+			if rule.from != "any" {
+				parts.push(saddr(rule.from)?);
+			}
+			if let (Some(protocol), Some(port)) = (rule.protocol, rule.port) {
+				parts.push(format!("{protocol} dport {}", nft_port(port)));
+			}
+			parts.push(format!(r#"accept comment "8inary: {}""#, rule.comment));
+			lines.push(parts.join(" "));
+		}
+		lines.push(format!(
+			r#"{pod_network} accept comment "8inary: pod network""#
+		));
+		for protocol in ["tcp", "udp"] {
+			let ports = rules
+				.iter()
+				.filter(|rule| rule.protocol == Some(protocol))
+				.filter_map(|rule| rule.port.map(nft_port))
+				.collect::<Vec<_>>();
+			if !ports.is_empty() {
+				lines.push(format!(
+					r#"{protocol} dport {{ {} }} drop comment "8inary: other sources""#,
+					ports.join(", ")
+				));
+			}
+		}
+		Ok(lines)
+	}
+
+	// Declaring the table first lets the delete succeed on the first load, nft applies the whole file as one transaction.
+	fn ruleset(rules: &[FirewallRule<'_>]) -> Result<String, InstallError> {
+		let table = Nftables::TABLE;
+		let lines = Nftables::rule_lines(rules)?
+			.iter()
+			.map(|line| format!("\t\t{line}\n"))
+			.collect::<String>();
+		Ok(format!(
+			"table inet {table}\ndelete table inet {table}\ntable inet {table} {{\n\tchain input {{\n\t\ttype filter hook input priority filter; policy accept;\n{lines}\t}}\n}}\n"
+		))
+	}
+
+	fn service_unit() -> String {
+		format!(
+			r#"[Unit]
+Description=8inary nftables table
+After=nftables.service
+Before=kubelet.service
+
+[Service]
+Type=oneshot
+RemainAfterExit=yes
+ExecStart=/usr/sbin/nft -f {}
+ExecStop=/usr/sbin/nft delete table inet {}
+
+[Install]
+WantedBy=multi-user.target
+"#,
+			Nftables::RULESET_PATH,
+			Nftables::TABLE
+		)
+	}
+
+	fn files(rules: &[FirewallRule<'_>]) -> Result<[(String, String); 2], InstallError> {
+		Ok([
+			(Nftables::RULESET_PATH.to_owned(), Nftables::ruleset(rules)?),
+			(
+				Path::new(UNIT_DIR)
+					.join(Nftables::SERVICE_NAME)
+					.display()
+					.to_string(),
+				Nftables::service_unit(),
+			),
+		])
+	}
+
+	fn live_rules() -> Result<Option<Vec<String>>, InstallError> {
+		let output = Cmd::new("nft")
+			.args(["--json", "list", "table", "inet", Nftables::TABLE])
+			.probe()?;
+		if !output.success() {
+			return Ok(None);
+		}
+		let ruleset: Value =
+			serde_json::from_str(&output.stdout).map_err(|err| InstallError::Other(err.into()))?;
+		Ok(Some(
+			ruleset["nftables"]
+				.as_array()
+				.into_iter()
+				.flatten()
+				.filter_map(|object| object.get("rule"))
+				.map(rule_line)
+				.collect(),
+		))
+	}
+}
+
+// Ranges are written 30000:32767 for ufw, nft wants 30000-32767.
+fn nft_port(port: &str) -> String {
+	port.replace(':', "-")
+}
+
+// nft stores the network address and drops a full length prefix, the rendered rule does the same.
+fn saddr(cidr: &str) -> Result<String, InstallError> {
+	let invalid = || InstallError::Config(format!("'{cidr}' is not a valid CIDR"));
+	let (addr, prefix) = cidr.split_once('/').ok_or_else(invalid)?;
+	let prefix = prefix.parse::<u32>().map_err(|_| invalid())?;
+	let (family, network, bits) = match addr.parse::<IpAddr>().map_err(|_| invalid())? {
+		IpAddr::V4(_) if prefix > 32 => return Err(invalid()),
+		IpAddr::V6(_) if prefix > 128 => return Err(invalid()),
+		IpAddr::V4(addr) => {
+			let mask = u32::MAX.checked_shl(32 - prefix).unwrap_or(0);
+			(
+				"ip",
+				IpAddr::from((u32::from(addr) & mask).to_be_bytes()),
+				32,
+			)
+		}
+		IpAddr::V6(addr) => {
+			let mask = u128::MAX.checked_shl(128 - prefix).unwrap_or(0);
+			(
+				"ip6",
+				IpAddr::from((u128::from(addr) & mask).to_be_bytes()),
+				128,
+			)
+		}
+	};
+	Ok(if prefix == bits {
+		format!("{family} saddr {network}")
+	} else {
+		format!("{family} saddr {network}/{prefix}")
+	})
+}
+
+fn render(value: &Value) -> String {
+	match value {
+		Value::String(string) => string.clone(),
+		Value::Object(object) => {
+			if let Some(prefix) = object.get("prefix") {
+				format!("{}/{}", value_str(&prefix["addr"]), prefix["len"])
+			} else if let Some(Value::Array(range)) = object.get("range") {
+				range.iter().map(render).collect::<Vec<_>>().join("-")
+			} else if let Some(Value::Array(set)) = object.get("set") {
+				format!(
+					"{{ {} }}",
+					set.iter().map(render).collect::<Vec<_>>().join(", ")
+				)
+			} else {
+				value.to_string()
+			}
+		}
+		_ => value.to_string(),
+	}
+}
+
+fn value_str(value: &Value) -> String {
+	value.as_str().map(str::to_owned).unwrap_or_default()
+}
+
+// Renders the statements this backend writes, anything else shows up verbatim and reads as drift.
+fn rule_line(rule: &Value) -> String {
+	let mut parts = Vec::new();
+	for expr in rule["expr"].as_array().into_iter().flatten() {
+		if let Some(test) = expr.get("match") {
+			let left = &test["left"];
+			if let Some(payload) = left.get("payload") {
+				parts.push(format!(
+					"{} {} {}",
+					value_str(&payload["protocol"]),
+					value_str(&payload["field"]),
+					render(&test["right"])
+				));
+			} else if let Some(meta) = left.get("meta") {
+				parts.push(format!(
+					r#"{} "{}""#,
+					value_str(&meta["key"]),
+					render(&test["right"])
+				));
+			} else {
+				parts.push(expr.to_string());
+			}
+		} else if expr.get("accept").is_some() {
+			parts.push("accept".to_owned());
+		} else if expr.get("drop").is_some() {
+			parts.push("drop".to_owned());
+		} else {
+			parts.push(expr.to_string());
+		}
+	}
+	if let Some(comment) = rule["comment"].as_str() {
+		parts.push(format!(r#"comment "{comment}""#));
+	}
+	parts.join(" ")
+}
+
+impl Backend for Nftables {
+	fn name(&self) -> &'static str {
+		"nftables"
+	}
+
+	fn check(&self, rules: &[FirewallRule<'_>]) -> Result<Check, InstallError> {
+		for (path, contents) in Nftables::files(rules)? {
+			if fs::read_to_string(&path).ok().as_deref() != Some(contents.as_str()) {
+				return Ok(Check::drift(format!("{path} is not up to date.")));
+			}
+		}
+		let is_enabled = Cmd::new("systemctl")
+			.args(["is-enabled", "--quiet", Nftables::SERVICE_NAME])
+			.probe()?
+			.success();
+		if !is_enabled {
+			return Ok(Check::drift(format!(
+				"{} is not enabled.",
+				Nftables::SERVICE_NAME
+			)));
+		}
+		let Some(live) = Nftables::live_rules()? else {
+			return Ok(Check::drift(format!(
+				"nftables table inet {} is not loaded.",
+				Nftables::TABLE
+			)));
+		};
+		if live == Nftables::rule_lines(rules)? {
+			info!("Firewall ports are open.");
+			Ok(Check::Satisfied)
+		} else {
+			Ok(Check::drift(format!(
+				"nftables table inet {} differs from {}.",
+				Nftables::TABLE,
+				Nftables::RULESET_PATH
+			)))
+		}
+	}
+
+	fn set(&self, rules: &[FirewallRule<'_>]) -> Result<(), InstallError> {
+		if !pkg::is_installed(Nftables::PACKAGE_NAME)? {
+			pkg::install(&[Nftables::PACKAGE_NAME])?;
+		}
+		fs::create_dir_all(
+			Path::new(Nftables::RULESET_PATH)
+				.parent()
+				.unwrap_or(Path::new("/")),
+		)?;
+		for (path, contents) in Nftables::files(rules)? {
+			fs::write(path, contents)?;
+		}
+		info!(
+			"Loading nftables table inet {} from {}.",
+			Nftables::TABLE,
+			Nftables::RULESET_PATH
+		);
+		Cmd::new("nft").args(["-f", Nftables::RULESET_PATH]).run()?;
+		// The unit only reloads the table at boot, it was loaded above.
+		Cmd::new("systemctl").arg("daemon-reload").run()?;
+		Cmd::new("systemctl")
+			.args(["enable", Nftables::SERVICE_NAME])
+			.run()
+	}
+
+	fn unset(&self, rules: &[FirewallRule<'_>]) -> Result<(), InstallError> {
+		Cmd::new("systemctl")
+			.args(["disable", Nftables::SERVICE_NAME])
+			.probe()?;
+		if pkg::is_installed(Nftables::PACKAGE_NAME)? && Nftables::live_rules()?.is_some() {
+			info!("Deleting nftables table inet {}.", Nftables::TABLE);
+			Cmd::new("nft")
+				.args(["delete", "table", "inet", Nftables::TABLE])
+				.run()?;
+		}
+		for (path, _) in Nftables::files(rules)? {
+			if Path::new(&path).exists() {
+				fs::remove_file(path)?;
+			}
+		}
+		Cmd::new("systemctl").arg("daemon-reload").run()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::setup::steps::Firewall;
+	use crate::setup::testing;
+
+	// `nft --json list table inet eightinary` for the worker table, written after libnftables-json(5).
+	const WORKER_TABLE_JSON: &str = r#"{"nftables": [
+		{"metainfo": {"version": "1.0.6", "release_name": "Lester Gooch #5", "json_schema_version": 1}},
+		{"table": {"family": "inet", "name": "eightinary", "handle": 7}},
+		{"chain": {"family": "inet", "table": "eightinary", "name": "input", "handle": 1, "type": "filter", "hook": "input", "prio": 0, "policy": "accept"}},
+		{"rule": {"family": "inet", "table": "eightinary", "chain": "input", "handle": 2, "expr": [
+			{"match": {"op": "==", "left": {"meta": {"key": "iifname"}}, "right": "lo"}},
+			{"accept": null}]}},
+		{"rule": {"family": "inet", "table": "eightinary", "chain": "input", "handle": 3, "comment": "8inary: cilium health", "expr": [
+			{"match": {"op": "==", "left": {"payload": {"protocol": "ip", "field": "saddr"}}, "right": {"prefix": {"addr": "192.168.0.0", "len": 16}}}},
+			{"match": {"op": "==", "left": {"payload": {"protocol": "tcp", "field": "dport"}}, "right": 4240}},
+			{"accept": null}]}},
+		{"rule": {"family": "inet", "table": "eightinary", "chain": "input", "handle": 4, "comment": "8inary: cilium vxlan", "expr": [
+			{"match": {"op": "==", "left": {"payload": {"protocol": "ip", "field": "saddr"}}, "right": {"prefix": {"addr": "192.168.0.0", "len": 16}}}},
+			{"match": {"op": "==", "left": {"payload": {"protocol": "udp", "field": "dport"}}, "right": 8472}},
+			{"accept": null}]}},
+		{"rule": {"family": "inet", "table": "eightinary", "chain": "input", "handle": 5, "comment": "8inary: kubelet", "expr": [
+			{"match": {"op": "==", "left": {"payload": {"protocol": "ip", "field": "saddr"}}, "right": {"prefix": {"addr": "192.168.0.0", "len": 16}}}},
+			{"match": {"op": "==", "left": {"payload": {"protocol": "tcp", "field": "dport"}}, "right": 10250}},
+			{"accept": null}]}},
+		{"rule": {"family": "inet", "table": "eightinary", "chain": "input", "handle": 6, "comment": "8inary: nodeport", "expr": [
+			{"match": {"op": "==", "left": {"payload": {"protocol": "ip", "field": "saddr"}}, "right": {"prefix": {"addr": "192.168.0.0", "len": 16}}}},
+			{"match": {"op": "==", "left": {"payload": {"protocol": "tcp", "field": "dport"}}, "right": {"range": [30000, 32767]}}},
+			{"accept": null}]}},
+		{"rule": {"family": "inet", "table": "eightinary", "chain": "input", "handle": 7, "comment": "8inary: nodeport", "expr": [
+			{"match": {"op": "==", "left": {"payload": {"protocol": "ip", "field": "saddr"}}, "right": {"prefix": {"addr": "192.168.0.0", "len": 16}}}},
+			{"match": {"op": "==", "left": {"payload": {"protocol": "udp", "field": "dport"}}, "right": {"range": [30000, 32767]}}},
+			{"accept": null}]}},
+		{"rule": {"family": "inet", "table": "eightinary", "chain": "input", "handle": 8, "comment": "8inary: pod network", "expr": [
+			{"match": {"op": "==", "left": {"payload": {"protocol": "ip", "field": "saddr"}}, "right": {"prefix": {"addr": "10.0.0.0", "len": 16}}}},
+			{"accept": null}]}},
+		{"rule": {"family": "inet", "table": "eightinary", "chain": "input", "handle": 9, "comment": "8inary: other sources", "expr": [
+			{"match": {"op": "==", "left": {"payload": {"protocol": "tcp", "field": "dport"}}, "right": {"set": [4240, 10250, {"range": [30000, 32767]}]}}},
+			{"drop": null}]}},
+		{"rule": {"family": "inet", "table": "eightinary", "chain": "input", "handle": 10, "comment": "8inary: other sources", "expr": [
+			{"match": {"op": "==", "left": {"payload": {"protocol": "udp", "field": "dport"}}, "right": {"set": [8472, {"range": [30000, 32767]}]}}},
+			{"drop": null}]}}
+	]}"#;
+
+	fn from_source(rules: &[FirewallRule<'static>]) -> Vec<FirewallRule<'static>> {
+		testing::init();
+		rules
+			.iter()
+			.map(|rule| FirewallRule {
+				from: "192.168.0.0/16",
+				..*rule
+			})
+			.collect()
+	}
+
+	#[test]
+	fn worker_ruleset_renders() {
+		assert_eq!(
+			Nftables::ruleset(&from_source(Firewall::WORKER_RULES)).unwrap(),
+			r#"table inet eightinary
+delete table inet eightinary
+table inet eightinary {
+	chain input {
+		type filter hook input priority filter; policy accept;
+		iifname "lo" accept
+		ip saddr 192.168.0.0/16 tcp dport 4240 accept comment "8inary: cilium health"
+		ip saddr 192.168.0.0/16 udp dport 8472 accept comment "8inary: cilium vxlan"
+		ip saddr 192.168.0.0/16 tcp dport 10250 accept comment "8inary: kubelet"
+		ip saddr 192.168.0.0/16 tcp dport 30000-32767 accept comment "8inary: nodeport"
+		ip saddr 192.168.0.0/16 udp dport 30000-32767 accept comment "8inary: nodeport"
+		ip saddr 10.0.0.0/16 accept comment "8inary: pod network"
+		tcp dport { 4240, 10250, 30000-32767 } drop comment "8inary: other sources"
+		udp dport { 8472, 30000-32767 } drop comment "8inary: other sources"
+	}
+}
+"#
+		);
+	}
+
+	#[test]
+	fn control_plane_rules_render() {
+		assert_eq!(
+			Nftables::rule_lines(&from_source(Firewall::CONTROL_PLANE_RULES)).unwrap(),
+			[
+				r#"iifname "lo" accept"#,
+				r#"ip saddr 192.168.0.0/16 tcp dport 2379 accept comment "8inary: etcd client""#,
+				r#"ip saddr 192.168.0.0/16 tcp dport 2380 accept comment "8inary: etcd peer""#,
+				r#"ip saddr 192.168.0.0/16 tcp dport 4240 accept comment "8inary: cilium health""#,
+				r#"ip saddr 192.168.0.0/16 tcp dport 6443 accept comment "8inary: kube-apiserver""#,
+				r#"ip saddr 192.168.0.0/16 udp dport 8472 accept comment "8inary: cilium vxlan""#,
+				r#"ip saddr 192.168.0.0/16 tcp dport 10250 accept comment "8inary: kubelet""#,
+				r#"ip saddr 192.168.0.0/16 tcp dport 10257 accept comment "8inary: controller-manager""#,
+				r#"ip saddr 192.168.0.0/16 tcp dport 10259 accept comment "8inary: scheduler""#,
+				r#"ip saddr 10.0.0.0/16 accept comment "8inary: pod network""#,
+				r#"tcp dport { 2379, 2380, 4240, 6443, 10250, 10257, 10259 } drop comment "8inary: other sources""#,
+				r#"udp dport { 8472 } drop comment "8inary: other sources""#,
+			]
+		);
+	}
+
+	#[test]
+	fn live_table_round_trips() {
+		let rules = from_source(Firewall::WORKER_RULES);
+		let runner = testing::script(&format!(
+			"[[command]]\nmatch = \"nft --json list table inet eightinary\"\nstdout = '''{WORKER_TABLE_JSON}'''\n"
+		));
+		assert_eq!(
+			Nftables::live_rules().unwrap(),
+			Some(Nftables::rule_lines(&rules).unwrap())
+		);
+		assert_eq!(runner.recorded(), ["nft --json list table inet eightinary"]);
+	}
+
+	#[test]
+	fn missing_table_reads_as_none() {
+		let _runner = testing::script(
+			r#"
+				[[command]]
+				match = "nft --json list table inet eightinary"
+				status = 1
+				stderr = "Error: No such file or directory"
+			"#,
+		);
+		assert_eq!(Nftables::live_rules().unwrap(), None);
+	}
+
+	#[test]
+	fn saddr_normalises_like_nft() {
+		for (cidr, expected) in [
+			("192.168.1.7/16", "ip saddr 192.168.0.0/16"),
+			("10.0.0.5/32", "ip saddr 10.0.0.5"),
+			("0.0.0.0/0", "ip saddr 0.0.0.0/0"),
+			("fd00:10::7/64", "ip6 saddr fd00:10::/64"),
+			("fd00::7/128", "ip6 saddr fd00::7"),
+			("::/0", "ip6 saddr ::/0"),
+		] {
+			assert_eq!(saddr(cidr).unwrap(), expected, "{cidr}");
+		}
+		for cidr in [
+			"10.0.0.0",
+			"10.0.0.0/33",
+			"fd00::/129",
+			"any/8",
+			"10.0.0.0/x",
+		] {
+			assert!(saddr(cidr).is_err(), "{cidr}");
+		}
+	}
+}
@@ -0,0 +1,248 @@
+use super::{Backend, FirewallRule, RuleDiff};
+use crate::error::InstallError;
+use crate::setup::Check;
+use crate::setup::utils::cmd::Cmd;
+use tracing::{info, warn};
+
+pub struct Ufw;
+
+impl Ufw {
+	pub const PACKAGE_NAME: &str = "ufw";
+	pub const COMMENT_PREFIX: &str = "8inary: ";
+
+	// The rule spec shared by `ufw allow` and `ufw delete allow`, ufw matches rules without their comment.
+	fn rule_args<'a>(rule: &FirewallRule<'a>) -> Vec<&'a str> {
+		let mut args = vec!["allow", "from", rule.from, "to", "any"];
+		if let Some(port) = rule.port {
+			args.extend(["port", port]);
+		}
+		if let Some(protocol) = rule.protocol {
+			args.extend(["proto", protocol]);
+		}
+		args
+	}
+
+	// Only rules carrying our comment are managed, everything else in ufw is left alone.
+	fn live_rules(added: &str) -> Vec<FirewallRule<'_>> {
+		added
+			.lines()
+			.filter(|line| line.contains(Ufw::COMMENT_PREFIX))
+			.filter_map(|line| {
+				let rule = parse(line);
+				if rule.is_none() {
+					warn!("Ignoring unrecognized ufw rule: {}", line.trim());
+				}
+				rule
+			})
+			.collect()
+	}
+
+	fn added() -> Result<String, InstallError> {
+		Ok(Cmd::new("ufw").args(["show", "added"]).output()?.stdout)
+	}
+}
+
+// `ufw show added` prints every rule as the command that added it, e.g.
+// `ufw allow from 192.168.0.0/16 to any port 2379 proto tcp comment '8inary: etcd client'`.
+fn parse(line: &str) -> Option<FirewallRule<'_>> {
+	let line = line.trim().strip_prefix("ufw allow ")?;
+	let (spec, comment) = line.split_once(" comment ")?;
+	let comment = comment
+		.trim()
+		.trim_matches('\'')
+		.strip_prefix(Ufw::COMMENT_PREFIX)?;
+	let mut rule = FirewallRule {
+		from: "any",
+		port: None,
+		protocol: None,
+		comment,
+	};
+	let mut tokens = spec.split_whitespace();
+	while let Some(token) = tokens.next() {
+		match token {
+			"from" => rule.from = tokens.next()?,
+			// Rules to a single destination are not ours.
+			"to" => {
+				if tokens.next()? != "any" {
+					return None;
+				}
+			}
+			"port" => rule.port = Some(tokens.next()?),
+			"proto" => rule.protocol = Some(tokens.next()?),
+			// The simple syntax, e.g. `ufw allow 22/tcp`.
+			simple if simple.starts_with(|ch: char| ch.is_ascii_digit()) => {
+				let (port, protocol) = match simple.split_once('/') {
+					Some((port, protocol)) => (port, Some(protocol)),
+					None => (simple, None),
+				};
+				rule.port = Some(port);
+				rule.protocol = protocol;
+			}
+			_ => return None,
+		}
+	}
+	Some(rule)
+}
+
+impl Backend for Ufw {
+	fn name(&self) -> &'static str {
+		"ufw"
+	}
+
+	fn check(&self, rules: &[FirewallRule<'_>]) -> Result<Check, InstallError> {
+		let added = Ufw::added()?;
+		let diff = RuleDiff::new(rules, &Ufw::live_rules(&added));
+		if diff.is_empty() {
+			info!("Firewall ports are open.");
+			Ok(Check::Satisfied)
+		} else {
+			Ok(Check::drift(format!("Firewall rules differ, {diff}.")))
+		}
+	}
+
+	fn set(&self, rules: &[FirewallRule<'_>]) -> Result<(), InstallError> {
+		let added = Ufw::added()?;
+		let diff = RuleDiff::new(rules, &Ufw::live_rules(&added));
+		let stale = diff.changed.iter().map(|(live, _)| live);
+		for rule in diff.extra.iter().chain(stale) {
+			info!("Deleting firewall rule {}.", rule);
+			Cmd::new("ufw")
+				.arg("delete")
+				.args(Ufw::rule_args(rule))
+				.run()?;
+		}
+		let wanted = diff.changed.iter().map(|(_, wanted)| wanted);
+		for rule in diff.missing.iter().chain(wanted) {
+			info!("Adding firewall rule {}.", rule);
+			Cmd::new("ufw")
+				.args(Ufw::rule_args(rule))
+				.arg("comment")
+				.arg(format!("{}{}", Ufw::COMMENT_PREFIX, rule.comment))
+				.run()?;
+		}
+		Cmd::new("ufw").arg("reload").run()
+	}
+
+	fn unset(&self, _rules: &[FirewallRule<'_>]) -> Result<(), InstallError> {
+		let added = Ufw::added()?;
+		for rule in Ufw::live_rules(&added) {
+			info!("Deleting firewall rule {}.", rule);
+			Cmd::new("ufw")
+				.arg("delete")
+				.args(Ufw::rule_args(&rule))
+				.run()?;
+		}
+		Cmd::new("ufw").arg("reload").run()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn parse_table() {
+		let rule = |from, port, protocol, comment| FirewallRule {
+			from,
+			port,
+			protocol,
+			comment,
+		};
+		for (line, expected) in [
+			(
+				"ufw allow from 192.168.0.0/16 to any port 30000:32767 proto tcp comment '8inary: nodeport'",
+				Some(rule(
+					"192.168.0.0/16",
+					Some("30000:32767"),
+					Some("tcp"),
+					"nodeport",
+				)),
+			),
+			(
+				"ufw allow from 192.168.0.0/16 to any port 2379 proto tcp comment '8inary: etcd client'",
+				Some(rule(
+					"192.168.0.0/16",
+					Some("2379"),
+					Some("tcp"),
+					"etcd client",
+				)),
+			),
+			(
+				"ufw allow 22/tcp comment '8inary: ssh'",
+				Some(rule("any", Some("22"), Some("tcp"), "ssh")),
+			),
+			(
+				"ufw allow 8472 comment '8inary: cilium vxlan'",
+				Some(rule("any", Some("8472"), None, "cilium vxlan")),
+			),
+			(
+				"ufw allow from 10.0.0.0/16 comment '8inary: pod network'",
+				Some(rule("10.0.0.0/16", None, None, "pod network")),
+			),
+			(
+				"  ufw allow from 192.168.0.0/16 to any port 6443 proto tcp comment '8inary: kube-apiserver'  ",
+				Some(rule(
+					"192.168.0.0/16",
+					Some("6443"),
+					Some("tcp"),
+					"kube-apiserver",
+				)),
+			),
+			(
+				"ufw allow from 192.168.0.0/16 to any port 6443 proto tcp",
+				None,
+			),
+			(
+				"ufw allow from 192.168.0.0/16 to 10.0.0.1 port 6443 proto tcp comment '8inary: kube-apiserver'",
+				None,
+			),
+			(
+				"ufw allow from 192.168.0.0/16 to any port 443 proto tcp comment 'web: https'",
+				None,
+			),
+			(
+				"ufw allow in on eth1 to any port 6443 comment '8inary: kube-apiserver'",
+				None,
+			),
+			("ufw deny 6443 comment '8inary: kube-apiserver'", None),
+		] {
+			assert_eq!(parse(line), expected, "{line}");
+		}
+	}
+
+	#[test]
+	fn live_rules_keep_only_ours() {
+		let added = "Added user rules (see 'ufw status' for running firewall):
+ufw allow 22/tcp
+ufw allow from 192.168.0.0/16 to any port 10250 proto tcp comment '8inary: kubelet'
+ufw allow from 192.168.0.0/16 to any port 443 proto tcp comment 'web: https'
+ufw allow in on eth1 to any port 6443 comment '8inary: kube-apiserver'
+";
+		assert_eq!(
+			Ufw::live_rules(added),
+			[FirewallRule {
+				from: "192.168.0.0/16",
+				port: Some("10250"),
+				protocol: Some("tcp"),
+				comment: "kubelet",
+			}]
+		);
+	}
+
+	#[test]
+	fn rule_args_round_trip_through_parse() {
+		let rule = FirewallRule {
+			from: "192.168.0.0/16",
+			port: Some("30000:32767"),
+			protocol: Some("udp"),
+			comment: "nodeport",
+		};
+		let line = format!(
+			"ufw {} comment '{}{}'",
+			Ufw::rule_args(&rule).join(" "),
+			Ufw::COMMENT_PREFIX,
+			rule.comment
+		);
+		assert_eq!(parse(&line), Some(rule));
+	}
+}
@@ -1,14 +1,17 @@
 use crate::error::InstallError;
-use crate::setup::utils::pkg;
-use crate::setup::SetupStep;
-use std::{fs, process::Command};
+use crate::setup::utils::{
+	cmd::Cmd,
+	pkg::{self, PkgManager},
+};
+use crate::setup::{Check, SetupStep};
+use std::{fs, path::Path};
 use tracing::info;
 
 pub struct Helm;
 
 impl Helm {
 	pub const PACKAGE_NAME: &str = "helm";
-	pub const DEPENDENCIES: &[&str] = &["apt-transport-https"];
+	pub const APT_DEPENDENCIES: &[&str] = &["apt-transport-https"];
 	pub const BASE_KEY_URL: &str = "https://packages.buildkite.com/helm-linux/helm-debian";
 	pub const APT_KEY_PATH: &str = "/usr/share/keyrings/helm.gpg";
 	pub const APT_CONFIG_PATH: &str = "/etc/apt/sources.list.d/helm-stable-debian.list";
@@ -19,35 +22,54 @@ impl SetupStep for Helm {
 		"Helm"
 	}
 
-	fn check(&self) -> Result<bool, InstallError> {
+	fn check(&self) -> Result<Check, InstallError> {
 		if pkg::is_installed(Helm::PACKAGE_NAME)? {
 			info!("Helm is already installed.");
-			Ok(true)
+			Ok(Check::Satisfied)
 		} else {
-			info!("Helm is not installed.");
-			Ok(false)
+			Ok(Check::drift("Helm is not installed."))
 		}
 	}
 
 	fn set(&self) -> Result<(), InstallError> {
 		info!("Installing Helm.");
-		pkg::install(Helm::DEPENDENCIES)?;
-		let key_command = format!(
-			"curl -fsSL {}/gpgkey | gpg --dearmor --yes -o {}",
-			Helm::BASE_KEY_URL,
-			Helm::APT_KEY_PATH,
-		);
-		Command::new("sh").arg("-c").arg(key_command).status()?;
-		let apt_config_txt = format!(
-			"deb [signed-by={}] {}/any/ any main",
-			Helm::APT_KEY_PATH,
-			Helm::BASE_KEY_URL,
-		);
-		fs::write(Helm::APT_CONFIG_PATH, apt_config_txt)?;
+		match pkg::get_pkg_manager()? {
+			PkgManager::Apt => {
+				pkg::install(Helm::APT_DEPENDENCIES)?;
+				let key_command = format!(
+					"curl -fsSL {}/gpgkey | gpg --dearmor --yes -o {}",
+					Helm::BASE_KEY_URL,
+					Helm::APT_KEY_PATH,
+				);
+				Cmd::sh(key_command).run()?;
+				let apt_config_txt = format!(
+					"deb [signed-by={}] {}/any/ any main",
+					Helm::APT_KEY_PATH,
+					Helm::BASE_KEY_URL,
+				);
+				fs::write(Helm::APT_CONFIG_PATH, apt_config_txt)?;
+			}
+			// Fedora and Arch package Helm in their main repositories.
+			PkgManager::Dnf | PkgManager::Pacman => {}
+		}
 		pkg::update()?;
 		pkg::install(&[Helm::PACKAGE_NAME])?;
 		pkg::mark(&[Helm::PACKAGE_NAME])?;
 		info!("Helm has been installed.");
 		Ok(())
 	}
+
+	fn unset(&self) -> Result<(), InstallError> {
+		info!("Removing Helm.");
+		pkg::unmark(&[Helm::PACKAGE_NAME])?;
+		pkg::remove(&[Helm::PACKAGE_NAME])?;
+		for path in [Helm::APT_CONFIG_PATH, Helm::APT_KEY_PATH] {
+			if Path::new(path).exists() {
+				fs::remove_file(path)?;
+			}
+		}
+		pkg::update()?;
+		info!("Helm has been removed.");
+		Ok(())
+	}
 }
@@ -1,18 +1,33 @@
+use crate::config;
 use crate::error::InstallError;
-use crate::setup::SetupStep;
-use std::{process::Command, thread::sleep, time::Duration};
+use crate::setup::utils::{
+	cmd::Cmd,
+	inventory::{self, MachineRole},
+	kctl, ledger,
+};
+use crate::setup::{Check, SetupStep};
+use k8s_openapi::api::core::v1::{Namespace, PersistentVolume};
+use kube::api::ObjectMeta;
+use std::time::Duration;
 use tracing::info;
 
 #[derive(Debug, Clone)]
 pub struct IdentityDatabase;
 
 impl IdentityDatabase {
-	pub const VERSION: &str = "v1.6.3";
 	pub const CRD_URL: &str =
 		"https://raw.githubusercontent.com/pingcap/tidb-operator/{VERSION}/manifests/crd.yaml";
 	pub const HELM_REPO: &str = "https://charts.pingcap.org/";
 	pub const NAMESPACE: &str = "identity";
 	pub const CONFIG: &str = "https://raw.githubusercontent.com/pingcap/tidb-operator/{VERSION}/examples/basic/tidb-cluster.yaml";
+	pub const DISK_PATHS: &[&str] = &[
+		"/mnt/disks/identity/pd",
+		"/mnt/disks/identity/tikv",
+		"/mnt/disks/identity/monitor",
+	];
+	pub const CRDS: &[&str] = &["tidbclusters.pingcap.com", "tidbmonitors.pingcap.com"];
+	pub const CRD_ESTABLISHED: Duration = Duration::from_secs(120);
+	pub const OPERATOR_READY: Duration = Duration::from_secs(300);
 	pub const MONITOR_CONFIG: &str = "https://raw.githubusercontent.com/pingcap/tidb-operator/{VERSION}/examples/basic/tidb-monitor.yaml";
 }
 
@@ -21,79 +36,90 @@ impl SetupStep for IdentityDatabase {
 		"IdentityDatabase"
 	}
 
-	fn check(&self) -> bool {
-		false
+	fn requires(&self) -> &'static [&'static str] {
+		&["ControlPlane", "Helm", "Istio"]
 	}
 
-	fn set(&self) {
+	fn check(&self) -> Result<Check, InstallError> {
+		if inventory::this()?.role == MachineRole::Worker {
+			info!(
+				"This machine is a worker, the identity database is installed from the control planes."
+			);
+			return Ok(Check::Satisfied);
+		}
+		let Some(record) = ledger::applied(self.name()) else {
+			return Ok(Check::drift("Identity database was never applied."));
+		};
+		let operator_version = &config::get().identity_database.operator_version;
+		match record.versions.get("tidb-operator") {
+			Some(applied) if applied == operator_version => {}
+			applied => {
+				return Ok(Check::drift(format!(
+					"TiDB operator {} was applied, {} is configured.",
+					applied.map_or("of unknown version", String::as_str),
+					operator_version
+				)));
+			}
+		}
+		if !kctl::is_deployment_installed("tidb-operator", IdentityDatabase::NAMESPACE)? {
+			return Ok(Check::drift("TiDB operator is not deployed."));
+		}
+		info!("Identity database is applied.");
+		Ok(Check::Satisfied)
+	}
+
+	fn set(&self) -> Result<(), InstallError> {
+		let database = &config::get().identity_database;
 		info!("Installing TiDB for identity service.");
-		Command::new("sh")
-			.arg("-c")
-			.arg(
-				r#"
-				set -euo pipefail
-				sudo mkdir -p /mnt/disks/identity/pd
-				sudo mkdir -p /mnt/disks/identity/tikv
-				sudo mkdir -p /mnt/disks/identity/monitor
-				"#,
-			)
-			.status()
-			.expect("Fatal failure to create TiDB local storage mount paths.");
-		Command::new("kubectl")
-			.args(["--kubeconfig", "/etc/kubernetes/admin.conf"])
+		info!("Creating TiDB local storage mount paths.");
+		Cmd::new("mkdir")
+			.arg("-p")
+			.args(IdentityDatabase::DISK_PATHS)
+			.run()?;
+		info!("Applying TiDB operator custom resource definitions.");
+		kctl::apply_url(
+			&IdentityDatabase::CRD_URL.replace("{VERSION}", &database.operator_version),
+			None,
+		)?;
+		for crd in IdentityDatabase::CRDS {
+			kctl::wait_crd_established(crd, IdentityDatabase::CRD_ESTABLISHED)?;
+		}
+		Cmd::new("helm")
 			.args([
-				"create",
-				"-f",
-				&IdentityDatabase::CRD_URL.replace("{VERSION}", IdentityDatabase::VERSION),
+				"repo",
+				"add",
+				"--force-update",
+				"pingcap",
+				IdentityDatabase::HELM_REPO,
 			])
-			.status()
-			.expect("Fatal failure to install custom resource definitions for the TiDB operator.");
-		Command::new("helm")
-			.args(["repo", "add", "pingcap", IdentityDatabase::HELM_REPO])
-			.status()
-			.expect("Fatal failure to add pingcap helm repo.");
-		Command::new("helm")
-			.args(["repo", "update"])
-			.status()
-			.expect("Fatal failure to update helm.");
-		Command::new("kubectl")
-			.args(["--kubeconfig", "/etc/kubernetes/admin.conf"])
-			.args(["create", "namespace", IdentityDatabase::NAMESPACE])
-			.status()
-			.expect("Fatal failure to create identity admin namespace.");
-		Command::new("helm")
-			.arg("install")
+			.run()?;
+		Cmd::new("helm").args(["repo", "update"]).run()?;
+		kctl::apply(
+			&kctl::cluster::<Namespace>()?,
+			&Namespace {
+				metadata: ObjectMeta {
+					name: Some(IdentityDatabase::NAMESPACE.to_owned()),
+					..ObjectMeta::default()
+				},
+				..Namespace::default()
+			},
+		)?;
+		info!("Installing TiDB operator.");
+		Cmd::new("helm")
+			.args(["upgrade", "--install"])
 			.args(["--namespace", IdentityDatabase::NAMESPACE])
 			.args(["tidb-operator", "pingcap/tidb-operator"])
-			.args(["--version", IdentityDatabase::VERSION])
-			.status()
-			.expect("Fatal failure to install TiDB operator.");
-		let mut is_ready = false;
-		for retry in 2..8 {
-			sleep(Duration::from_secs(u64::pow(2, retry)));
-			let ready_ouput = Command::new("kubectl")
-				.args(["--kubeconfig", "/etc/kubernetes/admin.conf"])
-				.args(["get", "pods"])
-				.args(["--namespace", IdentityDatabase::NAMESPACE])
-				.args(["-l", "app.kubernetes.io/instance=tidb-operator"])
-				.output()
-				.expect("Fatal failure to get tidb-operator pod.");
-			let ready_msg = str::from_utf8(&ready_ouput.stdout)
-				.expect("Fatal failure in tidb-operator pod status output.");
-			if ready_msg.lines().count() == 2 {
-				is_ready = true;
-				info!("TiDB operator pod is ready.");
-				break;
-			}
-		}
-		if !is_ready {
-			panic!("Fatal failure to ready tidb-operator pod.");
-		}
-		Command::new("kubectl")
-			.args(["--kubeconfig", "/etc/kubernetes/admin.conf"])
-			.args(["apply", "-f", "-"])
-			.arg(
-				r#"<<EOF
+			.args(["--version", &database.operator_version])
+			.run()?;
+		kctl::wait_pods_ready(
+			IdentityDatabase::NAMESPACE,
+			"app.kubernetes.io/instance=tidb-operator",
+			IdentityDatabase::OPERATOR_READY,
+		)?;
+		info!("TiDB operator pods are ready.");
+		info!("Applying TiDB cluster.");
+		let cluster_yaml = kctl::dedent(
+			&r#"
 					apiVersion: pingcap.com/v1alpha1
 					kind: TidbCluster
 					metadata:
@@ -105,7 +131,7 @@ impl SetupStep for IdentityDatabase {
 					  pvReclaimPolicy: Retain
 					  pd:
 						baseImage: pingcap/pd
-						replicas: 5
+						replicas: {PD_REPLICAS}
 						requests:
 						  storage: "10Gi"
 						tolerations:
@@ -114,7 +140,7 @@ impl SetupStep for IdentityDatabase {
 						  effect: NoSchedule
 					  tikv:
 						baseImage: pingcap/tikv
-						replicas: 5
+						replicas: {TIKV_REPLICAS}
 						requests:
 						  storage: "100Gi"
 						tolerations:
@@ -123,52 +149,38 @@ impl SetupStep for IdentityDatabase {
 						  effect: NoSchedule
 					  tidb:
 						baseImage: pingcap/tidb
-						replicas: 5
+						replicas: {TIDB_REPLICAS}
 						service:
 						  type: ClusterIP
 						tolerations:
 						- key: node-role.kubernetes.io/control-plane
 						  operator: Exists
 						  effect: NoSchedule
-					EOF"#
-					.replace("{NAMESPACE}", IdentityDatabase::NAMESPACE),
-			)
-			.status()
-			.expect("Fatal failure to apply tolerations for TiDB.");
-		Command::new("kubectl")
-			.args(["--kubeconfig", "/etc/kubernetes/admin.conf"])
-			.args(["--namespace", IdentityDatabase::NAMESPACE])
-			.args([
-				"apply",
-				"-f",
-				&IdentityDatabase::CONFIG.replace("{VERSION}", IdentityDatabase::VERSION),
-			])
-			.status()
-			.expect("Fatal failure to create identity database cluster.");
-		Command::new("kubectl")
-			.args(["--kubeconfig", "/etc/kubernetes/admin.conf"])
-			.args(["--namespace", IdentityDatabase::NAMESPACE])
-			.args([
-				"apply",
-				"-f",
-				&IdentityDatabase::MONITOR_CONFIG.replace("{VERSION}", IdentityDatabase::VERSION),
-			])
-			.status()
-			.expect("Fatal failure to create identity database cluster monitoring.");
-		Command::new("kubectl")
-			.args(["--kubeconfig", "/etc/kubernetes/admin.conf"])
-			.args(["patch", "storageclass", "local-storage"])
-			.args([
-				"-p",
-				r#"'{"metadata": {"annotations":{"storageclass.kubernetes.io/is-default-class":"true"}}}'"#,
-			])
-			.status()
-			.expect("Fatal failure to make local-storage the default storage class.");
-		Command::new("kubectl")
-			.args(["--kubeconfig", "/etc/kubernetes/admin.conf"])
-			.args(["apply", "-f", "-"])
-			.arg(
-				r#"<<EOF
+"#
+			.replace("{NAMESPACE}", IdentityDatabase::NAMESPACE)
+			.replace("{PD_REPLICAS}", &database.pd_replicas.to_string())
+			.replace("{TIKV_REPLICAS}", &database.tikv_replicas.to_string())
+			.replace("{TIDB_REPLICAS}", &database.tidb_replicas.to_string()),
+		);
+		kctl::apply_yaml(&cluster_yaml)?;
+		for manifest in [IdentityDatabase::CONFIG, IdentityDatabase::MONITOR_CONFIG] {
+			kctl::apply_url(
+				&manifest.replace("{VERSION}", &database.operator_version),
+				Some(IdentityDatabase::NAMESPACE),
+			)?;
+		}
+		info!("Applying local storage class and persistent volumes for TiDB.");
+		kctl::apply_yaml(&kctl::dedent(
+			&r#"
+					apiVersion: storage.k8s.io/v1
+					kind: StorageClass
+					metadata:
+					  name: local-storage
+					  annotations:
+						storageclass.kubernetes.io/is-default-class: "true"
+					provisioner: kubernetes.io/no-provisioner
+					volumeBindingMode: WaitForFirstConsumer
+					---
 					apiVersion: v1
 					kind: PersistentVolume
 					metadata:
@@ -210,16 +222,12 @@ impl SetupStep for IdentityDatabase {
 					  storageClassName: local-storage
 					  local:
 						path: /mnt/disks/identity/monitor
-					EOF"#
-					.replace("{NAMESPACE}", IdentityDatabase::NAMESPACE),
-			)
-			.status()
-			.expect("Fatal failure to apply persistent volumes for TiDB.");
-		Command::new("kubectl")
-			.args(["--kubeconfig", "/etc/kubernetes/admin.conf"])
-			.args(["apply", "-f", "-"])
-			.arg(
-				r#"<<EOF
+"#
+			.replace("{NAMESPACE}", IdentityDatabase::NAMESPACE),
+		))?;
+		info!("Applying Cilium network policies for TiDB.");
+		kctl::apply_yaml(&kctl::dedent(
+			&r#"
 					---
 					# Policy 1: TiDB Component Communication (Port-Specific)
 					apiVersion: cilium.io/v2
@@ -498,17 +506,12 @@ impl SetupStep for IdentityDatabase {
 							protocol: TCP
 						  - port: "20180"  # TiKV status port
 							protocol: TCP
-					EOF"#
-					.replace("{NAMESPACE}", IdentityDatabase::NAMESPACE),
-			)
-			.status()
-			.expect("Fatal failure to apply Cilium network policies for TiDB.");
-		Command::new("kubectl")
-			.args(["--kubeconfig", "/etc/kubernetes/admin.conf"])
-			.args(["apply", "-f", "-"])
-			.arg(
-				r#"
-				<<EOF
+"#
+			.replace("{NAMESPACE}", IdentityDatabase::NAMESPACE),
+		))?;
+		info!("Applying TiDB config maps.");
+		kctl::apply_yaml(&kctl::dedent(
+			&r#"
 					apiVersion: v1
 					kind: ConfigMap
 					metadata:
@@ -522,31 +525,27 @@ impl SetupStep for IdentityDatabase {
 					data:
 					  config-file: |
 						[replication]
-						max-replicas = 5
+						max-replicas = {PD_REPLICAS}
 					  startup-script: |
 						#!/bin/sh
 						set -uo pipefail
-						ARGS="--name=\${HOSTNAME} \
+						ARGS="--name=${HOSTNAME} \
 						--data-dir=/var/lib/pd \
 						--peer-urls=http://0.0.0.0:2380 \
-						--advertise-peer-urls=http://\${HOSTNAME}.{NAMESPACE}-pd-peer.{NAMESPACE}.svc:2380 \
+						--advertise-peer-urls=http://${HOSTNAME}.{NAMESPACE}-pd-peer.{NAMESPACE}.svc:2380 \
 						--client-urls=http://0.0.0.0:2379 \
-						--advertise-client-urls=http://\${HOSTNAME}.{NAMESPACE}-pd-peer.{NAMESPACE}.svc:2379"
+						--advertise-client-urls=http://${HOSTNAME}.{NAMESPACE}-pd-peer.{NAMESPACE}.svc:2379"
 						if [ -f /etc/pd/config-file ]; then
-						  ARGS="\${ARGS} --config=/etc/pd/config-file"
+						  ARGS="${ARGS} --config=/etc/pd/config-file"
 						fi
-						ARGS="\${ARGS} --initial-cluster=\${HOSTNAME}=http://\${HOSTNAME}.{NAMESPACE}-pd-peer.{NAMESPACE}.svc:2380"
-						exec /pd-server \${ARGS}
-				EOF"#.replace("{NAMESPACE}", IdentityDatabase::NAMESPACE),
-			)
-			.status()
-			.expect("Fatal failure to apply TiKV placement driver config map.");
-		Command::new("kubectl")
-			.args(["--kubeconfig", "/etc/kubernetes/admin.conf"])
-			.args(["apply", "-f", "-"])
-			.arg(
-				r#"
-				<<EOF
+						ARGS="${ARGS} --initial-cluster=${HOSTNAME}=http://${HOSTNAME}.{NAMESPACE}-pd-peer.{NAMESPACE}.svc:2380"
+						exec /pd-server ${ARGS}
+"#
+					.replace("{NAMESPACE}", IdentityDatabase::NAMESPACE)
+					.replace("{PD_REPLICAS}", &database.pd_replicas.to_string()),
+		))?;
+		kctl::apply_yaml(&kctl::dedent(
+			&r#"
 					apiVersion: v1
 					kind: ConfigMap
 					metadata:
@@ -570,24 +569,18 @@ impl SetupStep for IdentityDatabase {
 						#!/bin/sh
 						set -uo pipefail
 						ARGS="--addr=0.0.0.0:20160 \
-						--advertise-addr=\${HOSTNAME}.{NAMESPACE}-tikv-peer.{NAMESPACE}.svc:20160 \
+						--advertise-addr=${HOSTNAME}.{NAMESPACE}-tikv-peer.{NAMESPACE}.svc:20160 \
 						--data-dir=/var/lib/tikv \
 						--pd={NAMESPACE}-pd.{NAMESPACE}.svc:2379"
 						if [ -f /etc/tikv/config-file ]; then
-						  ARGS="\${ARGS} --config=/etc/tikv/config-file"
+						  ARGS="${ARGS} --config=/etc/tikv/config-file"
 						fi
-						exec /tikv-server \${ARGS}
-				EOF"#
-					.replace("{NAMESPACE}", IdentityDatabase::NAMESPACE),
-			)
-			.status()
-			.expect("Fatal failure to apply TiKV config map.");
-		Command::new("kubectl")
-			.args(["--kubeconfig", "/etc/kubernetes/admin.conf"])
-			.args(["apply", "-f", "-"])
-			.arg(
-				r#"
-				<<EOF
+						exec /tikv-server ${ARGS}
+"#
+			.replace("{NAMESPACE}", IdentityDatabase::NAMESPACE),
+		))?;
+		kctl::apply_yaml(&kctl::dedent(
+			&r#"
 					apiVersion: v1
 					kind: ConfigMap
 					metadata:
@@ -607,45 +600,68 @@ impl SetupStep for IdentityDatabase {
 						#!/bin/sh
 						set -uo pipefail
 						ARGS="--store=tikv \
-						--advertise-address=\${HOSTNAME}.{NAMESPACE}-tidb-peer.{NAMESPACE}.svc \
+						--advertise-address=${HOSTNAME}.{NAMESPACE}-tidb-peer.{NAMESPACE}.svc \
 						--host=0.0.0.0 \
 						-P=4000 \
 						--status=10080 \
 						--path={NAMESPACE}-pd.{NAMESPACE}.svc:2379"
 						if [ -f /etc/tidb/config-file ]; then
-						  ARGS="\${ARGS} --config=/etc/tidb/config-file"
+						  ARGS="${ARGS} --config=/etc/tidb/config-file"
 						fi
-						exec /tidb-server \${ARGS}
-				EOF"#
-					.replace("{NAMESPACE}", IdentityDatabase::NAMESPACE),
-			)
-			.status()
-			.expect("Fatal failure to apply TiDB config map.");
-		Command::new("kubectl")
-			.args(["--kubeconfig", "/etc/kubernetes/admin.conf"])
-			.args(["label", "namespace", IdentityDatabase::NAMESPACE])
-			.arg("istio-injection=enabled")
-			.arg("--overwrite")
-			.status()
-			.expect("Fatal failure to label identity namespace with istio-injection.");
-		Command::new("kubectl")
-			.args(["--kubeconfig", "/etc/kubernetes/admin.conf"])
-			.args(["apply", "-f", "-"])
-			.arg(
-				r#"
-				<<EOF
+						exec /tidb-server ${ARGS}
+"#
+			.replace("{NAMESPACE}", IdentityDatabase::NAMESPACE),
+		))?;
+		kctl::label(
+			&kctl::cluster::<Namespace>()?,
+			IdentityDatabase::NAMESPACE,
+			&[("istio-injection", "enabled")],
+		)?;
+		info!("Applying strict mTLS for the identity namespace.");
+		kctl::apply_yaml(&kctl::dedent(
+			&r#"
 					apiVersion: security.istio.io/v1
 					kind: PeerAuthentication
 					metadata:
 					  name: default-identity-mtls
-					  namespace: identity
+					  namespace: {NAMESPACE}
 					spec:
 					  mtls:
 						mode: STRICT
-				EOF"#
-					.replace("{NAMESPACE}", IdentityDatabase::NAMESPACE),
-			)
-			.status()
-			.expect("Fatal failure to apply TiDB config map.");
+"#
+			.replace("{NAMESPACE}", IdentityDatabase::NAMESPACE),
+		))?;
+		Ok(())
+	}
+
+	fn unset(&self) -> Result<(), InstallError> {
+		if inventory::this()?.role == MachineRole::Worker {
+			return Ok(());
+		}
+		let database = &config::get().identity_database;
+		info!("Uninstalling TiDB for identity service.");
+		Cmd::new("helm")
+			.arg("uninstall")
+			.args(["--namespace", IdentityDatabase::NAMESPACE])
+			.arg("tidb-operator")
+			.arg("--ignore-not-found")
+			.run()?;
+		kctl::delete(&kctl::cluster::<Namespace>()?, IdentityDatabase::NAMESPACE)?;
+		let volumes = kctl::cluster::<PersistentVolume>()?;
+		for volume in ["local-pv-pd", "local-pv-tikv", "local-pv-monitor"] {
+			kctl::delete(&volumes, volume)?;
+		}
+		kctl::delete_url(
+			&IdentityDatabase::CRD_URL.replace("{VERSION}", &database.operator_version),
+		)?;
+		info!("TiDB operator has been uninstalled.");
+		Ok(())
+	}
+
+	fn versions(&self) -> Vec<(&'static str, String)> {
+		vec![(
+			"tidb-operator",
+			config::get().identity_database.operator_version.clone(),
+		)]
 	}
 }
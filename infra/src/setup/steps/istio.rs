@@ -1,14 +1,24 @@
+use crate::config;
 use crate::error::InstallError;
-use crate::setup::utils::kctl;
-use crate::setup::SetupStep;
-use std::process::Command;
+use crate::setup::utils::{
+	cmd::Cmd,
+	inventory::{self, MachineRole},
+	kctl,
+};
+use crate::setup::{Check, SetupStep};
+use k8s_openapi::api::core::v1::Namespace;
+use std::{fs, path::Path, time::Duration};
 use tracing::info;
 
 pub struct Istio;
 
 impl Istio {
-	pub const VERSION: &str = "1.28.0";
 	pub const URL: &str = "https://istio.io/downloadIstio";
+	pub const ISTIOCTL_PATH: &str = "/usr/local/bin/istioctl";
+	pub const COMPLETION_PATH: &str = "/etc/bash_completion.d/istioctl.bash";
+	pub const NAMESPACE: &str = "istio-system";
+	pub const DEPLOYMENT: &str = "istiod";
+	pub const AVAILABLE: Duration = Duration::from_secs(300);
 }
 
 impl SetupStep for Istio {
@@ -16,48 +26,71 @@ impl SetupStep for Istio {
 		"Istio"
 	}
 
-	fn check(&self) -> Result<bool, InstallError> {
-		let is_installed = kctl::is_deployment_installed("istio", "istio-system")?;
+	fn requires(&self) -> &'static [&'static str] {
+		&["ControlPlane"]
+	}
+
+	fn check(&self) -> Result<Check, InstallError> {
+		if inventory::this()?.role == MachineRole::Worker {
+			info!("This machine is a worker, Istio is installed from the control planes.");
+			return Ok(Check::Satisfied);
+		}
+		let is_installed = kctl::is_deployment_installed(Istio::DEPLOYMENT, Istio::NAMESPACE)?;
 		if is_installed {
 			info!("Istio is already installed.");
-			Ok(true)
+			Ok(Check::Satisfied)
 		} else {
-			info!("Istio is not installed.");
-			Ok(false)
+			Ok(Check::drift("Istio is not installed."))
 		}
 	}
 
 	fn set(&self) -> Result<(), InstallError> {
 		info!("Installing Istio.");
-		Command::new("sh")
-			.arg("-c")
-			.arg(format!(
-				r#"
+		Cmd::sh(format!(
+			r#"
 				(cd /tmp && curl -L {} | ISTIO_VERSION={} sh -) && \
 				sudo mv /tmp/istio-{} /tmp/istio && \
 				sudo cp /tmp/istio/bin/istioctl /usr/local/bin/ && \
 				sudo cp /tmp/istio/tools/istioctl.bash /etc/bash_completion.d/ && \
 				sudo rm -rf /tmp/istio
 				"#,
-				Istio::URL,
-				Istio::VERSION,
-				Istio::VERSION,
-			))
-			.status()
-			.map_err(|err| InstallError::CommandLaunch {
-				cmd: format!("istio path bash commands"),
-				source: err,
-			})?;
-		Command::new("istioctl")
+			Istio::URL,
+			config::get().istio.version,
+			config::get().istio.version,
+		))
+		.run()?;
+		Cmd::new("istioctl")
 			.arg("install")
-			.args(["--kubeconfig", "/etc/kubernetes/admin.conf"])
+			.args(["--kubeconfig", kctl::KUBECONFIG])
 			.args(["--set", "profile=default"])
 			.arg("-y")
-			.status()
-			.map_err(|err| InstallError::CommandLaunch {
-				cmd: format!("istioctl --set profile=default -y"),
-				source: err,
-			})?;
+			.run()?;
+		kctl::wait_deployment_available(Istio::NAMESPACE, Istio::DEPLOYMENT, Istio::AVAILABLE)
+	}
+
+	fn unset(&self) -> Result<(), InstallError> {
+		if inventory::this()?.role == MachineRole::Worker {
+			return Ok(());
+		}
+		info!("Uninstalling Istio.");
+		if Path::new(Istio::ISTIOCTL_PATH).exists() {
+			Cmd::new(Istio::ISTIOCTL_PATH)
+				.arg("uninstall")
+				.args(["--kubeconfig", kctl::KUBECONFIG])
+				.args(["--purge", "-y"])
+				.run()?;
+		}
+		kctl::delete(&kctl::cluster::<Namespace>()?, Istio::NAMESPACE)?;
+		for path in [Istio::ISTIOCTL_PATH, Istio::COMPLETION_PATH] {
+			if Path::new(path).exists() {
+				fs::remove_file(path)?;
+			}
+		}
+		info!("Istio has been uninstalled.");
 		Ok(())
 	}
+
+	fn versions(&self) -> Vec<(&'static str, String)> {
+		vec![("istio", config::get().istio.version.clone())]
+	}
 }
@@ -1,8 +1,9 @@
 use crate::error::InstallError;
-use crate::setup::SetupStep;
+use crate::setup::utils::cmd::Cmd;
+use crate::setup::{Check, SetupStep};
 use hex_literal::hex;
 use sha2::{Digest, Sha256};
-use std::{fs, path::Path, process::Command};
+use std::{fs, path::Path};
 use tracing::info;
 
 pub struct KernelModules;
@@ -16,8 +17,7 @@ impl KernelModules {
 
 	pub fn load(module_name: &str) -> Result<(), InstallError> {
 		info!("Loading kernel module: {module_name}.");
-		Command::new("modprobe").arg(module_name).status()?;
-		Ok(())
+		Cmd::new("modprobe").arg(module_name).run()
 	}
 }
 
@@ -26,28 +26,24 @@ impl SetupStep for KernelModules {
 		"KernelModules"
 	}
 
-	fn check(&self) -> Result<bool, InstallError> {
+	fn check(&self) -> Result<Check, InstallError> {
 		const EXPECTED: [u8; 32] =
 			hex!("fcaf07413a456d658640930cef56ed4d13330123e3b522c481021613c64755e3");
 		let Ok(config_txt) = fs::read(KernelModules::CONFIG_PATH) else {
-			info!("Kernel module config missing or unreadable.");
-			return Ok(false);
+			return Ok(Check::drift("Kernel module config missing or unreadable."));
 		};
 		let is_valid = Sha256::digest(&config_txt)[..] == EXPECTED;
 		if !is_valid {
-			info!("Kernel modules are misconfigured.");
-			return Ok(false);
+			return Ok(Check::drift("Kernel module config hash mismatch."));
 		}
 		if !KernelModules::is_loaded("overlay") {
-			info!("Overlay fs kernel module not loaded.");
-			return Ok(false);
+			return Ok(Check::drift("Overlay fs kernel module not loaded."));
 		}
 		if !KernelModules::is_loaded("br_netfilter") {
-			info!("Bridge netfilter kernel module not loaded.");
-			return Ok(false);
+			return Ok(Check::drift("Bridge netfilter kernel module not loaded."));
 		}
 		info!("Kernel modules are already configured and loaded.");
-		Ok(true)
+		Ok(Check::Satisfied)
 	}
 
 	fn set(&self) -> Result<(), InstallError> {
@@ -59,4 +55,12 @@ impl SetupStep for KernelModules {
 		info!("Kernel modules have been successfully configured and loaded.");
 		Ok(())
 	}
+
+	fn unset(&self) -> Result<(), InstallError> {
+		if Path::new(KernelModules::CONFIG_PATH).exists() {
+			info!("Removing kernel module config, modules stay loaded until reboot.");
+			fs::remove_file(KernelModules::CONFIG_PATH)?;
+		}
+		Ok(())
+	}
 }
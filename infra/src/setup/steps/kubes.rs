@@ -1,7 +1,13 @@
+use crate::config;
 use crate::error::InstallError;
-use crate::setup::utils::pkg;
-use crate::setup::SetupStep;
-use std::{fs, process::Command};
+use crate::setup::utils::{
+	cmd::Cmd,
+	kctl,
+	pkg::{self, PkgManager},
+	version::Version,
+};
+use crate::setup::{Check, SetupStep};
+use std::{fs, path::Path};
 use tracing::info;
 
 pub struct Kubes;
@@ -10,7 +16,52 @@ impl Kubes {
 	pub const PACKAGE_NAMES: &[&str] = &["kubelet", "kubeadm", "kubectl"];
 	pub const APT_CONFIG_PATH: &str = "/etc/apt/sources.list.d/kubernetes.list";
 	pub const APT_KEY_PATH: &str = "/etc/apt/keyrings/kubernetes-apt-keyring.gpg";
-	pub const K8S_BASE_URL: &str = "https://pkgs.k8s.io/core:/stable:/v1.34/deb";
+	pub const DNF_REPO_PATH: &str = "/etc/yum.repos.d/kubernetes.repo";
+	pub const K8S_BASE_URL: &str = "https://pkgs.k8s.io/core:/stable:/{MINOR}/{FORMAT}";
+
+	fn base_url(format: &str) -> String {
+		Kubes::K8S_BASE_URL
+			.replace("{MINOR}", config::get().kubernetes.minor())
+			.replace("{FORMAT}", format)
+	}
+
+	pub fn add_repository(manager: PkgManager) -> Result<(), InstallError> {
+		match manager {
+			PkgManager::Apt => {
+				let key_command = format!(
+					"curl -fsSL {}/Release.key | gpg --dearmor --yes -o {}",
+					Kubes::base_url("deb"),
+					Kubes::APT_KEY_PATH,
+				);
+				Cmd::sh(key_command).run()?;
+				let apt_config_txt = format!(
+					"deb [signed-by={}] {} /",
+					Kubes::APT_KEY_PATH,
+					Kubes::base_url("deb"),
+				);
+				fs::write(Kubes::APT_CONFIG_PATH, apt_config_txt)?;
+			}
+			PkgManager::Dnf => {
+				let repo_txt = format!(
+					"[kubernetes]\nname=Kubernetes\nbaseurl={url}/\nenabled=1\ngpgcheck=1\ngpgkey={url}/repodata/repomd.xml.key\n",
+					url = Kubes::base_url("rpm"),
+				);
+				fs::write(Kubes::DNF_REPO_PATH, repo_txt)?;
+			}
+			PkgManager::Pacman => {
+				info!("Kubernetes tooling comes from the Arch extra repository.");
+			}
+		}
+		Ok(())
+	}
+
+	// Without a kubeconfig there is no cluster to skew against, one that cannot be asked is an error.
+	fn check_skew(kubelet: Version) -> Result<(), InstallError> {
+		let Some(apiserver) = kctl::apiserver_version()? else {
+			return Ok(());
+		};
+		Version::check_kubelet_skew(kubelet, Version::parse(&apiserver)?)
+	}
 }
 
 impl SetupStep for Kubes {
@@ -18,36 +69,67 @@ impl SetupStep for Kubes {
 		"Kubes"
 	}
 
-	fn check(&self) -> Result<bool, InstallError> {
+	fn check(&self) -> Result<Check, InstallError> {
+		let desired = Version::parse(&config::get().kubernetes.version)?;
+		// A skewed kubelet is drift, set installs the configured version if that one fits.
+		if let Some(kubelet) = pkg::installed_version("kubelet")? {
+			match Kubes::check_skew(Version::parse(&kubelet)?) {
+				Err(InstallError::VersionSkew(reason)) => {
+					return Ok(Check::drift(format!("Installed {reason}.")));
+				}
+				result => result?,
+			}
+		}
 		for package_name in Kubes::PACKAGE_NAMES {
-			let is_installed = pkg::is_installed(package_name)?;
-			if !is_installed {
-				info!("{package_name} is not installed.");
-				return Ok(false);
+			let Some(installed) = pkg::installed_version(package_name)? else {
+				return Ok(Check::drift(format!("{package_name} is not installed.")));
+			};
+			if Version::parse(&installed)? != desired {
+				return Ok(Check::drift(format!(
+					"{package_name} {installed} is installed, {desired} is configured."
+				)));
 			}
 		}
-		info!("Kubes are installed.");
-		Ok(true)
+		info!("Kubes {} are installed.", desired);
+		Ok(Check::Satisfied)
 	}
 
 	fn set(&self) -> Result<(), InstallError> {
-		info!("Installing Kubernetes tooling via apt-get.");
-		let key_command = format!(
-			"curl -fsSL {}/Release.key | gpg --dearmor --yes -o {}",
-			Kubes::K8S_BASE_URL,
-			Kubes::APT_KEY_PATH,
-		);
-		Command::new("sh").arg("-c").arg(key_command).status()?;
-		let apt_config_txt = format!(
-			"deb [signed-by={}] {} /",
-			Kubes::APT_KEY_PATH,
-			Kubes::K8S_BASE_URL,
+		let kubernetes = &config::get().kubernetes;
+		// The configured version is about to replace the installed one, the API server must accept it.
+		Kubes::check_skew(Version::parse(&kubernetes.version)?)?;
+		let manager = pkg::get_pkg_manager()?;
+		info!(
+			"Installing Kubernetes tooling {} via {:?}.",
+			kubernetes.version, manager
 		);
-		fs::write(Kubes::APT_CONFIG_PATH, apt_config_txt)?;
+		Kubes::add_repository(manager)?;
 		pkg::update()?;
-		pkg::install(Kubes::PACKAGE_NAMES)?;
+		pkg::install_pinned(Kubes::PACKAGE_NAMES, kubernetes.package_version())?;
 		pkg::mark(Kubes::PACKAGE_NAMES)?;
 		info!("Kubernetes tooling installed.");
 		Ok(())
 	}
+
+	fn unset(&self) -> Result<(), InstallError> {
+		info!("Removing Kubernetes tooling.");
+		pkg::unmark(Kubes::PACKAGE_NAMES)?;
+		pkg::remove(Kubes::PACKAGE_NAMES)?;
+		for path in [
+			Kubes::APT_CONFIG_PATH,
+			Kubes::APT_KEY_PATH,
+			Kubes::DNF_REPO_PATH,
+		] {
+			if Path::new(path).exists() {
+				fs::remove_file(path)?;
+			}
+		}
+		pkg::update()?;
+		info!("Kubernetes tooling removed.");
+		Ok(())
+	}
+
+	fn versions(&self) -> Vec<(&'static str, String)> {
+		vec![("kubernetes", config::get().kubernetes.version.clone())]
+	}
 }
@@ -1,6 +1,8 @@
+pub mod certificates;
 pub mod containerd;
 pub mod control_plane;
 pub mod disable_swap;
+pub mod etcd_backup;
 pub mod firewall;
 pub mod helm;
 pub mod identity_database;
@@ -8,10 +10,13 @@ pub mod istio;
 pub mod kernel_modules;
 pub mod kubes;
 pub mod sysctl;
+pub mod worker;
 
+pub use certificates::Certificates;
 pub use containerd::Containerd;
 pub use control_plane::ControlPlane;
 pub use disable_swap::DisableSwap;
+pub use etcd_backup::EtcdBackup;
 pub use firewall::Firewall;
 pub use helm::Helm;
 pub use identity_database::IdentityDatabase;
@@ -19,3 +24,4 @@ pub use istio::Istio;
 pub use kernel_modules::KernelModules;
 pub use kubes::Kubes;
 pub use sysctl::Sysctl;
+pub use worker::Worker;
@@ -1,8 +1,9 @@
 use crate::error::InstallError;
-use crate::setup::SetupStep;
+use crate::setup::utils::cmd::Cmd;
+use crate::setup::{Check, SetupStep};
 use hex_literal::hex;
 use sha2::{Digest, Sha256};
-use std::{fs, process::Command};
+use std::{fs, path::Path};
 use tracing::info;
 
 pub struct Sysctl;
@@ -16,20 +17,23 @@ impl SetupStep for Sysctl {
 		"Sysctl"
 	}
 
-	fn check(&self) -> Result<bool, InstallError> {
+	// The net.bridge keys only exist once br_netfilter is loaded.
+	fn requires(&self) -> &'static [&'static str] {
+		&["KernelModules"]
+	}
+
+	fn check(&self) -> Result<Check, InstallError> {
 		const EXPECTED: [u8; 32] =
 			hex!("6e3f751b8409493b80fb7154ee21989dece3322d8b9018157ffef64dfbc10799");
 		let Ok(config_txt) = fs::read(Sysctl::CONFIG_PATH) else {
-			info!("Sysctl config missing or unreadable.");
-			return Ok(false);
+			return Ok(Check::drift("Sysctl config missing or unreadable."));
 		};
 		let is_valid = Sha256::digest(&config_txt)[..] == EXPECTED;
 		if !is_valid {
-			info!("Kernel modules are misconfigured.");
-			return Ok(false);
+			return Ok(Check::drift("Sysctl config hash mismatch."));
 		}
 		info!("Sysctl already configured.");
-		Ok(true)
+		Ok(Check::Satisfied)
 	}
 
 	fn set(&self) -> Result<(), InstallError> {
@@ -42,8 +46,17 @@ impl SetupStep for Sysctl {
 		.join("\n")
 			+ "\n";
 		fs::write(Sysctl::CONFIG_PATH, config_txt)?;
-		Command::new("sysctl").arg("--system").status()?;
+		Cmd::new("sysctl").arg("--system").run()?;
 		info!("Sysctl has been successfully configured.");
 		Ok(())
 	}
+
+	fn unset(&self) -> Result<(), InstallError> {
+		if Path::new(Sysctl::CONFIG_PATH).exists() {
+			info!("Removing sysctl config.");
+			fs::remove_file(Sysctl::CONFIG_PATH)?;
+			Cmd::new("sysctl").arg("--system").run()?;
+		}
+		Ok(())
+	}
 }
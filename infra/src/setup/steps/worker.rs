@@ -0,0 +1,152 @@
+use crate::context;
+use crate::error::InstallError;
+use crate::setup::steps::control_plane::{self, ControlPlane};
+use crate::setup::utils::{
+	cmd::Cmd,
+	inventory::{self, Machine, MachineRole},
+	kctl,
+	retry::Retry,
+};
+use crate::setup::{Check, SetupStep};
+use k8s_openapi::api::core::v1::Node;
+use kube::ResourceExt;
+use std::{path::Path, time::Duration};
+use tracing::info;
+
+pub struct Worker;
+
+impl Worker {
+	pub const NODE_READY: Duration = Duration::from_secs(300);
+	pub const JOIN_COMMAND: Retry = Retry::attempts(3).deadline(Duration::from_secs(180));
+	pub const ROLE_LABEL: &str = "node-role.kubernetes.io/worker";
+	pub const ZONE_LABEL: &str = "topology.kubernetes.io/zone";
+
+	// The role and zone labels plus the free form inventory labels.
+	fn labels(machine: &Machine) -> Vec<(String, String)> {
+		let mut labels = vec![(Worker::ROLE_LABEL.to_owned(), String::new())];
+		if let Some(zone) = &machine.zone {
+			labels.push((Worker::ZONE_LABEL.to_owned(), zone.clone()));
+		}
+		labels.extend(machine.labels.clone());
+		labels
+	}
+}
+
+impl SetupStep for Worker {
+	fn name(&self) -> &'static str {
+		"Worker"
+	}
+
+	fn requires(&self) -> &'static [&'static str] {
+		&[
+			"DisableSwap",
+			"KernelModules",
+			"Sysctl",
+			"Containerd",
+			"Kubes",
+			"Firewall",
+		]
+	}
+
+	fn check(&self) -> Result<Check, InstallError> {
+		let machine = inventory::this()?;
+		if machine.role != MachineRole::Worker {
+			info!("This machine is a control plane, no worker setup required.");
+			return Ok(Check::Satisfied);
+		}
+		if !Path::new(kctl::KUBELET_KUBECONFIG).exists() {
+			return Ok(Check::drift("Node has not joined a cluster."));
+		}
+		let Some(node) = kctl::get_node(&context::get().hostname)? else {
+			return Ok(Check::drift("Node is not registered with the cluster."));
+		};
+		if let Some(reason) = kctl::node_pending(&node) {
+			return Ok(Check::drift(format!("Node is not Ready, {reason}.")));
+		}
+		let missing = Worker::labels(machine)
+			.into_iter()
+			.filter(|(key, value)| node.labels().get(key) != Some(value))
+			.map(|(key, _)| key)
+			.collect::<Vec<_>>();
+		if missing.is_empty() {
+			info!("Worker is registered and Ready.");
+			Ok(Check::Satisfied)
+		} else {
+			Ok(Check::drift(format!(
+				"Node is missing the labels {}.",
+				missing.join(", ")
+			)))
+		}
+	}
+
+	fn set(&self) -> Result<(), InstallError> {
+		let machine = inventory::this()?;
+		info!("Worker setup started.");
+		info!("Machine Id: {}", machine.id);
+		reset_worker()?;
+		let join_command =
+			Worker::JOIN_COMMAND.run("fetching the join command", get_worker_join_command)?;
+		info!("Executing join command:\n{join_command}\n");
+		Cmd::bash(join_command)
+			.timeout(ControlPlane::KUBEADM_TIMEOUT)
+			.run()?;
+		info!("This node has joined the cluster.");
+		// The kubelet may not set role labels on its own node, the root's credentials do it.
+		kctl::use_admin_kubeconfig(control_plane::run_on_root(&format!(
+			"sudo -n cat {}",
+			kctl::KUBECONFIG
+		))?);
+		let hostname = &context::get().hostname;
+		kctl::wait_node_ready(hostname, Worker::NODE_READY)?;
+		let labels = Worker::labels(machine);
+		let labels = labels
+			.iter()
+			.map(|(key, value)| (key.as_str(), value.as_str()))
+			.collect::<Vec<_>>();
+		ControlPlane::NODE_UPDATE.run("labeling the node", || {
+			kctl::label(&kctl::cluster::<Node>()?, hostname, &labels)
+		})?;
+		info!("Worker setup finished.");
+		Ok(())
+	}
+
+	fn unset(&self) -> Result<(), InstallError> {
+		if inventory::this()?.role != MachineRole::Worker {
+			return Ok(());
+		}
+		control_plane::reset_node()
+	}
+}
+
+fn reset_worker() -> Result<(), InstallError> {
+	info!("Hard reset Kubernetes worker node.");
+	Cmd::bash(
+		r#"
+			set -euo pipefail
+			sudo systemctl stop kubelet || true
+			sudo kubeadm reset --force || true
+			sudo rm -f /etc/kubernetes/kubelet.conf /etc/kubernetes/bootstrap-kubelet.conf
+			sudo rm -rf /etc/kubernetes/pki || true
+			sudo systemctl restart containerd || true
+			sudo systemctl start kubelet || true
+		"#,
+	)
+	.run()?;
+	info!("Node has been hard reset.");
+	Ok(())
+}
+
+fn get_worker_join_command() -> Result<String, InstallError> {
+	let output = control_plane::run_on_root(&format!(
+		"sudo -n kubeadm token create --print-join-command --kubeconfig {}",
+		kctl::KUBECONFIG
+	))?;
+	let join_cmd = output.trim().to_owned();
+	if !join_cmd.starts_with("kubeadm join") || join_cmd.contains("--control-plane") {
+		return Err(InstallError::Kube(format!(
+			"Received empty or invalid join command: {join_cmd:?}"
+		)));
+	}
+	info!("Successfully obtained fresh worker join command.");
+	Ok(join_cmd + " --v=5")
+}
@@ -0,0 +1,37 @@
+use crate::config::{self, Config};
+use crate::context::{self, Context};
+use crate::setup::utils::{inventory, ledger};
+use std::sync::Once;
+
+pub use crate::setup::utils::cmd::testing::{install, script};
+
+pub const MACHINE_ID: &str = "0123456789abcdef0123456789abcdef";
+pub const HOSTNAME: &str = "cp-1";
+
+static INIT: Once = Once::new();
+
+// The process wide state steps read, the same for every test: this machine is the control plane root.
+pub fn init() {
+	INIT.call_once(|| {
+		context::set(Context {
+			home: "/home/tester".to_owned(),
+			hostname: HOSTNAME.to_owned(),
+			machine_id: MACHINE_ID.to_owned(),
+			user: "tester".to_owned(),
+		});
+		config::set(Config::default());
+		inventory::set(
+			toml::from_str(&format!(
+				r#"
+					[[machine]]
+					id = "{MACHINE_ID}"
+					environment = "Dev"
+					role = "ControlPlaneRoot"
+					hostname = "{HOSTNAME}"
+				"#
+			))
+			.expect("Fatal test inventory parse."),
+		);
+		ledger::init(None).expect("Fatal test ledger initialization.");
+	});
+}
@@ -0,0 +1,107 @@
+use crate::config;
+use crate::error::InstallError;
+use crate::setup::SetupStep;
+use crate::setup::report::{Report, StepReport};
+use crate::setup::steps::{ControlPlane, Kubes};
+use crate::setup::utils::{
+	cmd::Cmd,
+	inventory::{self, MachineRole},
+	kctl,
+	ledger::{self, Action},
+	pkg,
+	version::Version,
+};
+use std::time::Duration;
+use tracing::info;
+
+pub const ADMIN_KUBECONFIG: &str = kctl::KUBECONFIG;
+pub const DRAIN_TIMEOUT: Duration = Duration::from_secs(600);
+// One `infra upgrade --phase` run on a node, package installs and kubeadm included.
+pub const PHASE_TIMEOUT: Duration = Duration::from_secs(45 * 60);
+
+// Runs on the workstation, the root's admin kubeconfig gives access to the cluster.
+pub fn preflight(admin_kubeconfig: String) -> Result<(), InstallError> {
+	kctl::use_admin_kubeconfig(admin_kubeconfig);
+	let target = Version::parse(&config::get().kubernetes.version)?;
+	let current = kctl::apiserver_version()?
+		.ok_or_else(|| InstallError::NotReady("the API server version is unknown".to_owned()))?;
+	let current = Version::parse(&current)?;
+	Version::check_upgrade(current, target)?;
+	info!("Upgrading the cluster from {} to {}.", current, target);
+	Ok(())
+}
+
+pub fn drain(node: &str) -> Result<(), InstallError> {
+	kctl::drain(node, DRAIN_TIMEOUT)
+}
+
+// The next machine only starts once this node and the API server are healthy again.
+pub fn restore(node: &str) -> Result<(), InstallError> {
+	kctl::uncordon(node)?;
+	kctl::wait_node_ready(node, ControlPlane::NODE_READY)?;
+	kctl::wait_apiserver_healthy(
+		&config::get().kube_vip.endpoint(),
+		ControlPlane::API_HEALTHY,
+	)
+}
+
+// Runs on the node, before the drain.
+pub fn kubeadm(report: &mut Report) -> Result<(), InstallError> {
+	let (step_report, result) = StepReport::run("UpgradeKubeadm", |_| upgrade_kubeadm());
+	report.steps.push(step_report);
+	result
+}
+
+// Runs on the node, between the drain and the uncordon.
+pub fn kubelet(report: &mut Report) -> Result<(), InstallError> {
+	let (step_report, result) = StepReport::run("UpgradeKubelet", |_| upgrade_kubelet());
+	report.steps.push(step_report);
+	result
+}
+
+fn upgrade_kubeadm() -> Result<(), InstallError> {
+	let kubernetes = &config::get().kubernetes;
+	info!("Upgrading kubeadm to {}.", kubernetes.version);
+	Kubes::add_repository(pkg::get_pkg_manager()?)?;
+	pkg::update()?;
+	pkg::install_pinned(&["kubeadm"], kubernetes.package_version())?;
+	pkg::mark(&["kubeadm"])?;
+	let role = inventory::this()?.role;
+	if role == MachineRole::ControlPlaneRoot {
+		let plan = Cmd::new("kubeadm")
+			.args(["upgrade", "plan", &kubernetes.version])
+			.timeout(ControlPlane::KUBEADM_TIMEOUT)
+			.output()?;
+		info!("Kubeadm upgrade plan:\n{}", plan.stdout.trim_end());
+		Cmd::new("kubeadm")
+			.args(["upgrade", "apply", &kubernetes.version, "--yes"])
+			.timeout(ControlPlane::KUBEADM_TIMEOUT)
+			.run()?;
+	} else {
+		Cmd::new("kubeadm")
+			.args(["upgrade", "node"])
+			.timeout(ControlPlane::KUBEADM_TIMEOUT)
+			.run()?;
+	}
+	if role.runs_etcd() {
+		ledger::record(
+			ControlPlane.name(),
+			Action::Upgraded,
+			ControlPlane.versions(),
+		)?;
+	}
+	info!("Kubeadm upgraded.");
+	Ok(())
+}
+
+fn upgrade_kubelet() -> Result<(), InstallError> {
+	let kubernetes = &config::get().kubernetes;
+	info!("Upgrading kubelet and kubectl to {}.", kubernetes.version);
+	pkg::install_pinned(&["kubelet", "kubectl"], kubernetes.package_version())?;
+	pkg::mark(&["kubelet", "kubectl"])?;
+	Cmd::new("systemctl").arg("daemon-reload").run()?;
+	Cmd::new("systemctl").args(["restart", "kubelet"]).run()?;
+	ledger::record(Kubes.name(), Action::Upgraded, Kubes.versions())?;
+	info!("Kubelet upgraded.");
+	Ok(())
+}
@@ -0,0 +1,513 @@
+use crate::error::InstallError;
+use serde::{Deserialize, Serialize};
+use std::{
+	cell::RefCell,
+	collections::VecDeque,
+	ffi::OsStr,
+	fs, io,
+	io::{BufRead, BufReader, Read, Write},
+	os::unix::process::{CommandExt, ExitStatusExt},
+	path::Path,
+	process::{Child, Command, ExitStatus, Stdio},
+	sync::{
+		Mutex, OnceLock, PoisonError,
+		mpsc::{self, Receiver},
+	},
+	thread::{self, sleep},
+	time::{Duration, Instant},
+};
+use tracing::{debug, info};
+
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(15 * 60);
+// How long output is still collected after the command exited or was killed.
+const READER_GRACE: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone)]
+pub struct Cmd {
+	program: String,
+	args: Vec<String>,
+	envs: Vec<(String, String)>,
+	stdin: Option<String>,
+	timeout: Duration,
+	echo: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Output {
+	pub status: ExitStatus,
+	pub stdout: String,
+	pub stderr: String,
+}
+
+impl Output {
+	pub fn success(&self) -> bool {
+		self.status.success()
+	}
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CommandRecord {
+	pub command: String,
+	pub status: Option<i32>,
+	pub duration_ms: u64,
+	pub error: Option<String>,
+}
+
+thread_local! {
+	static RECORDS: RefCell<Option<Vec<CommandRecord>>> = const { RefCell::new(None) };
+}
+
+// Collects every command run by `body` on this thread, steps run on their own threads.
+pub fn record<T>(body: impl FnOnce() -> T) -> (T, Vec<CommandRecord>) {
+	let outer = RECORDS.replace(Some(Vec::new()));
+	let value = body();
+	let records = RECORDS.replace(outer).unwrap_or_default();
+	(value, records)
+}
+
+pub trait Runner: Send + Sync {
+	fn execute(&self, cmd: &Cmd) -> Result<Output, InstallError>;
+}
+
+impl Cmd {
+	pub fn new(program: impl Into<String>) -> Cmd {
+		Cmd {
+			program: program.into(),
+			args: Vec::new(),
+			envs: Vec::new(),
+			stdin: None,
+			timeout: DEFAULT_TIMEOUT,
+			echo: None,
+		}
+	}
+
+	pub fn sh(script: impl Into<String>) -> Cmd {
+		Cmd::new("sh").arg("-c").arg(script.into())
+	}
+
+	pub fn bash(script: impl Into<String>) -> Cmd {
+		Cmd::new("bash").arg("-c").arg(script.into())
+	}
+
+	pub fn arg(mut self, arg: impl AsRef<OsStr>) -> Cmd {
+		self.args.push(arg.as_ref().to_string_lossy().into_owned());
+		self
+	}
+
+	pub fn args<I, S>(mut self, args: I) -> Cmd
+	where
+		I: IntoIterator<Item = S>,
+		S: AsRef<OsStr>,
+	{
+		self.args.extend(
+			args.into_iter()
+				.map(|arg| arg.as_ref().to_string_lossy().into_owned()),
+		);
+		self
+	}
+
+	pub fn env(mut self, key: impl Into<String>, value: impl Into<String>) -> Cmd {
+		self.envs.push((key.into(), value.into()));
+		self
+	}
+
+	pub fn stdin(mut self, input: impl Into<String>) -> Cmd {
+		self.stdin = Some(input.into());
+		self
+	}
+
+	pub fn timeout(mut self, timeout: Duration) -> Cmd {
+		self.timeout = timeout;
+		self
+	}
+
+	// Prints every output line as `[prefix] line` while it runs, the output is still collected.
+	pub fn echo(mut self, prefix: impl Into<String>) -> Cmd {
+		self.echo = Some(prefix.into());
+		self
+	}
+
+	pub fn argv(&self) -> Vec<&str> {
+		[self.program.as_str()]
+			.into_iter()
+			.chain(self.args.iter().map(String::as_str))
+			.collect()
+	}
+
+	pub fn display(&self) -> String {
+		self.argv().join(" ")
+	}
+
+	// Runs the command and returns its output whatever the exit status.
+	pub fn probe(&self) -> Result<Output, InstallError> {
+		debug!("Running: {}", self.display());
+		let started = Instant::now();
+		let result = runner().execute(self);
+		RECORDS.with_borrow_mut(|records| {
+			if let Some(records) = records {
+				records.push(CommandRecord {
+					command: self.display(),
+					status: result.as_ref().ok().and_then(|output| output.status.code()),
+					duration_ms: started.elapsed().as_millis() as u64,
+					error: result.as_ref().err().map(ToString::to_string),
+				});
+			}
+		});
+		result
+	}
+
+	// Runs the command and fails with CommandFailed on a non-zero exit status.
+	pub fn output(&self) -> Result<Output, InstallError> {
+		let output = self.probe()?;
+		if !output.success() {
+			let stderr = output.stderr.trim();
+			return Err(InstallError::CommandFailed {
+				cmd: self.display(),
+				status: output.status,
+				stderr: (!stderr.is_empty()).then(|| stderr.to_owned()),
+			});
+		}
+		Ok(output)
+	}
+
+	pub fn run(&self) -> Result<(), InstallError> {
+		self.output().map(|_| ())
+	}
+}
+
+pub struct SystemRunner;
+
+impl SystemRunner {
+	fn wait(cmd: &Cmd, child: &mut Child) -> Result<ExitStatus, InstallError> {
+		let started = Instant::now();
+		loop {
+			if let Some(status) = child.try_wait()? {
+				return Ok(status);
+			}
+			if started.elapsed() >= cmd.timeout {
+				// `sh -c` leaves its children behind on a plain kill, the whole group goes.
+				let _ = Command::new("kill")
+					.args(["-KILL", "--", &format!("-{}", child.id())])
+					.stdout(Stdio::null())
+					.stderr(Stdio::null())
+					.status();
+				let _ = child.kill();
+				let _ = child.wait();
+				return Err(InstallError::CommandTimeout {
+					cmd: cmd.display(),
+					timeout: cmd.timeout,
+				});
+			}
+			sleep(Duration::from_millis(50));
+		}
+	}
+}
+
+impl Runner for SystemRunner {
+	fn execute(&self, cmd: &Cmd) -> Result<Output, InstallError> {
+		let mut child = Command::new(&cmd.program)
+			.process_group(0)
+			.args(&cmd.args)
+			.envs(cmd.envs.iter().map(|(key, value)| (key, value)))
+			.stdin(if cmd.stdin.is_some() {
+				Stdio::piped()
+			} else {
+				Stdio::null()
+			})
+			.stdout(Stdio::piped())
+			.stderr(Stdio::piped())
+			.spawn()
+			.map_err(|source| InstallError::CommandLaunch {
+				cmd: cmd.display(),
+				source,
+			})?;
+		if let (Some(mut stdin), Some(input)) = (child.stdin.take(), cmd.stdin.clone()) {
+			thread::spawn(move || stdin.write_all(input.as_bytes()));
+		}
+		let stdout = read_all(child.stdout.take(), cmd.echo.clone());
+		let stderr = read_all(child.stderr.take(), cmd.echo.clone());
+		let status = SystemRunner::wait(cmd, &mut child);
+		// A daemon started by the command can hold the pipes open forever, its output is not waited for.
+		let deadline = Instant::now() + READER_GRACE;
+		let collect = |output: Receiver<Vec<u8>>| {
+			let mut buf = Vec::new();
+			while let Ok(chunk) =
+				output.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+			{
+				buf.extend(chunk);
+			}
+			String::from_utf8_lossy(&buf).into_owned()
+		};
+		let (stdout, stderr) = (collect(stdout), collect(stderr));
+		Ok(Output {
+			status: status?,
+			stdout,
+			stderr,
+		})
+	}
+}
+
+// Output is sent as it is read, what arrived before the deadline is kept.
+fn read_all(reader: Option<impl Read + Send + 'static>, echo: Option<String>) -> Receiver<Vec<u8>> {
+	let (sender, receiver) = mpsc::channel();
+	thread::spawn(move || match (reader, echo) {
+		(Some(reader), Some(prefix)) => {
+			let mut reader = BufReader::new(reader);
+			loop {
+				let mut line = Vec::new();
+				match reader.read_until(b'\n', &mut line) {
+					Ok(0) | Err(_) => break,
+					Ok(_) => println!("[{prefix}] {}", String::from_utf8_lossy(&line).trim_end()),
+				}
+				if sender.send(line).is_err() {
+					break;
+				}
+			}
+		}
+		(Some(mut reader), None) => {
+			let mut buf = [0; 8192];
+			loop {
+				match reader.read(&mut buf) {
+					Ok(0) | Err(_) => break,
+					Ok(read) => {
+						if sender.send(buf[..read].to_vec()).is_err() {
+							break;
+						}
+					}
+				}
+			}
+		}
+		(None, _) => {}
+	});
+	receiver
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScriptEntry {
+	#[serde(rename = "match")]
+	pub prefix: String,
+	#[serde(default)]
+	pub status: i32,
+	#[serde(default)]
+	pub stdout: String,
+	#[serde(default)]
+	pub stderr: String,
+	#[serde(default)]
+	pub repeat: bool,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Script {
+	#[serde(default, rename = "command")]
+	commands: Vec<ScriptEntry>,
+}
+
+// Replays canned results for commands matched by argv prefix, in order, and
+// records every argv it was asked to run. Nothing is executed.
+#[derive(Debug, Default)]
+pub struct ScriptedRunner {
+	script: Mutex<VecDeque<ScriptEntry>>,
+	recorded: Mutex<Vec<String>>,
+}
+
+impl ScriptedRunner {
+	pub fn new(entries: Vec<ScriptEntry>) -> ScriptedRunner {
+		ScriptedRunner {
+			script: Mutex::new(entries.into()),
+			recorded: Mutex::new(Vec::new()),
+		}
+	}
+
+	pub fn load(path: &Path) -> Result<ScriptedRunner, InstallError> {
+		ScriptedRunner::parse(&fs::read_to_string(path)?)
+			.map_err(|err| InstallError::Config(format!("{}: {}", path.display(), err)))
+	}
+
+	pub fn parse(script_txt: &str) -> Result<ScriptedRunner, toml::de::Error> {
+		let script: Script = toml::from_str(script_txt)?;
+		Ok(ScriptedRunner::new(script.commands))
+	}
+
+	pub fn recorded(&self) -> Vec<String> {
+		self.recorded
+			.lock()
+			.unwrap_or_else(PoisonError::into_inner)
+			.clone()
+	}
+}
+
+impl Runner for ScriptedRunner {
+	fn execute(&self, cmd: &Cmd) -> Result<Output, InstallError> {
+		let display = cmd.display();
+		self.recorded
+			.lock()
+			.unwrap_or_else(PoisonError::into_inner)
+			.push(display.clone());
+		let mut script = self.script.lock().unwrap_or_else(PoisonError::into_inner);
+		let Some(idx) = script
+			.iter()
+			.position(|entry| display.starts_with(&entry.prefix))
+		else {
+			return Err(InstallError::CommandLaunch {
+				cmd: display,
+				source: io::Error::new(io::ErrorKind::NotFound, "command is not scripted"),
+			});
+		};
+		let entry = if script[idx].repeat {
+			script[idx].clone()
+		} else {
+			script.remove(idx).expect("Fatal scripted entry removal.")
+		};
+		Ok(Output {
+			status: ExitStatus::from_raw(entry.status << 8),
+			stdout: entry.stdout,
+			stderr: entry.stderr,
+		})
+	}
+}
+
+static RUNNER: OnceLock<&'static dyn Runner> = OnceLock::new();
+static SCRIPTED: OnceLock<&'static ScriptedRunner> = OnceLock::new();
+
+fn runner() -> &'static dyn Runner {
+	#[cfg(test)]
+	if let Some(runner) = *testing::RUNNER
+		.lock()
+		.unwrap_or_else(PoisonError::into_inner)
+	{
+		return runner;
+	}
+	*RUNNER.get_or_init(|| &SystemRunner)
+}
+
+pub fn init_script(path: &Path) -> Result<(), InstallError> {
+	let scripted: &'static ScriptedRunner = Box::leak(Box::new(ScriptedRunner::load(path)?));
+	info!("Replaying scripted commands from {}.", path.display());
+	RUNNER.set(scripted).map_err(|_| {
+		InstallError::Config("commands ran before the script was loaded".to_owned())
+	})?;
+	SCRIPTED
+		.set(scripted)
+		.expect("Fatal script initialization.");
+	Ok(())
+}
+
+pub fn scripted() -> Option<&'static ScriptedRunner> {
+	SCRIPTED.get().copied()
+}
+
+// Tests swap in a scripted runner, one test at a time as steps run commands from their own threads.
+#[cfg(test)]
+pub mod testing {
+	use super::{Runner, ScriptedRunner};
+	use std::{
+		ops::Deref,
+		sync::{Mutex, MutexGuard, PoisonError},
+	};
+
+	pub(super) static RUNNER: Mutex<Option<&'static dyn Runner>> = Mutex::new(None);
+	static EXCLUSIVE: Mutex<()> = Mutex::new(());
+
+	// Commands go to the runner until this is dropped.
+	pub struct Installed {
+		_exclusive: MutexGuard<'static, ()>,
+	}
+
+	pub struct Scripted {
+		runner: &'static ScriptedRunner,
+		_installed: Installed,
+	}
+
+	pub fn install(runner: &'static dyn Runner) -> Installed {
+		let exclusive = EXCLUSIVE.lock().unwrap_or_else(PoisonError::into_inner);
+		*RUNNER.lock().unwrap_or_else(PoisonError::into_inner) = Some(runner);
+		Installed {
+			_exclusive: exclusive,
+		}
+	}
+
+	pub fn script(script_txt: &str) -> Scripted {
+		let runner: &'static ScriptedRunner = Box::leak(Box::new(
+			ScriptedRunner::parse(script_txt).expect("Fatal test script parse."),
+		));
+		Scripted {
+			runner,
+			_installed: install(runner),
+		}
+	}
+
+	impl Deref for Scripted {
+		type Target = ScriptedRunner;
+
+		fn deref(&self) -> &ScriptedRunner {
+			self.runner
+		}
+	}
+
+	impl Drop for Installed {
+		fn drop(&mut self) {
+			*RUNNER.lock().unwrap_or_else(PoisonError::into_inner) = None;
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn system_runner_collects_output_and_status() {
+		let output = SystemRunner
+			.execute(&Cmd::sh("echo out; echo err >&2; exit 3"))
+			.unwrap();
+		assert_eq!(output.status.code(), Some(3));
+		assert_eq!(output.stdout, "out\n");
+		assert_eq!(output.stderr, "err\n");
+	}
+
+	#[test]
+	fn system_runner_feeds_stdin() {
+		let output = SystemRunner
+			.execute(&Cmd::new("cat").stdin("input"))
+			.unwrap();
+		assert_eq!(output.stdout, "input");
+	}
+
+	#[test]
+	fn echoed_output_is_still_collected() {
+		let output = SystemRunner
+			.execute(&Cmd::sh("echo one; echo two").echo("host"))
+			.unwrap();
+		assert_eq!(output.stdout, "one\ntwo\n");
+	}
+
+	#[test]
+	fn timeout_kills_the_children_of_a_shell() {
+		let started = Instant::now();
+		let result =
+			SystemRunner.execute(&Cmd::sh("sleep 30 & sleep 30").timeout(Duration::from_secs(1)));
+		assert!(matches!(result, Err(InstallError::CommandTimeout { .. })));
+		assert!(started.elapsed() < Duration::from_secs(10));
+	}
+
+	#[test]
+	fn exited_command_does_not_wait_on_a_daemon_holding_the_pipes() {
+		let started = Instant::now();
+		let output = SystemRunner
+			.execute(&Cmd::sh("sleep 60 & echo done"))
+			.unwrap();
+		assert_eq!(output.stdout, "done\n");
+		assert!(started.elapsed() < Duration::from_secs(5));
+	}
+
+	#[test]
+	fn timeout_does_not_wait_on_a_daemon_holding_the_pipes() {
+		let started = Instant::now();
+		let result = SystemRunner
+			.execute(&Cmd::sh("setsid sleep 30 & sleep 30").timeout(Duration::from_secs(1)));
+		assert!(matches!(result, Err(InstallError::CommandTimeout { .. })));
+		assert!(started.elapsed() < Duration::from_secs(10));
+	}
+}
@@ -1,68 +1,417 @@
 use crate::context;
+use crate::error::InstallError;
+use serde::Deserialize;
+use std::{
+	collections::{BTreeMap, HashSet},
+	fs, io,
+	net::IpAddr,
+	path::Path,
+	sync::OnceLock,
+};
+use toml_edit::{DocumentMut, Item};
+use tracing::{info, warn};
 
-#[derive(Debug, Clone, Copy)]
+pub const DEFAULT_PATH: &str = "/etc/8inary/inventory.toml";
+
+// Retiring a control plane passes through an even member count, only `node remove` accepts one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EtcdMembers {
+	Odd,
+	Any,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
 pub enum Environment {
 	Dev,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
 pub enum MachineRole {
 	ControlPlane,
 	ControlPlaneRoot,
-	#[allow(dead_code)]
 	Worker,
 }
 
-#[derive(Debug, Clone)]
-struct IMachine<'a> {
-	id: &'a str,
-	environment: Environment,
-	role: MachineRole,
+impl MachineRole {
+	pub fn runs_etcd(&self) -> bool {
+		matches!(
+			self,
+			MachineRole::ControlPlane | MachineRole::ControlPlaneRoot
+		)
+	}
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Machine {
 	pub id: String,
-	pub _environment: Environment,
+	pub environment: Environment,
 	pub role: MachineRole,
+	pub hostname: Option<String>,
+	pub ip: Option<IpAddr>,
+	pub interface: Option<String>,
+	#[serde(default = "default_ssh_user")]
+	pub ssh_user: String,
+	pub zone: Option<String>,
+	#[serde(default)]
+	pub labels: BTreeMap<String, String>,
+	#[serde(default)]
+	pub disks: Vec<String>,
+}
+
+fn default_ssh_user() -> String {
+	"mgmt".to_owned()
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Inventory {
+	#[serde(default, rename = "machine")]
+	pub machines: Vec<Machine>,
+}
+
+impl Inventory {
+	pub fn load(path: &Path, etcd_members: EtcdMembers) -> Result<Inventory, InstallError> {
+		Inventory::parse(&fs::read_to_string(path)?, path, etcd_members)
+	}
+
+	fn parse(
+		inventory_txt: &str,
+		path: &Path,
+		etcd_members: EtcdMembers,
+	) -> Result<Inventory, InstallError> {
+		let inventory: Inventory = toml::from_str(inventory_txt)
+			.map_err(|err| InstallError::Config(format!("{}: {}", path.display(), err)))?;
+		let validated = match etcd_members {
+			EtcdMembers::Odd => inventory.validate(),
+			EtcdMembers::Any => inventory.validate_machines(),
+		};
+		validated.map_err(|err| InstallError::Config(format!("{}: {}", path.display(), err)))?;
+		Ok(inventory)
+	}
+
+	pub fn remove_machine(path: &Path, machine_id: &str) -> Result<(), InstallError> {
+		let updated_txt = Inventory::without_machine(&fs::read_to_string(path)?, path, machine_id)?;
+		let updated = Inventory::parse(&updated_txt, path, EtcdMembers::Any)?;
+		let tmp_path = path.with_extension("toml.tmp");
+		fs::write(&tmp_path, updated_txt)?;
+		fs::rename(&tmp_path, path)?;
+		info!("Removed machine {} from {}.", machine_id, path.display());
+		if let Err(err) = updated.validate() {
+			warn!(
+				"{}: {}, other commands refuse it until a control plane is added or removed.",
+				path.display(),
+				err
+			);
+		}
+		Ok(())
+	}
+
+	// Drops the machine's [[machine]] table, comments and formatting elsewhere stay as written.
+	fn without_machine(
+		inventory_txt: &str,
+		path: &Path,
+		machine_id: &str,
+	) -> Result<String, InstallError> {
+		let mut document: DocumentMut = inventory_txt
+			.parse()
+			.map_err(|err| InstallError::Config(format!("{}: {}", path.display(), err)))?;
+		let not_found = || {
+			InstallError::Config(format!(
+				"machine-id '{machine_id}' is not in {}",
+				path.display()
+			))
+		};
+		let machines = document
+			.get_mut("machine")
+			.and_then(Item::as_array_of_tables_mut)
+			.ok_or_else(not_found)?;
+		let count = machines.len();
+		machines.retain(|machine| machine.get("id").and_then(Item::as_str) != Some(machine_id));
+		if machines.len() == count {
+			return Err(not_found());
+		}
+		Ok(document.to_string())
+	}
+
+	pub fn validate(&self) -> Result<(), String> {
+		self.validate_machines()?;
+		let etcd_members = self
+			.machines
+			.iter()
+			.filter(|machine| machine.role.runs_etcd())
+			.count();
+		if etcd_members.is_multiple_of(2) {
+			return Err(format!(
+				"etcd needs an odd number of control plane machines, found {etcd_members}"
+			));
+		}
+		Ok(())
+	}
+
+	pub fn validate_machines(&self) -> Result<(), String> {
+		let mut ids = HashSet::new();
+		let mut hostnames = HashSet::new();
+		let mut ips = HashSet::new();
+		for machine in &self.machines {
+			if machine.id.len() != 32 || !machine.id.chars().all(|ch| ch.is_ascii_hexdigit()) {
+				return Err(format!(
+					"machine-id '{}' is not a 32 hex digit id",
+					machine.id
+				));
+			}
+			if !ids.insert(&machine.id) {
+				return Err(format!("duplicate machine-id '{}'", machine.id));
+			}
+			if let Some(hostname) = &machine.hostname
+				&& !hostnames.insert(hostname)
+			{
+				return Err(format!("duplicate hostname '{hostname}'"));
+			}
+			if let Some(ip) = &machine.ip
+				&& !ips.insert(ip)
+			{
+				return Err(format!("duplicate ip '{ip}'"));
+			}
+		}
+		let roots = self
+			.machines
+			.iter()
+			.filter(|machine| machine.role == MachineRole::ControlPlaneRoot)
+			.count();
+		if roots != 1 {
+			return Err(format!(
+				"expected exactly one ControlPlaneRoot machine, found {roots}"
+			));
+		}
+		Ok(())
+	}
+
+	pub fn root(&self) -> Option<&Machine> {
+		self.machines
+			.iter()
+			.find(|machine| machine.role == MachineRole::ControlPlaneRoot)
+	}
+}
+
+static INVENTORY: OnceLock<Inventory> = OnceLock::new();
+
+pub fn init(path: Option<&Path>, etcd_members: EtcdMembers) -> Result<(), InstallError> {
+	let inventory = match path {
+		Some(path) => Inventory::load(path, etcd_members)?,
+		None => match Inventory::load(Path::new(DEFAULT_PATH), etcd_members) {
+			Err(InstallError::Io(err)) if err.kind() == io::ErrorKind::NotFound => {
+				info!("No inventory at {DEFAULT_PATH}, this machine has no role.");
+				Inventory::default()
+			}
+			result => result?,
+		},
+	};
+	INVENTORY
+		.set(inventory)
+		.expect("Fatal inventory initialization.");
+	Ok(())
 }
 
-const INVENTORY: &[IMachine<'static>] = &[
-	IMachine {
-		id: "a218e8c2c31942e3acdbae7f4f532c2d",
-		environment: Environment::Dev,
-		role: MachineRole::ControlPlaneRoot,
-	},
-	IMachine {
-		id: "e65407e7fcd24bc58a7a20ce0b4992dd",
-		environment: Environment::Dev,
-		role: MachineRole::ControlPlane,
-	},
-	IMachine {
-		id: "75719c8d8ad84e2a8959733440b18233",
-		environment: Environment::Dev,
-		role: MachineRole::ControlPlane,
-	},
-	IMachine {
-		id: "ca9e447c051b4c18b154810ea3a4dc8a",
-		environment: Environment::Dev,
-		role: MachineRole::ControlPlane,
-	},
-	IMachine {
-		id: "4142f1ba2e8844d09cba6ea16e97dfa2",
-		environment: Environment::Dev,
-		role: MachineRole::ControlPlane,
-	},
-];
-
-pub fn this() -> Machine {
-	let this_imachine = INVENTORY
+#[cfg(test)]
+pub fn set(inventory: Inventory) {
+	INVENTORY
+		.set(inventory)
+		.expect("Fatal inventory initialization.");
+}
+
+pub fn get() -> &'static Inventory {
+	INVENTORY.get().expect("Fatal failure to get inventory.")
+}
+
+pub fn this() -> Result<&'static Machine, InstallError> {
+	let machine_id = &context::get().machine_id;
+	get()
+		.machines
 		.iter()
-		.find(|ma| ma.id == context::get().machine_id)
-		.expect("This machine is not in the inventory.");
-	Machine {
-		id: this_imachine.id.to_owned(),
-		_environment: this_imachine.environment,
-		role: this_imachine.role,
+		.find(|machine| machine.id == *machine_id)
+		.ok_or_else(|| {
+			InstallError::Config(format!("machine-id '{machine_id}' is not in the inventory"))
+		})
+}
+
+pub fn root() -> Result<&'static Machine, InstallError> {
+	get()
+		.root()
+		.ok_or_else(|| InstallError::Config("inventory has no ControlPlaneRoot".to_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::{env, process};
+
+	fn machine(id: char, role: &str) -> String {
+		format!(
+			"[[machine]]\nid = \"{}\"\nenvironment = \"Dev\"\nrole = \"{role}\"\n",
+			id.to_string().repeat(32)
+		)
+	}
+
+	fn control_planes(count: usize) -> String {
+		let mut inventory_txt = machine('0', "ControlPlaneRoot");
+		for id in "123456789".chars().take(count - 1) {
+			inventory_txt += &machine(id, "ControlPlane");
+		}
+		inventory_txt + &machine('f', "Worker")
+	}
+
+	fn validate(inventory_txt: &str) -> Result<(), String> {
+		toml::from_str::<Inventory>(inventory_txt)
+			.unwrap()
+			.validate()
+	}
+
+	#[test]
+	fn duplicates_are_refused() {
+		let root = machine('0', "ControlPlaneRoot");
+		assert_eq!(
+			validate(&(root.clone() + &machine('0', "Worker"))),
+			Err(format!("duplicate machine-id '{}'", "0".repeat(32)))
+		);
+		let worker = |id: char, field: &str| machine(id, "Worker") + field + "\n";
+		assert_eq!(
+			validate(
+				&(root.clone()
+					+ &worker('1', "hostname = \"w-1\"")
+					+ &worker('2', "hostname = \"w-1\""))
+			),
+			Err("duplicate hostname 'w-1'".to_owned())
+		);
+		assert_eq!(
+			validate(
+				&(root.clone()
+					+ &worker('1', "ip = \"10.0.0.5\"")
+					+ &worker('2', "ip = \"10.0.0.5\""))
+			),
+			Err("duplicate ip '10.0.0.5'".to_owned())
+		);
+		assert_eq!(
+			validate(
+				&(root + &worker('1', "ip = \"10.0.0.5\"") + &worker('2', "ip = \"10.0.0.6\""))
+			),
+			Ok(())
+		);
+	}
+
+	#[test]
+	fn exactly_one_root() {
+		assert_eq!(
+			validate(&machine('1', "Worker")),
+			Err("expected exactly one ControlPlaneRoot machine, found 0".to_owned())
+		);
+		assert_eq!(
+			validate(
+				&(machine('0', "ControlPlaneRoot")
+					+ &machine('1', "ControlPlaneRoot")
+					+ &machine('2', "ControlPlane"))
+			),
+			Err("expected exactly one ControlPlaneRoot machine, found 2".to_owned())
+		);
+		assert_eq!(validate(&machine('0', "ControlPlaneRoot")), Ok(()));
+	}
+
+	#[test]
+	fn machine_id_must_be_32_hex_digits() {
+		let short =
+			"[[machine]]\nid = \"abc\"\nenvironment = \"Dev\"\nrole = \"ControlPlaneRoot\"\n";
+		assert_eq!(
+			validate(short),
+			Err("machine-id 'abc' is not a 32 hex digit id".to_owned())
+		);
+		assert_eq!(
+			validate(&machine('g', "ControlPlaneRoot")),
+			Err(format!(
+				"machine-id '{}' is not a 32 hex digit id",
+				"g".repeat(32)
+			))
+		);
+	}
+
+	#[test]
+	fn even_etcd_count_is_refused() {
+		let path = Path::new("inventory.toml");
+		for count in [1, 3, 5] {
+			Inventory::parse(&control_planes(count), path, EtcdMembers::Odd).unwrap();
+		}
+		for count in [2, 4] {
+			let err = Inventory::parse(&control_planes(count), path, EtcdMembers::Odd).unwrap_err();
+			assert!(err.to_string().contains(&format!(
+				"etcd needs an odd number of control plane machines, found {count}"
+			)));
+		}
+	}
+
+	#[test]
+	fn remove_machine_keeps_the_rest_as_written() {
+		let inventory_txt = r#"# Lab cluster, keep the roots first.
+[[machine]]
+id = "00000000000000000000000000000000" # cp-1
+environment = "Dev"
+role = "ControlPlaneRoot"
+
+[[machine]]
+# Retiring this one.
+id   =   "11111111111111111111111111111111"
+environment = "Dev"
+role = "Worker"
+labels = { "8inary.io/pool" = "gpu" }
+
+[[machine]]
+id = "22222222222222222222222222222222"
+environment = "Dev"
+role = "Worker"
+disks = ["/dev/sdb"]
+"#;
+		let path = env::temp_dir().join(format!("inventory-{}.toml", process::id()));
+		fs::write(&path, inventory_txt).unwrap();
+		Inventory::remove_machine(&path, "11111111111111111111111111111111").unwrap();
+		let updated_txt = fs::read_to_string(&path).unwrap();
+		fs::remove_file(&path).unwrap();
+		assert_eq!(
+			updated_txt,
+			r#"# Lab cluster, keep the roots first.
+[[machine]]
+id = "00000000000000000000000000000000" # cp-1
+environment = "Dev"
+role = "ControlPlaneRoot"
+
+[[machine]]
+id = "22222222222222222222222222222222"
+environment = "Dev"
+role = "Worker"
+disks = ["/dev/sdb"]
+"#
+		);
+		let err = Inventory::without_machine(
+			&updated_txt,
+			Path::new("inventory.toml"),
+			"11111111111111111111111111111111",
+		)
+		.unwrap_err();
+		assert!(err.to_string().contains("is not in inventory.toml"));
+	}
+
+	#[test]
+	fn node_remove_accepts_even_etcd_count() {
+		let inventory = Inventory::parse(
+			&control_planes(2),
+			Path::new("inventory.toml"),
+			EtcdMembers::Any,
+		)
+		.unwrap();
+		assert_eq!(inventory.machines.len(), 3);
+		let err = Inventory::parse(
+			&(control_planes(2) + &machine('0', "Worker")),
+			Path::new("inventory.toml"),
+			EtcdMembers::Any,
+		)
+		.unwrap_err();
+		assert!(err.to_string().contains("duplicate machine-id"));
 	}
 }
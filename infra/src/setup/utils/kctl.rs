@@ -1,137 +1,613 @@
 use crate::error::InstallError;
+use crate::setup::utils::{cmd::Cmd, retry::Retry};
+use futures_util::StreamExt;
+use k8s_openapi::NamespaceResourceScope;
+use k8s_openapi::api::apps::v1::Deployment;
+use k8s_openapi::api::core::v1::{Node, Pod, Taint};
+use k8s_openapi::apiextensions_apiserver::pkg::apis::apiextensions::v1::CustomResourceDefinition;
+use kube::{
+	Api, Client, Config, Resource, ResourceExt,
+	api::{DeleteParams, DynamicObject, EvictParams, ListParams, Patch, PatchParams},
+	config::{KubeConfigOptions, Kubeconfig},
+	core::GroupVersionKind,
+	discovery::{self, Scope},
+	runtime::{WatchStreamExt, watcher},
+};
+use serde::{Serialize, de::DeserializeOwned};
+use serde_json::json;
 use std::{
-	io::Write,
-	process::{Command, Output, Stdio},
+	collections::BTreeMap,
+	fmt::Debug,
+	mem,
+	path::Path,
+	sync::OnceLock,
+	time::{Duration, Instant},
 };
+use tokio::runtime::Runtime;
+use tracing::{debug, info, warn};
 
 pub const KUBECONFIG: &str = "/etc/kubernetes/admin.conf";
+pub const KUBELET_KUBECONFIG: &str = "/etc/kubernetes/kubelet.conf";
+pub const FIELD_MANAGER: &str = "8inary";
 
-fn kubectl_status(args: &[&str]) -> Result<(), InstallError> {
-	let full_cmd = format!("kubectl {}", args.join(" "));
-	let status = Command::new("kubectl")
-		.args(["--kubeconfig", KUBECONFIG])
-		.args(args)
-		.status()
-		.map_err(|err| InstallError::CommandLaunch {
-			cmd: full_cmd.clone(),
-			source: err,
-		})?;
-	if !status.success() {
-		return Err(InstallError::CommandFailed {
-			cmd: full_cmd,
-			status,
-			stderr: None,
-		});
-	}
-	Ok(())
+static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+static ADMIN_KUBECONFIG: OnceLock<String> = OnceLock::new();
+
+// Steps are synchronous, API calls are driven to completion on a shared runtime.
+fn block_on<F: Future>(future: F) -> F::Output {
+	RUNTIME
+		.get_or_init(|| {
+			tokio::runtime::Builder::new_multi_thread()
+				.worker_threads(2)
+				.thread_name("kctl")
+				.enable_all()
+				.build()
+				.expect("Fatal Kubernetes client runtime initialization.")
+		})
+		.block_on(future)
 }
 
-fn kubectl_output(args: &[&str]) -> Result<Output, InstallError> {
-	let full_cmd = format!("kubectl {}", args.join(" "));
-	let output = Command::new("kubectl")
-		.args(["--kubeconfig", KUBECONFIG])
-		.args(args)
-		.output()
-		.map_err(|err| InstallError::CommandLaunch {
-			cmd: full_cmd.clone(),
-			source: err,
-		})?;
-	if !output.status.success() {
-		let stderr = if output.stderr.is_empty() {
-			None
-		} else {
-			Some(String::from_utf8_lossy(&output.stderr).trim().to_owned())
+// Off the cluster, e.g. for a rolling upgrade from a workstation, the root's admin kubeconfig stands in.
+pub fn use_admin_kubeconfig(kubeconfig: String) {
+	let _ = ADMIN_KUBECONFIG.set(kubeconfig);
+}
+
+fn has_kubeconfig(path: &str) -> bool {
+	(path == KUBECONFIG && ADMIN_KUBECONFIG.get().is_some()) || Path::new(path).exists()
+}
+
+fn config_from(path: &str) -> Result<Config, InstallError> {
+	block_on(async {
+		let kubeconfig = match ADMIN_KUBECONFIG.get() {
+			Some(admin) if path == KUBECONFIG => Kubeconfig::from_yaml(admin)?,
+			_ => Kubeconfig::read_from(path)?,
 		};
-		return Err(InstallError::CommandFailed {
-			cmd: full_cmd,
-			status: output.status,
-			stderr,
-		});
+		Ok(Config::from_custom_kubeconfig(kubeconfig, &KubeConfigOptions::default()).await?)
+	})
+}
+
+fn client_from(config: Config) -> Result<Client, InstallError> {
+	block_on(async { Ok(Client::try_from(config)?) })
+}
+
+// The admin kubeconfig is read on every call, kubeadm replaces it on init and reset.
+pub fn client() -> Result<Client, InstallError> {
+	client_from(config_from(KUBECONFIG)?)
+}
+
+// Workers only hold the kubelet credentials, either kubeconfig may read the version and nodes.
+fn node_client() -> Result<Option<Client>, InstallError> {
+	[KUBECONFIG, KUBELET_KUBECONFIG]
+		.into_iter()
+		.find(|path| has_kubeconfig(path))
+		.map(|path| client_from(config_from(path)?))
+		.transpose()
+}
+
+pub fn apiserver_version() -> Result<Option<String>, InstallError> {
+	let Some(client) = node_client()? else {
+		return Ok(None);
+	};
+	Ok(Some(block_on(client.apiserver_version())?.git_version))
+}
+
+pub fn get_node(name: &str) -> Result<Option<Node>, InstallError> {
+	let Some(client) = node_client()? else {
+		return Ok(None);
+	};
+	get(&Api::all(client), name)
+}
+
+pub fn cluster<K>() -> Result<Api<K>, InstallError>
+where
+	K: Resource<DynamicType = ()>,
+{
+	Ok(Api::all(client()?))
+}
+
+pub fn namespaced<K>(namespace: &str) -> Result<Api<K>, InstallError>
+where
+	K: Resource<Scope = NamespaceResourceScope, DynamicType = ()>,
+{
+	Ok(Api::namespaced(client()?, namespace))
+}
+
+pub fn get<K>(api: &Api<K>, name: &str) -> Result<Option<K>, InstallError>
+where
+	K: Resource + Clone + DeserializeOwned + Debug,
+{
+	Ok(block_on(api.get_opt(name))?)
+}
+
+// Server-side apply, this installer owns every field it sets.
+pub fn apply<K>(api: &Api<K>, resource: &K) -> Result<K, InstallError>
+where
+	K: Resource + Clone + DeserializeOwned + Serialize + Debug,
+{
+	let params = PatchParams::apply(FIELD_MANAGER).force();
+	Ok(block_on(api.patch(
+		&resource.name_any(),
+		&params,
+		&Patch::Apply(resource),
+	))?)
+}
+
+pub fn delete<K>(api: &Api<K>, name: &str) -> Result<(), InstallError>
+where
+	K: Resource + Clone + DeserializeOwned + Debug,
+{
+	match block_on(api.delete(name, &DeleteParams::default())) {
+		Ok(_) => Ok(()),
+		Err(kube::Error::Api(status)) if status.is_not_found() => Ok(()),
+		Err(err) => Err(err.into()),
 	}
-	Ok(output)
 }
 
-pub fn generate_yaml(create_args: &[&str]) -> Result<String, InstallError> {
-	let mut args = create_args.to_vec();
-	args.extend_from_slice(&["--dry-run=client", "-o", "yaml"]);
-	let output = kubectl_output(&args)?;
-	Ok(String::from_utf8_lossy(&output.stdout).to_string())
+pub fn list<K>(api: &Api<K>, label_selector: &str) -> Result<Vec<K>, InstallError>
+where
+	K: Resource + Clone + DeserializeOwned + Debug,
+{
+	let params = ListParams::default().labels(label_selector);
+	Ok(block_on(api.list(&params))?.items)
 }
 
-pub fn apply_yaml(yaml: &str) -> Result<(), InstallError> {
-	let mut child = Command::new("kubectl")
-		.args(["--kubeconfig", KUBECONFIG])
-		.args(["apply", "-f", "-"])
-		.stdin(Stdio::piped())
-		.stdout(Stdio::piped())
-		.stderr(Stdio::piped())
-		.spawn()
-		.map_err(|err| InstallError::CommandLaunch {
-			cmd: "kubectl apply -f -".to_owned(),
-			source: err,
-		})?;
-	let stdin = child
-		.stdin
-		.as_mut()
-		.ok_or_else(|| InstallError::Other("Failed to open stdin for kubectl apply".into()))?;
-	stdin.write_all(yaml.as_bytes())?;
-	let output = child
-		.wait_with_output()
-		.map_err(|err| InstallError::CommandLaunch {
-			cmd: "kubectl apply -f -".to_owned(),
-			source: err,
-		})?;
-	if !output.status.success() {
-		let stderr = Some(String::from_utf8_lossy(&output.stderr).trim().to_owned());
-		return Err(InstallError::CommandFailed {
-			cmd: "kubectl apply -f -".to_owned(),
-			status: output.status,
-			stderr,
-		});
+pub fn label<K>(api: &Api<K>, name: &str, labels: &[(&str, &str)]) -> Result<(), InstallError>
+where
+	K: Resource + Clone + DeserializeOwned + Debug,
+{
+	let labels: BTreeMap<&str, &str> = labels.iter().copied().collect();
+	let patch = json!({ "metadata": { "labels": labels } });
+	block_on(api.patch(name, &PatchParams::default(), &Patch::Merge(&patch)))?;
+	Ok(())
+}
+
+// The resource version makes a concurrent node update fail with a conflict instead of being lost.
+fn patch_taints(node: &Node, taints: Vec<Taint>) -> Result<(), InstallError> {
+	let patch = json!({
+		"metadata": { "resourceVersion": node.resource_version() },
+		"spec": { "taints": taints },
+	});
+	block_on(cluster::<Node>()?.patch(
+		&node.name_any(),
+		&PatchParams::default(),
+		&Patch::Merge(&patch),
+	))?;
+	Ok(())
+}
+
+fn node(name: &str) -> Result<Node, InstallError> {
+	get(&cluster::<Node>()?, name)?
+		.ok_or_else(|| InstallError::Kube(format!("Node {name} is not registered.")))
+}
+
+fn taints(node: &Node) -> Vec<Taint> {
+	node.spec
+		.as_ref()
+		.and_then(|spec| spec.taints.clone())
+		.unwrap_or_default()
+}
+
+pub fn untaint(node_name: &str, key: &str, effect: &str) -> Result<(), InstallError> {
+	let node = node(node_name)?;
+	let mut taints = taints(&node);
+	let count = taints.len();
+	taints.retain(|taint| taint.key != key || taint.effect != effect);
+	if taints.len() == count {
+		return Ok(());
 	}
+	patch_taints(&node, taints)
+}
+
+fn set_unschedulable(node: &str, unschedulable: bool) -> Result<(), InstallError> {
+	let patch = json!({ "spec": { "unschedulable": unschedulable } });
+	block_on(cluster::<Node>()?.patch(node, &PatchParams::default(), &Patch::Merge(&patch)))?;
 	Ok(())
 }
 
-pub fn apply(create_args: &[&str]) -> Result<(), InstallError> {
-	let yaml = generate_yaml(create_args)?;
-	apply_yaml(&yaml)?;
+pub fn cordon(node: &str) -> Result<(), InstallError> {
+	info!("Cordoning node {}.", node);
+	set_unschedulable(node, true)
+}
+
+pub fn uncordon(node: &str) -> Result<(), InstallError> {
+	info!("Uncordoning node {}.", node);
+	set_unschedulable(node, false)
+}
+
+// DaemonSet pods come straight back and mirror pods belong to the kubelet, drains leave both.
+fn is_evictable(pod: &Pod) -> bool {
+	let is_mirror = pod
+		.annotations()
+		.contains_key("kubernetes.io/config.mirror");
+	let is_daemon = pod
+		.owner_references()
+		.iter()
+		.any(|owner| owner.kind == "DaemonSet");
+	let is_finished = matches!(
+		pod.status
+			.as_ref()
+			.and_then(|status| status.phase.as_deref()),
+		Some("Succeeded" | "Failed")
+	);
+	!is_mirror && !is_daemon && !is_finished
+}
+
+// The timeout bounds the whole drain, every eviction and the final wait share one deadline.
+pub fn drain(node: &str, timeout: Duration) -> Result<(), InstallError> {
+	let deadline = Instant::now() + timeout;
+	cordon(node)?;
+	let client = client()?;
+	let on_node = format!("spec.nodeName={node}");
+	let pods =
+		block_on(Api::<Pod>::all(client.clone()).list(&ListParams::default().fields(&on_node)))?;
+	for pod in pods.items.iter().filter(|pod| is_evictable(pod)) {
+		let namespace = pod.namespace().unwrap_or_default();
+		let name = pod.name_any();
+		let api = Api::<Pod>::namespaced(client.clone(), &namespace);
+		// A disruption budget answers 429 until a replacement is ready elsewhere.
+		let evict = Retry::within(deadline.saturating_duration_since(Instant::now()))
+			.backoff(Duration::from_secs(5), Duration::from_secs(30));
+		evict.run(
+			&format!("evicting pod {namespace}/{name}"),
+			|| match block_on(api.evict(&name, &EvictParams::default())) {
+				Ok(_) => Ok(()),
+				Err(kube::Error::Api(status)) if status.is_not_found() => Ok(()),
+				Err(err) => Err(err.into()),
+			},
+		)?;
+	}
+	watch(
+		&Api::<Pod>::all(client),
+		watcher::Config::default().fields(&on_node),
+		&format!("node {node} to drain"),
+		deadline.saturating_duration_since(Instant::now()),
+		|pods| {
+			let remaining = pods.iter().filter(|pod| is_evictable(pod)).count();
+			(remaining > 0).then(|| format!("{remaining} pods still running"))
+		},
+	)
+}
+
+fn object_key<K: Resource>(object: &K) -> (String, String) {
+	(object.namespace().unwrap_or_default(), object.name_any())
+}
+
+// Watches until `pending` reports nothing left to wait for, logging each new reason to wait.
+pub fn watch<K>(
+	api: &Api<K>,
+	config: watcher::Config,
+	what: &str,
+	timeout: Duration,
+	mut pending: impl FnMut(&[&K]) -> Option<String>,
+) -> Result<(), InstallError>
+where
+	K: Resource + Clone + DeserializeOwned + Debug + Send + 'static,
+{
+	info!("Waiting up to {}s for {}.", timeout.as_secs(), what);
+	let mut waiting_on = String::from("no response from the API server");
+	let watching = async {
+		let mut events = watcher(api.clone(), config).default_backoff().boxed();
+		let mut objects = BTreeMap::new();
+		let mut initial = BTreeMap::new();
+		while let Some(event) = events.next().await {
+			match event {
+				Ok(watcher::Event::Init) => initial.clear(),
+				Ok(watcher::Event::InitApply(object)) => {
+					initial.insert(object_key(&object), object);
+					continue;
+				}
+				Ok(watcher::Event::InitDone) => objects = mem::take(&mut initial),
+				Ok(watcher::Event::Apply(object)) => {
+					objects.insert(object_key(&object), object);
+				}
+				Ok(watcher::Event::Delete(object)) => {
+					objects.remove(&object_key(&object));
+				}
+				Err(err) => {
+					warn!("Watch of {} interrupted: {}", what, err);
+					continue;
+				}
+			}
+			let current: Vec<&K> = objects.values().collect();
+			match pending(&current) {
+				None => return true,
+				Some(reason) if reason != waiting_on => {
+					info!("Waiting for {}: {}.", what, reason);
+					waiting_on = reason;
+				}
+				Some(_) => {}
+			}
+		}
+		false
+	};
+	match block_on(async { tokio::time::timeout(timeout, watching).await }) {
+		Ok(true) => {
+			info!("Done waiting for {}.", what);
+			Ok(())
+		}
+		Ok(false) | Err(_) => Err(InstallError::NotReady(format!(
+			"{what} after {}s, {waiting_on}",
+			timeout.as_secs()
+		))),
+	}
+}
+
+fn named(name: &str) -> watcher::Config {
+	watcher::Config::default().fields(&format!("metadata.name={name}"))
+}
+
+fn condition_pending<'a>(
+	conditions: impl IntoIterator<Item = (&'a str, &'a str, Option<&'a String>)>,
+	wanted: &str,
+) -> Option<String> {
+	match conditions.into_iter().find(|(kind, ..)| *kind == wanted) {
+		Some((_, "True", _)) => None,
+		Some((_, status, message)) => Some(format!(
+			"{wanted} is {status}{}",
+			message
+				.map(|message| format!(", {message}"))
+				.unwrap_or_default()
+		)),
+		None => Some(format!("{wanted} is not reported yet")),
+	}
+}
+
+pub fn wait_node_ready(name: &str, timeout: Duration) -> Result<(), InstallError> {
+	watch(
+		&cluster::<Node>()?,
+		named(name),
+		&format!("node {name} to be ready"),
+		timeout,
+		|nodes| match nodes.first() {
+			Some(node) => node_pending(node),
+			None => Some("the node is not registered".to_owned()),
+		},
+	)
+}
+
+// Why the node is not Ready, or None once it is.
+pub fn node_pending(node: &Node) -> Option<String> {
+	let conditions = node
+		.status
+		.as_ref()
+		.and_then(|status| status.conditions.as_ref())
+		.into_iter()
+		.flatten()
+		.map(|condition| {
+			(
+				condition.type_.as_str(),
+				condition.status.as_str(),
+				condition.message.as_ref(),
+			)
+		});
+	condition_pending(conditions, "Ready")
+}
+
+pub fn wait_deployment_available(
+	namespace: &str,
+	name: &str,
+	timeout: Duration,
+) -> Result<(), InstallError> {
+	watch(
+		&namespaced::<Deployment>(namespace)?,
+		named(name),
+		&format!("deployment {namespace}/{name} to be available"),
+		timeout,
+		|deployments| {
+			let Some(deployment) = deployments.first() else {
+				return Some("the deployment does not exist".to_owned());
+			};
+			let Some(status) = &deployment.status else {
+				return Some("no status reported yet".to_owned());
+			};
+			let generation = deployment.metadata.generation.unwrap_or_default();
+			if status.observed_generation.unwrap_or_default() < generation {
+				return Some("the rollout has not been observed yet".to_owned());
+			}
+			let wanted = deployment
+				.spec
+				.as_ref()
+				.and_then(|spec| spec.replicas)
+				.unwrap_or(1);
+			let updated = status.updated_replicas.unwrap_or_default();
+			let available = status.available_replicas.unwrap_or_default();
+			if updated < wanted || available < wanted {
+				return Some(format!(
+					"{updated} of {wanted} replicas updated, {available} available"
+				));
+			}
+			let conditions = status.conditions.iter().flatten().map(|condition| {
+				(
+					condition.type_.as_str(),
+					condition.status.as_str(),
+					condition.message.as_ref(),
+				)
+			});
+			condition_pending(conditions, "Available")
+		},
+	)
+}
+
+fn is_pod_ready(pod: &Pod) -> bool {
+	pod.status
+		.as_ref()
+		.and_then(|status| status.conditions.as_ref())
+		.into_iter()
+		.flatten()
+		.any(|condition| condition.type_ == "Ready" && condition.status == "True")
+}
+
+pub fn wait_pods_ready(
+	namespace: &str,
+	selector: &str,
+	timeout: Duration,
+) -> Result<(), InstallError> {
+	watch(
+		&namespaced::<Pod>(namespace)?,
+		watcher::Config::default().labels(selector),
+		&format!("pods {selector} in {namespace} to be ready"),
+		timeout,
+		|pods| {
+			let ready = pods.iter().filter(|pod| is_pod_ready(pod)).count();
+			match pods.len() {
+				0 => Some("no pods match".to_owned()),
+				total if ready < total => Some(format!("{ready} of {total} pods ready")),
+				_ => None,
+			}
+		},
+	)
+}
+
+pub fn wait_crd_established(name: &str, timeout: Duration) -> Result<(), InstallError> {
+	watch(
+		&cluster::<CustomResourceDefinition>()?,
+		named(name),
+		&format!("custom resource {name} to be established"),
+		timeout,
+		|crds| {
+			let Some(crd) = crds.first() else {
+				return Some("the definition does not exist".to_owned());
+			};
+			let conditions = crd
+				.status
+				.as_ref()
+				.and_then(|status| status.conditions.as_ref())
+				.into_iter()
+				.flatten()
+				.map(|condition| {
+					(
+						condition.type_.as_str(),
+						condition.status.as_str(),
+						condition.message.as_ref(),
+					)
+				});
+			condition_pending(conditions, "Established")
+		},
+	)
+}
+
+// Goes through `vip` instead of the kubeconfig server, a healthy answer proves the VIP is held.
+pub fn wait_apiserver_healthy(vip: &str, timeout: Duration) -> Result<(), InstallError> {
+	let what = format!("the API server at {vip}");
+	info!("Waiting up to {}s for {}.", timeout.as_secs(), what);
+	let mut config = config_from(KUBECONFIG)?;
+	config.cluster_url = format!("https://{vip}")
+		.parse()
+		.map_err(|err| InstallError::Config(format!("API server address {vip}: {err}")))?;
+	let client = client_from(config)?;
+	Retry::within(timeout)
+		.backoff(Duration::from_secs(2), Duration::from_secs(10))
+		.run(&what, || {
+			let request = http::Request::get("/readyz?verbose")
+				.body(Vec::new())
+				.map_err(|err| InstallError::Other(err.into()))?;
+			block_on(client.request_text(request))?;
+			Ok(())
+		})?;
+	info!("API server at {} is healthy.", vip);
 	Ok(())
 }
 
+fn manifest_objects(yaml: &str) -> Result<Vec<DynamicObject>, InstallError> {
+	let documents: Vec<serde_json::Value> = serde_saphyr::from_multiple(yaml)
+		.map_err(|err| InstallError::Kube(format!("Invalid manifest: {err}")))?;
+	documents
+		.into_iter()
+		.filter(|document| !document.is_null())
+		.map(|document| {
+			serde_json::from_value(document)
+				.map_err(|err| InstallError::Kube(format!("Invalid manifest object: {err}")))
+		})
+		.collect()
+}
+
+async fn dynamic_api(
+	client: &Client,
+	object: &DynamicObject,
+	namespace: Option<&str>,
+) -> Result<Api<DynamicObject>, InstallError> {
+	let types = object.types.as_ref().ok_or_else(|| {
+		InstallError::Kube(format!("{} has no apiVersion or kind.", object.name_any()))
+	})?;
+	let gvk =
+		GroupVersionKind::try_from(types).map_err(|err| InstallError::Kube(err.to_string()))?;
+	let (resource, capabilities) = discovery::pinned_kind(client, &gvk).await?;
+	Ok(match capabilities.scope {
+		Scope::Namespaced => {
+			let namespace = object
+				.namespace()
+				.or(namespace.map(str::to_owned))
+				.unwrap_or_else(|| "default".to_owned());
+			Api::namespaced_with(client.clone(), &namespace, &resource)
+		}
+		Scope::Cluster => Api::all_with(client.clone(), &resource),
+	})
+}
+
+fn apply_manifest(yaml: &str, namespace: Option<&str>) -> Result<(), InstallError> {
+	let objects = manifest_objects(yaml)?;
+	let client = client()?;
+	let params = PatchParams::apply(FIELD_MANAGER).force();
+	block_on(async {
+		for object in &objects {
+			let api = dynamic_api(&client, object, namespace).await?;
+			debug!("Applying {}.", object.name_any());
+			api.patch(&object.name_any(), &params, &Patch::Apply(object))
+				.await?;
+		}
+		Ok(())
+	})
+}
+
+// Manifests are embedded in tab indented source, YAML only allows spaces.
+pub fn dedent(yaml: &str) -> String {
+	let leading_tabs = |line: &str| line.len() - line.trim_start_matches('\t').len();
+	let indent = yaml
+		.lines()
+		.filter(|line| !line.trim().is_empty())
+		.map(leading_tabs)
+		.min()
+		.unwrap_or(0);
+	yaml.lines()
+		.map(|line| {
+			let line = &line[leading_tabs(line).min(indent)..];
+			let tabs = leading_tabs(line);
+			"    ".repeat(tabs) + &line[tabs..] + "\n"
+		})
+		.collect()
+}
+
+pub fn apply_yaml(yaml: &str) -> Result<(), InstallError> {
+	apply_manifest(yaml, None)
+}
+
+fn fetch(url: &str) -> Result<String, InstallError> {
+	Ok(Cmd::new("curl").args(["-fsSL", url]).output()?.stdout)
+}
+
+pub fn apply_url(url: &str, namespace: Option<&str>) -> Result<(), InstallError> {
+	apply_manifest(&fetch(url)?, namespace)
+}
+
+pub fn delete_url(url: &str) -> Result<(), InstallError> {
+	let objects = manifest_objects(&fetch(url)?)?;
+	let client = client()?;
+	block_on(async {
+		for object in &objects {
+			let api = dynamic_api(&client, object, None).await?;
+			match api
+				.delete(&object.name_any(), &DeleteParams::default())
+				.await
+			{
+				Ok(_) => debug!("Deleted {}.", object.name_any()),
+				Err(kube::Error::Api(status)) if status.is_not_found() => {}
+				Err(err) => return Err(err.into()),
+			}
+		}
+		Ok(())
+	})
+}
+
 pub fn is_deployment_installed(name: &str, namespace: &str) -> Result<bool, InstallError> {
-	let status = Command::new("kubectl")
-		.args(["--kubeconfig", KUBECONFIG])
-		.args(["get", "deployment", name])
-		.args(["-n", namespace])
-		.stdout(Stdio::null())
-		.stderr(Stdio::null())
-		.status()
-		.map_err(|err| InstallError::CommandLaunch {
-			cmd: format!("kubectl get deployment {name} -n {namespace}"),
-			source: err,
-		})?;
-	Ok(status.success())
-}
-
-pub fn get_pods(namespace: &str, label: &str) -> Result<String, InstallError> {
-	let output = Command::new("kubectl")
-		.args(["--kubeconfig", KUBECONFIG])
-		.args(["get", "pods"])
-		.args(["--namespace", namespace])
-		.args(["-l", label])
-		.args(["-o", "name"])
-		.output()
-		.map_err(|err| InstallError::CommandLaunch {
-			cmd: format!("kubectl get pods -n {namespace} -l {label}"),
-			source: err,
-		})?;
-	if !output.status.success() {
-		let stderr = Some(String::from_utf8_lossy(&output.stderr).trim().to_owned());
-		return Err(InstallError::CommandFailed {
-			cmd: format!("kubectl get pods -n {namespace} -l {label}"),
-			status: output.status,
-			stderr,
-		});
-	}
-	Ok(String::from_utf8_lossy(&output.stdout).to_string())
+	Ok(get(&namespaced::<Deployment>(namespace)?, name)?.is_some())
 }
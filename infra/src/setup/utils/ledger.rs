@@ -0,0 +1,227 @@
+use crate::config;
+use crate::error::InstallError;
+use jiff::Timestamp;
+use serde::{Deserialize, Serialize};
+use std::{
+	collections::BTreeMap,
+	fs::{self, File},
+	io,
+	path::{Path, PathBuf},
+	sync::{Mutex, MutexGuard, OnceLock, PoisonError},
+};
+use tracing::info;
+
+pub const DEFAULT_PATH: &str = "/var/lib/8inary/state.json";
+const HISTORY_LIMIT: usize = 500;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+	Applied,
+	Adopted,
+	Upgraded,
+	Reset,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StepRecord {
+	pub applied_at: Timestamp,
+	pub config_hash: String,
+	#[serde(default)]
+	pub versions: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Event {
+	pub at: Timestamp,
+	pub step: String,
+	pub action: Action,
+	pub config_hash: String,
+	#[serde(default)]
+	pub versions: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Ledger {
+	#[serde(default)]
+	pub steps: BTreeMap<String, StepRecord>,
+	#[serde(default)]
+	pub history: Vec<Event>,
+}
+
+impl Ledger {
+	pub fn load(path: &Path) -> Result<Ledger, InstallError> {
+		let ledger_txt = match fs::read_to_string(path) {
+			Ok(ledger_txt) => ledger_txt,
+			Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Ledger::default()),
+			Err(err) => return Err(err.into()),
+		};
+		serde_json::from_str(&ledger_txt)
+			.map_err(|err| InstallError::Config(format!("{}: {}", path.display(), err)))
+	}
+
+	// Written to a sibling file and renamed, a crash never leaves half a ledger.
+	pub fn save(&self, path: &Path) -> Result<(), InstallError> {
+		if let Some(dir) = path.parent() {
+			fs::create_dir_all(dir)?;
+		}
+		let ledger_txt =
+			serde_json::to_string_pretty(self).map_err(|err| InstallError::Other(err.into()))?;
+		let tmp_path = path.with_extension("json.tmp");
+		fs::write(&tmp_path, ledger_txt + "\n")?;
+		fs::rename(&tmp_path, path)?;
+		Ok(())
+	}
+
+	pub fn record(&mut self, step: &str, action: Action, versions: BTreeMap<String, String>) {
+		let at = Timestamp::now();
+		let config_hash = config::get().hash();
+		match action {
+			Action::Applied | Action::Adopted | Action::Upgraded => {
+				self.steps.insert(
+					step.to_owned(),
+					StepRecord {
+						applied_at: at,
+						config_hash: config_hash.clone(),
+						versions: versions.clone(),
+					},
+				);
+			}
+			Action::Reset => {
+				self.steps.remove(step);
+			}
+		}
+		self.history.push(Event {
+			at,
+			step: step.to_owned(),
+			action,
+			config_hash,
+			versions,
+		});
+		let excess = self.history.len().saturating_sub(HISTORY_LIMIT);
+		self.history.drain(..excess);
+	}
+
+	// watch, upgrade phases and manual runs share the file, each record is applied to what is on disk.
+	pub fn record_to(
+		&mut self,
+		path: &Path,
+		step: &str,
+		action: Action,
+		versions: BTreeMap<String, String>,
+	) -> Result<(), InstallError> {
+		if let Some(dir) = path.parent() {
+			fs::create_dir_all(dir)?;
+		}
+		let lock = File::create(path.with_extension("json.lock"))?;
+		lock.lock()?;
+		*self = Ledger::load(path)?;
+		self.record(step, action, versions);
+		self.save(path)
+	}
+}
+
+#[derive(Debug)]
+struct Store {
+	path: Option<PathBuf>,
+	ledger: Mutex<Ledger>,
+}
+
+static STORE: OnceLock<Store> = OnceLock::new();
+
+// Without a path the ledger starts empty and only lives for this run.
+pub fn init(path: Option<&Path>) -> Result<(), InstallError> {
+	let ledger = match path {
+		Some(path) => Ledger::load(path)?,
+		None => {
+			info!("State ledger is not persisted for this run.");
+			Ledger::default()
+		}
+	};
+	let store = Store {
+		path: path.map(Path::to_path_buf),
+		ledger: Mutex::new(ledger),
+	};
+	STORE.set(store).expect("Fatal ledger initialization.");
+	Ok(())
+}
+
+fn lock() -> MutexGuard<'static, Ledger> {
+	STORE
+		.get()
+		.expect("Fatal failure to get ledger.")
+		.ledger
+		.lock()
+		.unwrap_or_else(PoisonError::into_inner)
+}
+
+pub fn applied(step: &str) -> Option<StepRecord> {
+	lock().steps.get(step).cloned()
+}
+
+pub fn history() -> Vec<Event> {
+	lock().history.clone()
+}
+
+pub fn record(
+	step: &str,
+	action: Action,
+	versions: Vec<(&'static str, String)>,
+) -> Result<(), InstallError> {
+	let versions = versions
+		.into_iter()
+		.map(|(component, version)| (component.to_owned(), version))
+		.collect();
+	let mut ledger = lock();
+	match &STORE.get().expect("Fatal failure to get ledger.").path {
+		Some(path) => ledger.record_to(path, step, action, versions),
+		None => {
+			ledger.record(step, action, versions);
+			Ok(())
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::setup::testing;
+	use std::{env, process};
+
+	#[test]
+	fn concurrent_writers_keep_each_others_records() {
+		testing::init();
+		let dir = env::temp_dir().join(format!("ledger-{}", process::id()));
+		let path = dir.join("state.json");
+		let mut watch = Ledger::load(&path).unwrap();
+		let mut apply = Ledger::load(&path).unwrap();
+		watch
+			.record_to(&path, "Sysctl", Action::Applied, BTreeMap::new())
+			.unwrap();
+		apply
+			.record_to(&path, "Kubes", Action::Upgraded, BTreeMap::new())
+			.unwrap();
+		watch
+			.record_to(&path, "Firewall", Action::Adopted, BTreeMap::new())
+			.unwrap();
+		let ledger = Ledger::load(&path).unwrap();
+		fs::remove_dir_all(&dir).unwrap();
+		assert_eq!(
+			ledger.steps.keys().collect::<Vec<_>>(),
+			["Firewall", "Kubes", "Sysctl"]
+		);
+		assert_eq!(
+			ledger
+				.history
+				.iter()
+				.map(|event| (event.step.as_str(), event.action))
+				.collect::<Vec<_>>(),
+			[
+				("Sysctl", Action::Applied),
+				("Kubes", Action::Upgraded),
+				("Firewall", Action::Adopted),
+			]
+		);
+		assert_eq!(watch.steps.len(), 3);
+	}
+}
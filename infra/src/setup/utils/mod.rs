@@ -1,3 +1,8 @@
+pub mod cmd;
 pub mod inventory;
 pub mod kctl;
+pub mod ledger;
 pub mod pkg;
+pub mod retry;
+pub mod systemd;
+pub mod version;
@@ -1,82 +1,423 @@
 use crate::error::InstallError;
-use std::process::Command;
+use crate::setup::utils::{cmd::Cmd, version::Version};
+use std::{
+	fs,
+	sync::{Mutex, MutexGuard, OnceLock, PoisonError},
+};
+use tracing::info;
 
+pub const OS_RELEASE_PATH: &str = "/etc/os-release";
+pub const PACMAN_CONFIG_PATH: &str = "/etc/pacman.conf";
+const PACMAN_HOLD_MARKER: &str = "# Held by 8inary.";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum PkgManager {
 	Apt,
+	Dnf,
+	Pacman,
 }
 
-fn get_pkg_manager() -> PkgManager {
-	PkgManager::Apt
+// Steps run concurrently, but the package manager holds a system wide lock.
+static LOCK: Mutex<()> = Mutex::new(());
+static MANAGER: OnceLock<PkgManager> = OnceLock::new();
+
+fn lock() -> MutexGuard<'static, ()> {
+	LOCK.lock().unwrap_or_else(PoisonError::into_inner)
 }
 
-pub fn is_installed(package_name: &str) -> Result<bool, InstallError> {
-	let installed;
-	match get_pkg_manager() {
+fn os_release_field<'a>(os_release: &'a str, key: &str) -> Option<&'a str> {
+	os_release.lines().find_map(|line| {
+		let value = line.strip_prefix(key)?.strip_prefix('=')?;
+		Some(value.trim().trim_matches('"').trim_matches('\''))
+	})
+}
+
+// ID_LIKE covers derivatives, e.g. Rocky Linux is "rhel centos fedora".
+fn detect(os_release: &str) -> Result<PkgManager, InstallError> {
+	let id = os_release_field(os_release, "ID").unwrap_or_default();
+	let id_like = os_release_field(os_release, "ID_LIKE").unwrap_or_default();
+	for distro in std::iter::once(id).chain(id_like.split_whitespace()) {
+		match distro {
+			"debian" | "ubuntu" => return Ok(PkgManager::Apt),
+			"fedora" | "rhel" | "centos" => return Ok(PkgManager::Dnf),
+			"arch" => return Ok(PkgManager::Pacman),
+			_ => {}
+		}
+	}
+	Err(InstallError::Config(format!(
+		"unsupported distribution '{id}' in {OS_RELEASE_PATH}"
+	)))
+}
+
+pub fn get_pkg_manager() -> Result<PkgManager, InstallError> {
+	if let Some(manager) = MANAGER.get() {
+		return Ok(*manager);
+	}
+	let manager = detect(&fs::read_to_string(OS_RELEASE_PATH)?)?;
+	info!("Using the {:?} package manager.", manager);
+	Ok(*MANAGER.get_or_init(|| manager))
+}
+
+fn installed(manager: PkgManager, package_name: &str) -> Result<bool, InstallError> {
+	let installed = match manager {
 		PkgManager::Apt => {
-			let output = Command::new("dpkg-query")
+			let output = Cmd::new("dpkg-query")
 				.args(["-W", "-f=${Status}", package_name])
-				.output()
-				.map_err(|source| InstallError::CommandLaunch {
-					cmd: format!("dpkg-query -W -f=${{Status}}) {package_name}"),
-					source,
-				})?;
-			if !output.status.success() {
+				.probe()?;
+			if !output.success() {
 				return Ok(false);
 			}
-			let stdout = String::from_utf8_lossy(&output.stdout);
-			let status = stdout.trim();
-			installed = status == "install ok installed" || status == "hold ok installed";
+			let status = output.stdout.trim();
+			status == "install ok installed" || status == "hold ok installed"
 		}
-	}
+		PkgManager::Dnf => Cmd::new("rpm")
+			.args(["-q", package_name])
+			.probe()?
+			.success(),
+		PkgManager::Pacman => Cmd::new("pacman")
+			.args(["-Q", package_name])
+			.probe()?
+			.success(),
+	};
 	Ok(installed)
 }
 
+pub fn is_installed(package_name: &str) -> Result<bool, InstallError> {
+	let manager = get_pkg_manager()?;
+	let _lock = lock();
+	installed(manager, package_name)
+}
+
+fn package_version(
+	manager: PkgManager,
+	package_name: &str,
+) -> Result<Option<String>, InstallError> {
+	let output = match manager {
+		PkgManager::Apt => Cmd::new("dpkg-query")
+			.args(["-W", "-f=${Status}\t${Version}", package_name])
+			.probe()?,
+		PkgManager::Dnf => Cmd::new("rpm")
+			.args(["-q", "--qf", "%{VERSION}-%{RELEASE}", package_name])
+			.probe()?,
+		PkgManager::Pacman => Cmd::new("pacman").args(["-Q", package_name]).probe()?,
+	};
+	if !output.success() {
+		return Ok(None);
+	}
+	let stdout = output.stdout.trim();
+	let version = match manager {
+		PkgManager::Apt => match stdout.split_once('\t') {
+			Some(("install ok installed" | "hold ok installed", version)) => version,
+			_ => return Ok(None),
+		},
+		PkgManager::Dnf => stdout,
+		PkgManager::Pacman => stdout.split_whitespace().nth(1).unwrap_or_default(),
+	};
+	Ok(Some(version.to_owned()).filter(|version| !version.is_empty()))
+}
+
+// The full package version, e.g. "1.34.2-1.1", or None when the package is not installed.
+pub fn installed_version(package_name: &str) -> Result<Option<String>, InstallError> {
+	let manager = get_pkg_manager()?;
+	let _lock = lock();
+	package_version(manager, package_name)
+}
+
 pub fn update() -> Result<(), InstallError> {
-	match get_pkg_manager() {
-		PkgManager::Apt => {
-			Command::new("apt-get")
-				.arg("update")
-				.status()
-				.map_err(|err| InstallError::CommandLaunch {
-					cmd: "apt-get update".to_owned(),
-					source: err,
-				})?;
+	let manager = get_pkg_manager()?;
+	let _lock = lock();
+	match manager {
+		PkgManager::Apt => Cmd::new("apt-get").arg("update").run(),
+		PkgManager::Dnf => Cmd::new("dnf").args(["makecache", "-y"]).run(),
+		PkgManager::Pacman => Cmd::new("pacman").args(["-Sy", "--noconfirm"]).run(),
+	}
+}
+
+pub fn install(package_names: &[&str]) -> Result<(), InstallError> {
+	let manager = get_pkg_manager()?;
+	let _lock = lock();
+	match manager {
+		PkgManager::Apt => Cmd::new("apt-get")
+			.args(["install", "-y", "--no-install-recommends"])
+			.args(package_names)
+			.env("DEBIAN_FRONTEND", "noninteractive")
+			.run(),
+		PkgManager::Dnf => Cmd::new("dnf")
+			.args(["install", "-y"])
+			.args(package_names)
+			.run(),
+		PkgManager::Pacman => Cmd::new("pacman")
+			.args(["-S", "--noconfirm", "--needed"])
+			.args(package_names)
+			.run(),
+	}
+}
+
+// Installs upstream `version` of every package, moving held packages as well.
+pub fn install_pinned(package_names: &[&str], version: &str) -> Result<(), InstallError> {
+	let manager = get_pkg_manager()?;
+	let _lock = lock();
+	match manager {
+		PkgManager::Apt => Cmd::new("apt-get")
+			.args(["install", "-y", "--no-install-recommends"])
+			.args(["--allow-change-held-packages", "--allow-downgrades"])
+			.args(
+				package_names
+					.iter()
+					.map(|package_name| format!("{package_name}={version}-*")),
+			)
+			.env("DEBIAN_FRONTEND", "noninteractive")
+			.run()?,
+		PkgManager::Dnf => {
+			// A version lock from an earlier install would hide the requested version.
+			Cmd::new("dnf")
+				.args(["versionlock", "delete"])
+				.args(package_names)
+				.probe()?;
+			// dnf only installs missing packages, distro-sync moves installed ones up or down.
+			let mut missing = Vec::new();
+			let mut present = Vec::new();
+			for package_name in package_names {
+				let spec = format!("{package_name}-{version}");
+				if installed(manager, package_name)? {
+					present.push(spec);
+				} else {
+					missing.push(spec);
+				}
+			}
+			if !missing.is_empty() {
+				Cmd::new("dnf")
+					.args(["install", "-y"])
+					.args(missing)
+					.run()?;
+			}
+			if !present.is_empty() {
+				Cmd::new("dnf")
+					.args(["distro-sync", "-y"])
+					.args(present)
+					.run()?;
+			}
+		}
+		// Arch only ships the latest release, the version check below reports a mismatch.
+		PkgManager::Pacman => Cmd::new("pacman")
+			.args(["-S", "--noconfirm", "--needed"])
+			.args(package_names)
+			.run()?,
+	}
+	for package_name in package_names {
+		let installed = package_version(manager, package_name)?;
+		let installed_upstream = installed
+			.as_deref()
+			.and_then(|installed| Version::parse(installed).ok());
+		if installed_upstream.is_none() || installed_upstream != Version::parse(version).ok() {
+			return Err(InstallError::Package(format!(
+				"{package_name} {} was installed, {version} is configured",
+				installed.as_deref().unwrap_or("nothing")
+			)));
 		}
 	}
 	Ok(())
 }
 
-pub fn install(package_names: &[&str]) -> Result<(), InstallError> {
-	match get_pkg_manager() {
-		PkgManager::Apt => {
-			let args = ["install", "-y", "--no-install-recommends"];
-			Command::new("apt-get")
-				.args(args.iter().chain(package_names.iter()))
-				.status()
-				.map_err(|err| InstallError::CommandLaunch {
-					cmd: format!(
-						"apt-get install -y --no-install-recommends {}",
-						package_names.join(", ")
-					),
-					source: err,
-				})?;
+// pacman has no hold command, held packages are an IgnorePkg line below a marker in its config.
+fn pacman_holds(config: &str) -> Vec<String> {
+	config
+		.lines()
+		.skip_while(|line| *line != PACMAN_HOLD_MARKER)
+		.nth(1)
+		.and_then(|line| line.strip_prefix("IgnorePkg"))
+		.and_then(|line| line.trim_start().strip_prefix('='))
+		.map(|names| names.split_whitespace().map(str::to_owned).collect())
+		.unwrap_or_default()
+}
+
+// Rewrites only the marker and its IgnorePkg line, the rest of the config is the admin's.
+fn with_pacman_holds(config: &str, holds: &[String]) -> Result<String, InstallError> {
+	let mut lines = Vec::new();
+	let mut skip_next = false;
+	for line in config.lines() {
+		if skip_next {
+			skip_next = false;
+			continue;
+		}
+		if line == PACMAN_HOLD_MARKER {
+			skip_next = true;
+			continue;
+		}
+		lines.push(line.to_owned());
+		if line.trim() == "[options]" && !holds.is_empty() {
+			lines.push(PACMAN_HOLD_MARKER.to_owned());
+			lines.push(format!("IgnorePkg = {}", holds.join(" ")));
 		}
 	}
+	if !holds.is_empty() && !lines.iter().any(|line| line == PACMAN_HOLD_MARKER) {
+		return Err(InstallError::Config(format!(
+			"no [options] section in {PACMAN_CONFIG_PATH}"
+		)));
+	}
+	Ok(lines.join("\n") + "\n")
+}
+
+fn write_pacman_holds(holds: &[String]) -> Result<(), InstallError> {
+	let config = fs::read_to_string(PACMAN_CONFIG_PATH)?;
+	fs::write(PACMAN_CONFIG_PATH, with_pacman_holds(&config, holds)?)?;
 	Ok(())
 }
 
 pub fn mark(package_names: &[&str]) -> Result<(), InstallError> {
-	match get_pkg_manager() {
-		PkgManager::Apt => {
-			let args = ["hold"];
-			Command::new("apt-mark")
-				.args(args.iter().chain(package_names.iter()))
-				.status()
-				.map_err(|err| InstallError::CommandLaunch {
-					cmd: format!("apt-mark hold {}", package_names.join(", ")),
-					source: err,
-				})?;
+	let manager = get_pkg_manager()?;
+	let _lock = lock();
+	match manager {
+		PkgManager::Apt => Cmd::new("apt-mark").arg("hold").args(package_names).run(),
+		PkgManager::Dnf => {
+			Cmd::new("dnf")
+				.args(["install", "-y", "dnf-command(versionlock)"])
+				.run()?;
+			Cmd::new("dnf")
+				.args(["versionlock", "add"])
+				.args(package_names)
+				.run()
+		}
+		PkgManager::Pacman => {
+			let mut holds = pacman_holds(&fs::read_to_string(PACMAN_CONFIG_PATH)?);
+			for package_name in package_names {
+				if !holds.iter().any(|hold| hold == package_name) {
+					holds.push((*package_name).to_owned());
+				}
+			}
+			write_pacman_holds(&holds)
 		}
 	}
-	Ok(())
+}
+
+pub fn unmark(package_names: &[&str]) -> Result<(), InstallError> {
+	let manager = get_pkg_manager()?;
+	let _lock = lock();
+	match manager {
+		PkgManager::Apt => Cmd::new("apt-mark").arg("unhold").args(package_names).run(),
+		PkgManager::Dnf => {
+			let output = Cmd::new("dnf")
+				.args(["versionlock", "delete"])
+				.args(package_names)
+				.probe()?;
+			// Nothing to unlock when versionlock was never set up.
+			if !output.success() {
+				info!("No version locks removed: {}", output.stderr.trim());
+			}
+			Ok(())
+		}
+		PkgManager::Pacman => {
+			let holds: Vec<String> = pacman_holds(&fs::read_to_string(PACMAN_CONFIG_PATH)?)
+				.into_iter()
+				.filter(|hold| !package_names.contains(&hold.as_str()))
+				.collect();
+			write_pacman_holds(&holds)
+		}
+	}
+}
+
+pub fn remove(package_names: &[&str]) -> Result<(), InstallError> {
+	let manager = get_pkg_manager()?;
+	let _lock = lock();
+	match manager {
+		PkgManager::Apt => Cmd::new("apt-get")
+			.args(["purge", "-y"])
+			.args(package_names)
+			.env("DEBIAN_FRONTEND", "noninteractive")
+			.run(),
+		PkgManager::Dnf => Cmd::new("dnf")
+			.args(["remove", "-y"])
+			.args(package_names)
+			.run(),
+		PkgManager::Pacman => {
+			// pacman fails on packages that are not installed, apt and dnf skip them.
+			let mut installed_names = Vec::new();
+			for package_name in package_names {
+				if installed(manager, package_name)? {
+					installed_names.push(*package_name);
+				}
+			}
+			if installed_names.is_empty() {
+				return Ok(());
+			}
+			Cmd::new("pacman")
+				.args(["-Rns", "--noconfirm"])
+				.args(installed_names)
+				.run()
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const PACMAN_CONFIG: &str = "\
+# Local tweaks.
+[options]
+HoldPkg = pacman glibc
+ParallelDownloads = 5
+
+[core]
+Include = /etc/pacman.d/mirrorlist
+";
+
+	fn holds(names: &[&str]) -> Vec<String> {
+		names.iter().map(|name| (*name).to_owned()).collect()
+	}
+
+	#[test]
+	fn pacman_holds_go_below_the_options_section() {
+		let held = with_pacman_holds(PACMAN_CONFIG, &holds(&["kubelet", "kubeadm"])).unwrap();
+		assert_eq!(
+			held,
+			PACMAN_CONFIG.replace(
+				"[options]\n",
+				"[options]\n# Held by 8inary.\nIgnorePkg = kubelet kubeadm\n"
+			)
+		);
+		assert_eq!(pacman_holds(&held), ["kubelet", "kubeadm"]);
+	}
+
+	#[test]
+	fn pacman_holds_are_rewritten_in_place() {
+		let held = with_pacman_holds(PACMAN_CONFIG, &holds(&["kubelet"])).unwrap();
+		let again = with_pacman_holds(&held, &holds(&["kubelet"])).unwrap();
+		assert_eq!(again, held);
+		let moved = with_pacman_holds(&held, &holds(&["kubelet", "kubectl"])).unwrap();
+		assert_eq!(pacman_holds(&moved), ["kubelet", "kubectl"]);
+		assert_eq!(moved.matches(PACMAN_HOLD_MARKER).count(), 1);
+		assert_eq!(with_pacman_holds(&moved, &[]).unwrap(), PACMAN_CONFIG);
+	}
+
+	#[test]
+	fn pacman_holds_need_an_options_section() {
+		let result = with_pacman_holds("[core]\n", &holds(&["kubelet"]));
+		assert!(matches!(result, Err(InstallError::Config(_))));
+		assert_eq!(with_pacman_holds("[core]\n", &[]).unwrap(), "[core]\n");
+	}
+
+	#[test]
+	fn detect_reads_id_then_id_like() {
+		let detected = |os_release: &str| detect(os_release).ok();
+		assert_eq!(
+			detected("ID=ubuntu\nVERSION_ID=\"24.04\"\n"),
+			Some(PkgManager::Apt)
+		);
+		assert_eq!(detected("ID=\"arch\"\n"), Some(PkgManager::Pacman));
+		assert_eq!(
+			detected("ID=\"rocky\"\nID_LIKE=\"rhel centos fedora\"\n"),
+			Some(PkgManager::Dnf)
+		);
+		assert_eq!(
+			detected("ID=linuxmint\nID_LIKE='ubuntu debian'\n"),
+			Some(PkgManager::Apt)
+		);
+		assert_eq!(detected("ID=alpine\n"), None);
+		// The ID lookup skips over the ID_LIKE line.
+		assert_eq!(
+			detected("ID_LIKE=arch\nID=gentoo\n"),
+			Some(PkgManager::Pacman)
+		);
+	}
 }
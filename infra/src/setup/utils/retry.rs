@@ -0,0 +1,181 @@
+use crate::error::InstallError;
+use std::{
+	thread::sleep,
+	time::{Duration, Instant},
+};
+use tracing::warn;
+
+// A declarative retry policy, e.g. `Retry::attempts(5).deadline(Duration::from_secs(120))`.
+#[derive(Debug, Clone, Copy)]
+pub struct Retry {
+	attempts: u32,
+	deadline: Option<Duration>,
+	delay: Duration,
+	max_delay: Duration,
+}
+
+impl Retry {
+	pub const fn attempts(attempts: u32) -> Retry {
+		Retry {
+			attempts,
+			deadline: None,
+			delay: Duration::from_secs(1),
+			max_delay: Duration::from_secs(30),
+		}
+	}
+
+	pub const fn within(deadline: Duration) -> Retry {
+		Retry::attempts(u32::MAX).deadline(deadline)
+	}
+
+	pub const fn deadline(mut self, deadline: Duration) -> Retry {
+		self.deadline = Some(deadline);
+		self
+	}
+
+	// The delay doubles after every failed attempt, up to max_delay.
+	pub const fn backoff(mut self, delay: Duration, max_delay: Duration) -> Retry {
+		self.delay = delay;
+		self.max_delay = max_delay;
+		self
+	}
+
+	pub fn run<T>(
+		&self,
+		what: &str,
+		mut op: impl FnMut() -> Result<T, InstallError>,
+	) -> Result<T, InstallError> {
+		let started = Instant::now();
+		let mut delay = self.delay;
+		let mut attempt = 0;
+		loop {
+			attempt += 1;
+			let err = match op() {
+				Ok(value) => return Ok(value),
+				Err(err) => err,
+			};
+			let elapsed = started.elapsed();
+			let remaining = self
+				.deadline
+				.map_or(Duration::MAX, |deadline| deadline.saturating_sub(elapsed));
+			if attempt >= self.attempts || remaining.is_zero() {
+				return Err(InstallError::RetryExhausted {
+					what: what.to_owned(),
+					attempts: attempt,
+					elapsed,
+					last: Box::new(err),
+				});
+			}
+			warn!("Retrying {} after attempt {}: {}", what, attempt, err);
+			sleep(delay.min(remaining));
+			delay = delay.saturating_mul(2).min(self.max_delay);
+		}
+	}
+
+	pub fn until(
+		&self,
+		what: &str,
+		mut is_ready: impl FnMut() -> Result<bool, InstallError>,
+	) -> Result<(), InstallError> {
+		self.run(what, || {
+			if is_ready()? {
+				Ok(())
+			} else {
+				Err(InstallError::NotReady(what.to_owned()))
+			}
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const FAST: Retry =
+		Retry::attempts(3).backoff(Duration::from_millis(1), Duration::from_millis(1));
+
+	fn failing() -> Result<(), InstallError> {
+		Err(InstallError::Config("down".to_owned()))
+	}
+
+	#[test]
+	fn run_gives_up_after_the_attempts() {
+		let mut calls = 0;
+		let result = FAST.run("probe", || {
+			calls += 1;
+			failing()
+		});
+		let Err(InstallError::RetryExhausted {
+			what,
+			attempts,
+			last,
+			..
+		}) = result
+		else {
+			panic!("expected RetryExhausted, got {result:?}");
+		};
+		assert_eq!((what.as_str(), attempts, calls), ("probe", 3, 3));
+		assert!(matches!(*last, InstallError::Config(message) if message == "down"));
+	}
+
+	#[test]
+	fn run_returns_the_first_success() {
+		let mut calls = 0;
+		let result = FAST.run("probe", || {
+			calls += 1;
+			if calls == 2 {
+				Ok(calls)
+			} else {
+				failing().map(|_| 0)
+			}
+		});
+		assert_eq!(result.unwrap(), 2);
+	}
+
+	#[test]
+	fn deadline_stops_before_the_attempts_run_out() {
+		let started = Instant::now();
+		let result = Retry::attempts(1000)
+			.deadline(Duration::from_millis(100))
+			.backoff(Duration::from_millis(20), Duration::from_millis(20))
+			.run("probe", failing);
+		let Err(InstallError::RetryExhausted { attempts, .. }) = result else {
+			panic!("expected RetryExhausted, got {result:?}");
+		};
+		assert!(attempts < 1000);
+		assert!(started.elapsed() >= Duration::from_millis(100));
+		assert!(started.elapsed() < Duration::from_secs(2));
+	}
+
+	#[test]
+	fn backoff_doubles_up_to_the_max() {
+		let mut calls = Vec::new();
+		let _ = Retry::attempts(5)
+			.backoff(Duration::from_millis(25), Duration::from_millis(60))
+			.run("probe", || {
+				calls.push(Instant::now());
+				failing()
+			});
+		let gaps = calls
+			.windows(2)
+			.map(|pair| pair[1] - pair[0])
+			.collect::<Vec<_>>();
+		let expected = [25, 50, 60, 60].map(Duration::from_millis);
+		assert_eq!(gaps.len(), expected.len());
+		for (gap, expected) in gaps.iter().zip(expected) {
+			assert!(*gap >= expected, "{gap:?} < {expected:?}");
+		}
+		// Uncapped, the last two waits would be 100ms and 200ms.
+		assert!(gaps[2] < Duration::from_millis(100));
+		assert!(gaps[3] < Duration::from_millis(100));
+	}
+
+	#[test]
+	fn until_reports_not_ready() {
+		let result = FAST.until("pods", || Ok(false));
+		let Err(InstallError::RetryExhausted { last, .. }) = result else {
+			panic!("expected RetryExhausted, got {result:?}");
+		};
+		assert!(matches!(*last, InstallError::NotReady(what) if what == "pods"));
+	}
+}
@@ -0,0 +1,38 @@
+// Units written by setup steps and the watch command live next to the admin's own.
+pub const UNIT_DIR: &str = "/etc/systemd/system";
+// Units run the installed copy, not whatever binary happened to apply them.
+pub const BINARY_PATH: &str = "/usr/local/sbin/8inary-infra";
+
+// Quotes one ExecStart= word, systemd expands specifiers (%) and variables ($) even inside quotes.
+pub fn quote(word: &str) -> String {
+	let mut quoted = String::from('"');
+	for c in word.chars() {
+		match c {
+			'"' | '\\' => {
+				quoted.push('\\');
+				quoted.push(c);
+			}
+			'%' => quoted.push_str("%%"),
+			'$' => quoted.push_str("$$"),
+			_ => quoted.push(c),
+		}
+	}
+	quoted.push('"');
+	quoted
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn quote_escapes_what_systemd_expands() {
+		assert_eq!(
+			quote("/etc/8inary/config.toml"),
+			r#""/etc/8inary/config.toml""#
+		);
+		assert_eq!(quote("/srv/my infra/x.toml"), r#""/srv/my infra/x.toml""#);
+		assert_eq!(quote(r#"a"b\c"#), r#""a\"b\\c""#);
+		assert_eq!(quote("100%$HOME"), r#""100%%$$HOME""#);
+	}
+}
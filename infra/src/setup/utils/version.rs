@@ -0,0 +1,147 @@
+use crate::error::InstallError;
+use std::fmt;
+
+// Kubelets may trail the API server by three minor versions, but never lead it.
+// The skew policy is defined on minor versions, patch releases do not matter.
+pub const MAX_KUBELET_SKEW: u32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Version {
+	pub major: u32,
+	pub minor: u32,
+	pub patch: u32,
+}
+
+impl Version {
+	// Accepts "v1.34.2" as well as package versions like "1.34.2-1.1" and "1:1.34.2-150500.1.1".
+	pub fn parse(text: &str) -> Result<Version, InstallError> {
+		let upstream = text.split_once(':').map_or(text, |(_, rest)| rest);
+		let upstream = upstream.strip_prefix('v').unwrap_or(upstream);
+		let upstream = upstream.split(['-', '+', '~']).next().unwrap_or_default();
+		let numbers = upstream
+			.split('.')
+			.map(str::parse::<u32>)
+			.collect::<Result<Vec<_>, _>>()
+			.ok();
+		match numbers.as_deref() {
+			Some(&[major, minor, patch]) => Ok(Version {
+				major,
+				minor,
+				patch,
+			}),
+			_ => Err(InstallError::Config(format!(
+				"'{text}' is not an X.Y.Z version"
+			))),
+		}
+	}
+
+	pub fn check_kubelet_skew(kubelet: Version, apiserver: Version) -> Result<(), InstallError> {
+		let is_valid = kubelet.major == apiserver.major
+			&& kubelet.minor <= apiserver.minor
+			&& apiserver.minor - kubelet.minor <= MAX_KUBELET_SKEW;
+		if is_valid {
+			Ok(())
+		} else {
+			Err(InstallError::VersionSkew(format!(
+				"kubelet {kubelet} against API server {apiserver}, kubelets may trail by at most {MAX_KUBELET_SKEW} minor versions and never lead"
+			)))
+		}
+	}
+
+	// kubeadm moves the control plane one minor version per upgrade and never down.
+	pub fn check_upgrade(current: Version, target: Version) -> Result<(), InstallError> {
+		let is_valid =
+			target.major == current.major && target >= current && target.minor <= current.minor + 1;
+		if is_valid {
+			Ok(())
+		} else {
+			Err(InstallError::VersionSkew(format!(
+				"cannot upgrade the cluster from {current} to {target}, kubeadm moves one minor version at a time"
+			)))
+		}
+	}
+}
+
+impl fmt::Display for Version {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "v{}.{}.{}", self.major, self.minor, self.patch)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn version(text: &str) -> Version {
+		Version::parse(text).unwrap()
+	}
+
+	#[test]
+	fn parses_tags_and_package_versions() {
+		let expected = Version {
+			major: 1,
+			minor: 34,
+			patch: 2,
+		};
+		for text in [
+			"v1.34.2",
+			"1.34.2",
+			"1.34.2-1.1",
+			"1:1.34.2-150500.1.1",
+			"v1.34.2+k3s1",
+			"1.34.2~rc1",
+		] {
+			assert_eq!(version(text), expected, "{text}");
+		}
+		for text in ["1.34", "v1.34.x", "1.34.2.1", "", "latest"] {
+			assert!(Version::parse(text).is_err(), "{text}");
+		}
+	}
+
+	#[test]
+	fn orders_numerically() {
+		assert!(version("v1.9.0") < version("v1.10.0"));
+		assert!(version("v1.34.10") > version("v1.34.9"));
+		assert_eq!(version("1.34.2-1.1").to_string(), "v1.34.2");
+	}
+
+	#[test]
+	fn upgrades_move_one_minor_at_a_time() {
+		let current = version("v1.33.4");
+		for target in ["v1.33.4", "v1.33.5", "v1.34.0", "v1.34.2"] {
+			assert!(
+				Version::check_upgrade(current, version(target)).is_ok(),
+				"{target}"
+			);
+		}
+		for target in ["v1.35.0", "v1.33.3", "v1.32.9", "v2.33.4"] {
+			assert!(
+				matches!(
+					Version::check_upgrade(current, version(target)),
+					Err(InstallError::VersionSkew(_))
+				),
+				"{target}"
+			);
+		}
+	}
+
+	#[test]
+	fn kubelet_may_trail_but_not_lead() {
+		let apiserver = version("v1.34.2");
+		for kubelet in ["v1.34.0", "v1.34.9", "v1.33.5", "v1.32.0", "v1.31.7"] {
+			assert!(
+				Version::check_kubelet_skew(version(kubelet), apiserver).is_ok(),
+				"{kubelet}"
+			);
+		}
+		for kubelet in ["v1.35.0", "v1.36.0", "v1.30.9", "v2.34.2"] {
+			assert!(
+				matches!(
+					Version::check_kubelet_skew(version(kubelet), apiserver),
+					Err(InstallError::VersionSkew(_))
+				),
+				"{kubelet}"
+			);
+		}
+	}
+}
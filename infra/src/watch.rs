@@ -0,0 +1,308 @@
+use crate::error::InstallError;
+use crate::metrics;
+use crate::setup::{self, BINARY_PATH, Check, Cmd, Report, StepFilter, UNIT_DIR, systemd_quote};
+use std::{
+	env, fs,
+	net::SocketAddr,
+	path::{Path, PathBuf},
+	thread::sleep,
+	time::Duration,
+};
+use tracing::{error, info, warn};
+
+pub const SERVICE_NAME: &str = "8inary-watch.service";
+pub const TIMER_NAME: &str = "8inary-watch.timer";
+// Their set resets the node, an unattended pass must never take that path.
+pub const UNREMEDIABLE: &[&str] = &["ControlPlane", "Worker"];
+
+#[derive(Debug)]
+pub struct WatchOptions {
+	pub interval: Duration,
+	pub remediate: Vec<String>,
+	pub once: bool,
+	pub metrics: Option<SocketAddr>,
+	pub filter: StepFilter,
+	pub config: Option<PathBuf>,
+	pub inventory: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pass {
+	Clean,
+	Drift,
+	Failed,
+}
+
+pub fn run(options: &WatchOptions) -> Pass {
+	info!(
+		"Watching for drift every {}s, remediating: {}.",
+		options.interval.as_secs(),
+		if options.remediate.is_empty() {
+			"nothing".to_owned()
+		} else {
+			options.remediate.join(", ")
+		}
+	);
+	if let Some(addr) = options.metrics
+		&& let Err(err) = metrics::serve(addr)
+	{
+		error!("Metrics endpoint failed: {}", err);
+		return Pass::Failed;
+	}
+	loop {
+		let outcome = pass(options);
+		if options.once {
+			return outcome;
+		}
+		sleep(options.interval);
+	}
+}
+
+fn pass(options: &WatchOptions) -> Pass {
+	let mut report = Report::new("watch");
+	let plan = match setup::plan(&options.filter, &mut report) {
+		Ok(plan) => plan,
+		Err(err) => {
+			error!("Watch pass failed: {}", err);
+			return Pass::Failed;
+		}
+	};
+	let mut drifted = Vec::new();
+	for entry in &plan.entries {
+		match &entry.outcome {
+			Ok(Check::Satisfied) => {}
+			Ok(Check::Drift(reason)) => {
+				warn!("Drift detected: {}, {}", entry.step, reason);
+				drifted.push(entry.step);
+			}
+			Err(err) => error!("Check failed: {}, {}", entry.step, err),
+		}
+	}
+	let (remediate, unattended): (Vec<&str>, Vec<&str>) = drifted
+		.iter()
+		.partition(|step| options.remediate.iter().any(|name| name == *step));
+	let mut outcome = if plan.has_errors() {
+		Pass::Failed
+	} else if unattended.is_empty() {
+		Pass::Clean
+	} else {
+		Pass::Drift
+	};
+	if !remediate.is_empty() {
+		info!("Remediating drifted steps: {}.", remediate.join(", "));
+		let filter = StepFilter {
+			only: remediate.iter().map(|step| (*step).to_owned()).collect(),
+			..StepFilter::default()
+		};
+		match setup::setup(&filter, &mut report) {
+			Ok(()) => info!("Remediation finished."),
+			Err(err) => {
+				error!("Remediation failed: {}", err);
+				outcome = Pass::Failed;
+			}
+		}
+	}
+	report.finish(match outcome {
+		Pass::Clean => 0,
+		Pass::Drift => 2,
+		Pass::Failed => 1,
+	});
+	metrics::observe(&report);
+	if drifted.is_empty() && outcome == Pass::Clean {
+		info!("No drift detected.");
+		return outcome;
+	}
+	if setup::scripted_commands().is_none()
+		&& let Err(err) = report.write(Path::new(setup::RUNS_DIR))
+	{
+		warn!("Run report not written: {}", err);
+	}
+	outcome
+}
+
+pub fn validate_remediate(remediate: &[String]) -> Result<(), InstallError> {
+	match remediate
+		.iter()
+		.find(|step| UNREMEDIABLE.contains(&step.as_str()))
+	{
+		Some(step) => Err(InstallError::Config(format!(
+			"{step} resets the node and cannot be remediated by watch, apply it by hand"
+		))),
+		None => Ok(()),
+	}
+}
+
+fn absolute(path: &Path) -> Result<String, InstallError> {
+	Ok(systemd_quote(&fs::canonicalize(path)?.to_string_lossy()))
+}
+
+fn exec_start(options: &WatchOptions) -> Result<String, InstallError> {
+	let mut args = vec![BINARY_PATH.to_owned()];
+	if let Some(config) = &options.config {
+		args.push(format!("--config {}", absolute(config)?));
+	}
+	if let Some(inventory) = &options.inventory {
+		args.push(format!("--inventory {}", absolute(inventory)?));
+	}
+	match options.metrics {
+		Some(addr) => args.push(format!(
+			"watch --interval {} --metrics {addr}",
+			options.interval.as_secs()
+		)),
+		None => args.push("watch --once".to_owned()),
+	}
+	if !options.remediate.is_empty() {
+		args.push(format!("--remediate {}", options.remediate.join(",")));
+	}
+	let filter = &options.filter;
+	if !filter.only.is_empty() {
+		args.push(format!("--only {}", filter.only.join(",")));
+	}
+	if !filter.skip.is_empty() {
+		args.push(format!("--skip {}", filter.skip.join(",")));
+	}
+	if let Some(from) = &filter.from {
+		args.push(format!("--from {from}"));
+	}
+	Ok(args.join(" "))
+}
+
+// With metrics the watch runs as a daemon, otherwise the timer starts single passes.
+pub fn service_unit(options: &WatchOptions) -> Result<String, InstallError> {
+	if options.metrics.is_some() {
+		return Ok(format!(
+			r#"[Unit]
+Description=8inary drift watch and metrics
+Wants=network-online.target
+After=network-online.target
+
+[Service]
+Type=simple
+ExecStart={}
+Restart=on-failure
+RestartSec=30
+
+[Install]
+WantedBy=multi-user.target
+"#,
+			exec_start(options)?
+		));
+	}
+	Ok(format!(
+		r#"[Unit]
+Description=8inary drift check
+Wants=network-online.target
+After=network-online.target
+
+[Service]
+Type=oneshot
+ExecStart={}
+SuccessExitStatus=2
+"#,
+		exec_start(options)?
+	))
+}
+
+pub fn timer_unit(options: &WatchOptions) -> String {
+	format!(
+		r#"[Unit]
+Description=Run the 8inary drift check every {interval}s
+
+[Timer]
+OnBootSec=2min
+OnUnitActiveSec={interval}s
+Unit={SERVICE_NAME}
+
+[Install]
+WantedBy=timers.target
+"#,
+		interval = options.interval.as_secs()
+	)
+}
+
+pub fn install_units(options: &WatchOptions) -> Result<(), InstallError> {
+	let service = service_unit(options)?;
+	info!(
+		"Installing {} as {}.",
+		env::current_exe()?.display(),
+		BINARY_PATH
+	);
+	Cmd::new("install")
+		.args(["-m", "0755"])
+		.arg(env::current_exe()?)
+		.arg(BINARY_PATH)
+		.run()?;
+	let unit_dir = Path::new(UNIT_DIR);
+	fs::write(unit_dir.join(SERVICE_NAME), service)?;
+	let timer_path = unit_dir.join(TIMER_NAME);
+	if options.metrics.is_none() {
+		fs::write(&timer_path, timer_unit(options))?;
+		Cmd::new("systemctl").arg("daemon-reload").run()?;
+		Cmd::new("systemctl")
+			.args(["enable", "--now", TIMER_NAME])
+			.run()?;
+		info!("Drift check timer {} is enabled.", TIMER_NAME);
+		return Ok(());
+	}
+	if timer_path.exists() {
+		info!("Replacing the drift check timer with the watch daemon.");
+		Cmd::new("systemctl")
+			.args(["disable", "--now", TIMER_NAME])
+			.run()?;
+		fs::remove_file(&timer_path)?;
+	}
+	Cmd::new("systemctl").arg("daemon-reload").run()?;
+	Cmd::new("systemctl").args(["enable", SERVICE_NAME]).run()?;
+	Cmd::new("systemctl")
+		.args(["restart", SERVICE_NAME])
+		.run()?;
+	info!("Drift watch daemon {} is enabled.", SERVICE_NAME);
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::process;
+
+	fn steps(names: &[&str]) -> Vec<String> {
+		names.iter().map(|name| (*name).to_owned()).collect()
+	}
+
+	#[test]
+	fn remediation_refuses_steps_that_reset_the_node() {
+		assert!(validate_remediate(&steps(&["Sysctl", "Firewall"])).is_ok());
+		for step in UNREMEDIABLE {
+			let result = validate_remediate(&steps(&["Sysctl", step]));
+			assert!(
+				matches!(result, Err(InstallError::Config(message)) if message.starts_with(step))
+			);
+		}
+	}
+
+	#[test]
+	fn exec_start_quotes_paths() {
+		let dir = env::temp_dir().join(format!("watch unit {}", process::id()));
+		fs::create_dir_all(&dir).unwrap();
+		let config = fs::canonicalize(&dir).unwrap().join("config.toml");
+		fs::write(&config, "").unwrap();
+		let options = WatchOptions {
+			interval: Duration::from_secs(300),
+			remediate: steps(&["Sysctl"]),
+			once: false,
+			metrics: None,
+			filter: StepFilter::default(),
+			config: Some(config.clone()),
+			inventory: None,
+		};
+		let exec_start = exec_start(&options).unwrap();
+		fs::remove_dir_all(&dir).unwrap();
+		assert_eq!(
+			exec_start,
+			format!(
+				"{BINARY_PATH} --config \"{}\" watch --once --remediate Sysctl",
+				config.display()
+			)
+		);
+	}
+}